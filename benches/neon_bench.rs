@@ -0,0 +1,113 @@
+#![cfg(target_arch = "aarch64")]
+#![feature(test)]
+
+//! Each `neon::recon_*` against the `fallbacks::recon_*` of the same filter, at
+//! every bytes per pixel, on one 4200 byte row (a multiple of every ByPP).
+//!
+//! `unfilter_lines` skips some of the `neon` functions at some widths (see
+//! `ReconFns::select_with` in `lib.rs`, such as paeth and sub at ByPP 3), and
+//! these are the benches to re-check those choices against. Every pass copies
+//! the filtered row back in first, on both sides, so that each pass does the
+//! same work.
+//!
+//! Results: these haven't been run on aarch64 hardware yet. When they are,
+//! put a table here (as in `the_bench.rs`), and the thresholds in `lib.rs`
+//! should be updated to match it. Until then `lib.rs` keeps the original
+//! choices: `neon` for "up" at every ByPP, and for sub, average, and paeth at
+//! ByPP 2 and 4 to 8.
+//!
+//! The ones waiting on these numbers are `recon_average` at ByPP 1 and 3,
+//! and `recon_sub_prefix` at ByPP 1 (benched here instead of
+//! `recon_sub::<1>`).
+
+extern crate test;
+use test::{black_box, Bencher};
+
+use png_filters::{fallbacks, neon};
+
+const ROW_LEN: usize = 4200;
+
+fn rand_row() -> Vec<u8> {
+  let mut bytes = vec![0_u8; ROW_LEN];
+  getrandom::getrandom(&mut bytes).unwrap();
+  bytes
+}
+
+/// Benches `f(row, previous)`, restoring `row` before each pass.
+fn bench_row(b: &mut Bencher, f: impl Fn(&mut [u8], &[u8])) {
+  let filtered = rand_row();
+  let previous = rand_row();
+  let mut row = filtered.clone();
+  b.iter(|| {
+    row.copy_from_slice(&filtered);
+    f(black_box(&mut row), black_box(&previous));
+  })
+}
+
+macro_rules! bench_bpp {
+  ($bpp:literal, $neon_sub:expr, $name:ident) => {
+    mod $name {
+      use super::*;
+
+      #[bench]
+      fn sub_fallback(b: &mut Bencher) {
+        bench_row(b, |row, _| fallbacks::recon_sub::<$bpp>(row))
+      }
+      #[bench]
+      fn sub_neon(b: &mut Bencher) {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+          bench_row(b, |row, _| unsafe { $neon_sub(row) })
+        }
+      }
+      #[bench]
+      fn up_fallback(b: &mut Bencher) {
+        bench_row(b, fallbacks::recon_up)
+      }
+      #[bench]
+      fn up_neon(b: &mut Bencher) {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+          bench_row(b, |row, previous| unsafe { neon::recon_up(row, previous) })
+        }
+      }
+      #[bench]
+      fn average_fallback(b: &mut Bencher) {
+        bench_row(b, fallbacks::recon_average::<$bpp>)
+      }
+      #[bench]
+      fn average_neon(b: &mut Bencher) {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+          bench_row(b, |row, previous| unsafe { neon::recon_average::<$bpp>(row, previous) })
+        }
+      }
+      #[bench]
+      fn average_top_fallback(b: &mut Bencher) {
+        bench_row(b, |row, _| fallbacks::recon_average_top::<$bpp>(row))
+      }
+      #[bench]
+      fn average_top_neon(b: &mut Bencher) {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+          bench_row(b, |row, _| unsafe { neon::recon_average_top::<$bpp>(row) })
+        }
+      }
+      #[bench]
+      fn paeth_fallback(b: &mut Bencher) {
+        bench_row(b, fallbacks::recon_paeth::<$bpp>)
+      }
+      #[bench]
+      fn paeth_neon(b: &mut Bencher) {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+          bench_row(b, |row, previous| unsafe { neon::recon_paeth::<$bpp>(row, previous) })
+        }
+      }
+    }
+  };
+}
+
+bench_bpp!(1, neon::recon_sub_prefix, bpp_1);
+bench_bpp!(2, neon::recon_sub::<2>, bpp_2);
+bench_bpp!(3, neon::recon_sub::<3>, bpp_3);
+bench_bpp!(4, neon::recon_sub::<4>, bpp_4);
+bench_bpp!(5, neon::recon_sub::<5>, bpp_5);
+bench_bpp!(6, neon::recon_sub::<6>, bpp_6);
+bench_bpp!(7, neon::recon_sub::<7>, bpp_7);
+bench_bpp!(8, neon::recon_sub::<8>, bpp_8);
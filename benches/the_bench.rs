@@ -9,7 +9,8 @@
 extern crate test;
 use test::Bencher;
 
-use png_filters::unfilter_lines;
+use core::slice::ChunksExactMut;
+use png_filters::{fallbacks, unfilter_lines};
 
 fn rand_bytes<const BYTES_PER_PIXEL: usize>(width: usize, height: usize) -> Vec<u8> {
   let mut bytes = vec![0_u8; (1 + width * BYTES_PER_PIXEL) * height];
@@ -17,6 +18,19 @@ fn rand_bytes<const BYTES_PER_PIXEL: usize>(width: usize, height: usize) -> Vec<
   bytes
 }
 
+#[bench]
+fn bench_all_none(b: &mut Bencher) {
+  let mut bytes = rand_bytes::<4>(1024, 1024);
+  bytes.chunks_exact_mut(1 + 1024 * 4).for_each(|chunk| {
+    let (f, _line) = chunk.split_first_mut().unwrap();
+    *f = 0;
+  });
+  //
+  b.iter(|| unfilter_lines::<4>(bytes.chunks_exact_mut(1 + 1024 * 4)))
+}
+
+// // //
+
 #[bench]
 fn bench_all_sub_1(b: &mut Bencher) {
   let mut bytes = rand_bytes::<1>(1024, 1024);
@@ -221,13 +235,535 @@ fn bench_all_paeth_6(b: &mut Bencher) {
   b.iter(|| unfilter_lines::<6>(bytes.chunks_exact_mut(1 + 1024 * 6)))
 }
 
+// Unlike the other `bench_all_*` benches this one copies the filtered image
+// back in before each pass, so every pass does the full "paeth" work (and not
+// just the first one).
+//
+// Results (x86_64), with `sse4_1::recon_paeth_8` vs the pipelined loop:
+// 4.19ms vs 5.79ms per pass (both including the 8MB copy).
 #[bench]
 fn bench_all_paeth_8(b: &mut Bencher) {
-  let mut bytes = rand_bytes::<8>(1024, 1024);
-  bytes.chunks_exact_mut(1 + 1024).for_each(|chunk| {
+  let mut original = rand_bytes::<8>(1024, 1024);
+  original.chunks_exact_mut(1 + 1024 * 8).for_each(|chunk| {
     let (f, _line) = chunk.split_first_mut().unwrap();
     *f = 4;
   });
+  let mut bytes = original.clone();
   //
-  b.iter(|| unfilter_lines::<8>(bytes.chunks_exact_mut(1 + 1024 * 8)))
+  b.iter(|| {
+    bytes.copy_from_slice(&original);
+    unfilter_lines::<8>(bytes.chunks_exact_mut(1 + 1024 * 8))
+  })
+}
+
+// // //
+
+// Dispatch overhead: on small images the per-call feature detection and
+// function selection of `unfilter_lines` is a larger fraction of the total
+// work. These compare it against `unfilter_lines_static`, which has the
+// backend (the fallbacks) picked at compile time. Each iteration copies in a
+// fresh filtered image (since unfiltering zeroes the filter bytes), so both
+// sides pay for that copy equally.
+//
+// At `BYTES_PER_PIXEL == 1` the dynamic version doesn't pick only fallbacks on
+// x86 (it has `sse2::recon_up` or `avx512::recon_up`, a prefix sum "sub" from
+// `avx2` or `sse2`, and paeth is `fallbacks::recon_paeth_table`), so these
+// aren't the cost of dispatch alone:
+//
+// * With a SIMD function picked, each line is an indirect call through a
+//   function pointer instead of an inlined fallback. On these 16 and 64 byte
+//   lines "up" is memory bound either way, so that's most of the difference,
+//   around 3ns per line.
+// * "paeth" is faster dynamically since the table version beats the plain
+//   `fallbacks::recon_paeth` that the static version calls.
+//
+// Results (x86_64, default target features):
+//
+// | bench                           | ns/iter |
+// |:--------------------------------|--------:|
+// | bench_dispatch_dynamic_up_16    |     111 |
+// | bench_dispatch_static_up_16     |      74 |
+// | bench_dispatch_dynamic_up_64    |     432 |
+// | bench_dispatch_static_up_64     |     248 |
+// | bench_dispatch_dynamic_paeth_16 |     592 |
+// | bench_dispatch_static_paeth_16  |    1012 |
+// | bench_dispatch_dynamic_paeth_64 |    8973 |
+// | bench_dispatch_static_paeth_64  |   18868 |
+//
+// Before the SIMD "up" and the table paeth were picked at this width (when both
+// sides were all fallbacks) the dynamic version was 10-20ns slower per image,
+// which is the feature detection and function selection alone. That's
+// noticeable at 16x16 with a cheap filter, and it's lost in the noise for
+// anything bigger. Caching the selected functions could save at most that much
+// per image.
+
+/// As `unfilter_lines`, but always using the fallbacks, with no runtime
+/// dispatch at all.
+fn unfilter_lines_static<const BYTES_PER_PIXEL: usize>(lines: ChunksExactMut<'_, u8>) {
+  let mut lines = lines.map(|line| line.split_first_mut().unwrap());
+  let mut previous: &[u8] = if let Some((filter, line)) = lines.next() {
+    match filter {
+      1 => fallbacks::recon_sub::<BYTES_PER_PIXEL>(line),
+      3 => fallbacks::recon_average_top::<BYTES_PER_PIXEL>(line),
+      4 => fallbacks::recon_sub::<BYTES_PER_PIXEL>(line),
+      _ => (),
+    }
+    *filter = 0;
+    line
+  } else {
+    return;
+  };
+  lines.for_each(|(filter, line)| {
+    match filter {
+      1 => fallbacks::recon_sub::<BYTES_PER_PIXEL>(line),
+      2 => fallbacks::recon_up(line, previous),
+      3 => fallbacks::recon_average::<BYTES_PER_PIXEL>(line, previous),
+      4 => fallbacks::recon_paeth::<BYTES_PER_PIXEL>(line, previous),
+      _ => (),
+    }
+    *filter = 0;
+    previous = line;
+  });
+}
+
+fn rand_filtered<const BYTES_PER_PIXEL: usize>(size: usize, filter: u8) -> Vec<u8> {
+  let mut bytes = rand_bytes::<BYTES_PER_PIXEL>(size, size);
+  bytes.chunks_exact_mut(1 + size * BYTES_PER_PIXEL).for_each(|chunk| {
+    let (f, _line) = chunk.split_first_mut().unwrap();
+    *f = filter;
+  });
+  bytes
+}
+
+#[bench]
+fn bench_dispatch_dynamic_up_16(b: &mut Bencher) {
+  let original = rand_filtered::<1>(16, 2);
+  let mut bytes = original.clone();
+  //
+  b.iter(|| {
+    bytes.copy_from_slice(&original);
+    unfilter_lines::<1>(bytes.chunks_exact_mut(1 + 16 * 1))
+  })
+}
+
+#[bench]
+fn bench_dispatch_static_up_16(b: &mut Bencher) {
+  let original = rand_filtered::<1>(16, 2);
+  let mut bytes = original.clone();
+  //
+  b.iter(|| {
+    bytes.copy_from_slice(&original);
+    unfilter_lines_static::<1>(bytes.chunks_exact_mut(1 + 16 * 1))
+  })
+}
+
+#[bench]
+fn bench_dispatch_dynamic_up_64(b: &mut Bencher) {
+  let original = rand_filtered::<1>(64, 2);
+  let mut bytes = original.clone();
+  //
+  b.iter(|| {
+    bytes.copy_from_slice(&original);
+    unfilter_lines::<1>(bytes.chunks_exact_mut(1 + 64 * 1))
+  })
+}
+
+#[bench]
+fn bench_dispatch_static_up_64(b: &mut Bencher) {
+  let original = rand_filtered::<1>(64, 2);
+  let mut bytes = original.clone();
+  //
+  b.iter(|| {
+    bytes.copy_from_slice(&original);
+    unfilter_lines_static::<1>(bytes.chunks_exact_mut(1 + 64 * 1))
+  })
+}
+
+#[bench]
+fn bench_dispatch_dynamic_paeth_16(b: &mut Bencher) {
+  let original = rand_filtered::<1>(16, 4);
+  let mut bytes = original.clone();
+  //
+  b.iter(|| {
+    bytes.copy_from_slice(&original);
+    unfilter_lines::<1>(bytes.chunks_exact_mut(1 + 16 * 1))
+  })
+}
+
+#[bench]
+fn bench_dispatch_static_paeth_16(b: &mut Bencher) {
+  let original = rand_filtered::<1>(16, 4);
+  let mut bytes = original.clone();
+  //
+  b.iter(|| {
+    bytes.copy_from_slice(&original);
+    unfilter_lines_static::<1>(bytes.chunks_exact_mut(1 + 16 * 1))
+  })
+}
+
+#[bench]
+fn bench_dispatch_dynamic_paeth_64(b: &mut Bencher) {
+  let original = rand_filtered::<1>(64, 4);
+  let mut bytes = original.clone();
+  //
+  b.iter(|| {
+    bytes.copy_from_slice(&original);
+    unfilter_lines::<1>(bytes.chunks_exact_mut(1 + 64 * 1))
+  })
+}
+
+#[bench]
+fn bench_dispatch_static_paeth_64(b: &mut Bencher) {
+  let original = rand_filtered::<1>(64, 4);
+  let mut bytes = original.clone();
+  //
+  b.iter(|| {
+    bytes.copy_from_slice(&original);
+    unfilter_lines_static::<1>(bytes.chunks_exact_mut(1 + 64 * 1))
+  })
+}
+
+// // //
+
+// These call `recon_average_top` directly on one 4096 byte row, to compare the
+// SIMD versions against the fallback at the widths where `unfilter_lines` uses
+// the fallback, and the `avx2` one against `sse4_1` at ByPP 8. The last two
+// rows were run later than the others, on a noisier machine (the fallback at
+// ByPP 8 was 2343 that time).
+//
+// Results (x86_64, default target features):
+//
+// | bench                           | ns/iter |
+// |:--------------------------------|--------:|
+// | bench_average_top_fallback_1    |    2768 |
+// | bench_average_top_sse2_1        |   11475 |
+// | bench_average_top_fallback_3    |    1575 |
+// | bench_average_top_sse2_3        |    3913 |
+// | bench_average_top_fallback_8    |    2109 |
+// | bench_average_top_sse2_8        |    1437 |
+// | bench_average_top_sse4_1_8      |    1584 |
+// | bench_average_top_avx2_8        |     604 |
+
+fn bench_average_top_with<const BYTES_PER_PIXEL: usize>(
+  b: &mut Bencher, recon: unsafe fn(&mut [u8]),
+) {
+  let mut filtered = vec![0_u8; 4096 / BYTES_PER_PIXEL * BYTES_PER_PIXEL];
+  getrandom::getrandom(&mut filtered).unwrap();
+  let mut row = filtered.clone();
+  b.iter(|| {
+    row.copy_from_slice(&filtered);
+    unsafe { recon(test::black_box(&mut row)) }
+  })
+}
+
+#[bench]
+fn bench_average_top_fallback_1(b: &mut Bencher) {
+  bench_average_top_with::<1>(b, fallbacks::recon_average_top::<1>)
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_average_top_sse2_1(b: &mut Bencher) {
+  bench_average_top_with::<1>(b, png_filters::sse2::recon_average_top::<1>)
+}
+
+#[bench]
+fn bench_average_top_fallback_3(b: &mut Bencher) {
+  bench_average_top_with::<3>(b, fallbacks::recon_average_top::<3>)
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_average_top_sse2_3(b: &mut Bencher) {
+  bench_average_top_with::<3>(b, png_filters::sse2::recon_average_top::<3>)
+}
+
+#[bench]
+fn bench_average_top_fallback_8(b: &mut Bencher) {
+  bench_average_top_with::<8>(b, fallbacks::recon_average_top::<8>)
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_average_top_sse2_8(b: &mut Bencher) {
+  bench_average_top_with::<8>(b, png_filters::sse2::recon_average_top::<8>)
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_average_top_sse4_1_8(b: &mut Bencher) {
+  if is_x86_feature_detected!("sse4.1") {
+    bench_average_top_with::<8>(b, png_filters::sse4_1::recon_average_top::<8>)
+  }
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_average_top_avx2_8(b: &mut Bencher) {
+  if is_x86_feature_detected!("avx2") {
+    bench_average_top_with::<8>(b, png_filters::avx2::recon_average_top_8)
+  }
+}
+
+// // //
+
+// These call `recon_up` directly on one row, to compare the SIMD versions
+// against the fallback. "up" doesn't depend on the bytes per pixel at all, and
+// it's one add per byte, so it's limited by memory rather than by the adds.
+// The 4095 byte row has a 15 byte tail after the last full 16 bytes.
+//
+// Results (x86_64, default target features):
+//
+// | bench                   | ns/iter |
+// |:------------------------|--------:|
+// | bench_up_fallback_4096  |     106 |
+// | bench_up_sse2_4096      |      93 |
+// | bench_up_sse4_1_4096    |     100 |
+// | bench_up_fallback_4095  |     112 |
+// | bench_up_sse2_4095      |     100 |
+// | bench_up_sse4_1_4095    |      99 |
+// | bench_up_avx512_4096    |      66 |
+// | bench_up_avx512_4095    |      66 |
+//
+// The `avx512` rows were run later, when the others measured lower (89, 75
+// and 75 at 4096 bytes, and 94, 81 and 81 at 4095). Its tail is a masked load
+// and store, so the 4095 byte row costs nothing extra.
+//
+// `sse4_1::recon_up` is `sse2::recon_up`. When it was a plain byte loop it
+// measured the same as the fallback (98 to 106 ns/iter at 4096 bytes).
+
+fn bench_up_with(b: &mut Bencher, len: usize, recon: unsafe fn(&mut [u8], &[u8])) {
+  let mut filtered = vec![0_u8; len];
+  getrandom::getrandom(&mut filtered).unwrap();
+  let mut previous = vec![0_u8; len];
+  getrandom::getrandom(&mut previous).unwrap();
+  b.iter(|| unsafe { recon(test::black_box(&mut filtered), test::black_box(&previous)) })
+}
+
+#[bench]
+fn bench_up_fallback_4096(b: &mut Bencher) {
+  bench_up_with(b, 4096, fallbacks::recon_up)
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_up_sse2_4096(b: &mut Bencher) {
+  bench_up_with(b, 4096, png_filters::sse2::recon_up)
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_up_sse4_1_4096(b: &mut Bencher) {
+  bench_up_with(b, 4096, png_filters::sse4_1::recon_up)
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_up_avx512_4096(b: &mut Bencher) {
+  if is_x86_feature_detected!("avx512bw") {
+    bench_up_with(b, 4096, png_filters::avx512::recon_up)
+  }
+}
+
+#[bench]
+fn bench_up_fallback_4095(b: &mut Bencher) {
+  bench_up_with(b, 4095, fallbacks::recon_up)
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_up_sse2_4095(b: &mut Bencher) {
+  bench_up_with(b, 4095, png_filters::sse2::recon_up)
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_up_sse4_1_4095(b: &mut Bencher) {
+  bench_up_with(b, 4095, png_filters::sse4_1::recon_up)
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_up_avx512_4095(b: &mut Bencher) {
+  if is_x86_feature_detected!("avx512bw") {
+    bench_up_with(b, 4095, png_filters::avx512::recon_up)
+  }
+}
+
+// // //
+
+// These are images with a huge number of tiny lines, to see the per-line
+// overhead of `unfilter_lines` (splitting off the filter byte, the `match`,
+// and the calls) rather than the cost of reconstructing the bytes. Each image
+// is about 200,000 bytes with random filter types 0 to 4. The image is copied
+// back in before each pass, since unfiltering clears the filter bytes.
+//
+// Results (x86_64, default target features):
+//
+// | bench                 | lines   | ns/iter   |
+// |:----------------------|--------:|----------:|
+// | bench_tiny_rows_2     | 100,000 | 1,726,000 |
+// | bench_tiny_rows_5     |  40,000 |   775,000 |
+// | bench_tiny_rows_17    |  11,764 |   350,000 |
+//
+// The filter byte is split off each line with `split_first_mut().unwrap()`.
+// Replacing that with `unwrap_unchecked` didn't help (1,748,000, 822,000 and
+// 375,000, within the run to run noise), so the unwrap isn't where the
+// per-line time goes.
+
+fn bench_tiny_rows_with(b: &mut Bencher, line_len: usize) {
+  let mut original = vec![0_u8; 200_000 / line_len * line_len];
+  getrandom::getrandom(&mut original).unwrap();
+  original.chunks_exact_mut(line_len).for_each(|line| line[0] %= 5);
+  let mut bytes = original.clone();
+  b.iter(|| {
+    bytes.copy_from_slice(&original);
+    unfilter_lines::<1>(test::black_box(&mut bytes[..]).chunks_exact_mut(line_len))
+  })
+}
+
+#[bench]
+fn bench_tiny_rows_2(b: &mut Bencher) {
+  bench_tiny_rows_with(b, 2)
+}
+
+#[bench]
+fn bench_tiny_rows_5(b: &mut Bencher) {
+  bench_tiny_rows_with(b, 5)
+}
+
+#[bench]
+fn bench_tiny_rows_17(b: &mut Bencher) {
+  bench_tiny_rows_with(b, 17)
+}
+
+// // //
+
+// Paeth at ByPP 3, with the SIMD paeth allowed (`paeth: 3`, the default on
+// x86) and not allowed (`paeth: 4`), to check `DispatchThresholds::DEFAULT`.
+// The image is 1024x64 RGB8 with every line "paeth", copied back in before
+// each pass since unfiltering clears the filter bytes.
+//
+// Results:
+//
+// | target          | bench                  | ns/iter |
+// |:----------------|:-----------------------|--------:|
+// | x86_64 (sse4.1) | bench_paeth_3_simd     | 366,000 |
+// | x86_64 (sse4.1) | bench_paeth_3_fallback | 404,000 |
+//
+// On aarch64 the `neon` paeth is never used at ByPP 3 (see the note in
+// `ReconFns::select_with`), so there these two are the same.
+
+fn bench_paeth_3_with(b: &mut Bencher, paeth: usize) {
+  use png_filters::{options::UnfilterOptions, DispatchThresholds};
+  let line_len = 1 + 1024 * 3;
+  let mut original = rand_bytes::<3>(1024, 64);
+  original.chunks_exact_mut(line_len).for_each(|line| line[0] = 4);
+  let mut bytes = original.clone();
+  let options =
+    UnfilterOptions::new().thresholds(DispatchThresholds { paeth, ..DispatchThresholds::DEFAULT });
+  b.iter(|| {
+    bytes.copy_from_slice(&original);
+    options.run::<3>(test::black_box(&mut bytes[..]).chunks_exact_mut(line_len))
+  })
+}
+
+#[bench]
+fn bench_paeth_3_simd(b: &mut Bencher) {
+  bench_paeth_3_with(b, 3)
+}
+
+#[bench]
+fn bench_paeth_3_fallback(b: &mut Bencher) {
+  bench_paeth_3_with(b, 4)
+}
+
+// // //
+
+// These call the ByPP 1 `sub` functions directly on one row. The prefix sum
+// versions have one dependent add per block (16 bytes for `sse2`, 32 for
+// `avx2`) instead of one per byte. The 4127 byte row has a 31 byte tail.
+// `sse2::recon_sub_prefix_runs` (what `unfilter_lines` picks without `avx2`)
+// skips blocks of zeroes, so it's also run on a row of all zeroes.
+//
+// Results (x86_64):
+//
+// | bench                                    | ns/iter |
+// |:-----------------------------------------|--------:|
+// | bench_sub_1_fallback_4096                |   1,375 |
+// | bench_sub_1_sse2_prefix_4096             |     433 |
+// | bench_sub_1_sse2_prefix_runs_4096        |     434 |
+// | bench_sub_1_avx2_prefix_4096             |     260 |
+// | bench_sub_1_zeroes_sse2_prefix_runs_4096 |     181 |
+// | bench_sub_1_zeroes_avx2_prefix_4096      |     261 |
+// | bench_sub_1_fallback_4127                |   1,384 |
+// | bench_sub_1_sse2_prefix_4127             |     437 |
+// | bench_sub_1_avx2_prefix_4127             |     272 |
+
+fn bench_sub_1_with(b: &mut Bencher, len: usize, recon: unsafe fn(&mut [u8])) {
+  let mut filtered = vec![0_u8; len];
+  getrandom::getrandom(&mut filtered).unwrap();
+  b.iter(|| unsafe { recon(test::black_box(&mut filtered)) })
+}
+
+#[bench]
+fn bench_sub_1_fallback_4096(b: &mut Bencher) {
+  bench_sub_1_with(b, 4096, fallbacks::recon_sub::<1>)
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_sub_1_sse2_prefix_4096(b: &mut Bencher) {
+  bench_sub_1_with(b, 4096, png_filters::sse2::recon_sub_prefix::<1>)
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_sub_1_sse2_prefix_runs_4096(b: &mut Bencher) {
+  bench_sub_1_with(b, 4096, png_filters::sse2::recon_sub_prefix_runs::<1>)
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_sub_1_zeroes_sse2_prefix_runs_4096(b: &mut Bencher) {
+  let mut filtered = vec![0_u8; 4096];
+  b.iter(|| unsafe {
+    png_filters::sse2::recon_sub_prefix_runs::<1>(test::black_box(&mut filtered))
+  })
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_sub_1_zeroes_avx2_prefix_4096(b: &mut Bencher) {
+  let mut filtered = vec![0_u8; 4096];
+  if is_x86_feature_detected!("avx2") {
+    b.iter(|| unsafe { png_filters::avx2::recon_sub_prefix(test::black_box(&mut filtered)) })
+  }
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_sub_1_avx2_prefix_4096(b: &mut Bencher) {
+  if is_x86_feature_detected!("avx2") {
+    bench_sub_1_with(b, 4096, png_filters::avx2::recon_sub_prefix)
+  }
+}
+
+#[bench]
+fn bench_sub_1_fallback_4127(b: &mut Bencher) {
+  bench_sub_1_with(b, 4127, fallbacks::recon_sub::<1>)
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_sub_1_sse2_prefix_4127(b: &mut Bencher) {
+  bench_sub_1_with(b, 4127, png_filters::sse2::recon_sub_prefix::<1>)
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_sub_1_avx2_prefix_4127(b: &mut Bencher) {
+  if is_x86_feature_detected!("avx2") {
+    bench_sub_1_with(b, 4127, png_filters::avx2::recon_sub_prefix)
+  }
 }
@@ -0,0 +1,79 @@
+//! Using this crate in place of a decoder's own unfiltering step.
+//!
+//! A PNG decoder (such as the `png` crate) inflates the `IDAT` stream into
+//! scanlines that are each a filter type byte followed by the filtered row.
+//! What comes out of the decoder at the end is the unfiltered rows with the
+//! filter bytes removed. This example builds that inflated data with the
+//! [`encode`](png_filters::encode) module (so that it doesn't need the `png`
+//! crate as a dependency) and then shows the three ways to get the decoder's
+//! output from it:
+//!
+//! * The scanlines are kept interleaved: [`unfilter_lines`] in place, then drop
+//!   the filter bytes.
+//! * The filter bytes were already split off: [`unfilter_split`].
+//! * The decoder goes one row at a time, holding the previous row:
+//!   [`UnfilterOptions::previous_row`].
+//!
+//! Each way is checked against the original image.
+//!
+//! [`unfilter_lines`]: png_filters::unfilter_lines
+//! [`unfilter_split`]: png_filters::unfilter_split
+//! [`UnfilterOptions::previous_row`]: png_filters::options::UnfilterOptions::previous_row
+
+use png_filters::{
+  encode::{filter_lines_auto, FilterHeuristic},
+  options::UnfilterOptions,
+};
+
+/// RGB8, like most photos.
+const BYTES_PER_PIXEL: usize = 3;
+const WIDTH: usize = 37;
+const HEIGHT: usize = 21;
+
+fn main() {
+  let row_len = WIDTH * BYTES_PER_PIXEL;
+  let line_len = 1 + row_len;
+  // Some image with gradients, so that the heuristic picks a mix of filters.
+  let image: Vec<u8> = (0..HEIGHT)
+    .flat_map(|y| (0..row_len).map(move |i| (y * 7 + i * 3 + (i * y) % 11) as u8))
+    .collect();
+  // This is the same layout as the inflated `IDAT` data.
+  let scanlines = filter_lines_auto::<BYTES_PER_PIXEL>(FilterHeuristic::MinSumAbs, &image, row_len);
+  assert_eq!(scanlines.len(), line_len * HEIGHT);
+
+  // Interleaved scanlines.
+  let mut buffer = scanlines.clone();
+  let reconstructed =
+    png_filters::unfilter_lines::<BYTES_PER_PIXEL>(buffer.chunks_exact_mut(line_len));
+  assert_eq!(reconstructed, image.len());
+  let output: Vec<u8> =
+    buffer.chunks_exact(line_len).flat_map(|line| line[1..].iter().copied()).collect();
+  assert_eq!(output, image);
+
+  // Filter bytes already split off.
+  let mut filters: Vec<u8> = scanlines.chunks_exact(line_len).map(|line| line[0]).collect();
+  let mut output: Vec<u8> =
+    scanlines.chunks_exact(line_len).flat_map(|line| line[1..].iter().copied()).collect();
+  let reconstructed =
+    png_filters::unfilter_split::<BYTES_PER_PIXEL>(&mut filters, output.chunks_exact_mut(row_len));
+  assert_eq!(reconstructed, image.len());
+  assert_eq!(output, image);
+
+  // One row at a time.
+  let mut output: Vec<u8> = Vec::with_capacity(image.len());
+  let mut current = vec![0_u8; line_len];
+  for scanline in scanlines.chunks_exact(line_len) {
+    current.copy_from_slice(scanline);
+    let mut options = UnfilterOptions::new();
+    let previous_start = output.len().saturating_sub(row_len);
+    if !output.is_empty() {
+      options = options.previous_row(&output[previous_start..]);
+    }
+    let outcome = options.run::<BYTES_PER_PIXEL>(current.chunks_exact_mut(line_len));
+    assert_eq!(outcome.result, Ok(()));
+    output.extend_from_slice(&current[1..]);
+  }
+  assert_eq!(output, image);
+
+  println!("all three ways match the original {WIDTH}x{HEIGHT} image");
+}
@@ -0,0 +1,116 @@
+#![cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+
+//! PNG filter functions specialized to the `avx2` cpu extension.
+
+#[cfg(target_arch = "x86")]
+use core::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::*;
+
+use crate::simd_bytes::as_mut_lanes;
+
+/// Like [`recon_sub`](crate::fallbacks::recon_sub) at 1 byte per pixel, but
+/// using a prefix sum to process 32 bytes at a time.
+///
+/// This is [`sse2::recon_sub_prefix`] widened to 256 bits. The byte shifts
+/// only work within each 128-bit half, so the shifts by 1, 2, 4, and 8 bytes
+/// give two separate 16 byte prefix sums. Then the last byte of the low half
+/// is broadcast into the high half (the only cross-half step) and added, and
+/// finally the last byte of the previous block is added to everything. Any
+/// bytes after the last full block are done one at a time.
+///
+/// On a 4096 byte row (x86_64) this is 263ns, vs 439ns for
+/// [`sse2::recon_sub_prefix`] and 1.4us for the fallback (see
+/// `bench_sub_1_*`). [`unfilter_lines`](crate::unfilter_lines) uses it at 1
+/// byte per pixel when `avx2` is available, as
+/// [`Backend::Avx2`](crate::Backend::Avx2).
+///
+/// [`sse2::recon_sub_prefix`]: crate::sse2::recon_sub_prefix
+///
+/// ## Safety
+/// * The `avx2` CPU feature must be available at runtime.
+#[target_feature(enable = "avx2")]
+pub unsafe fn recon_sub_prefix(filtered_row: &mut [u8]) {
+  // `_mm256_shuffle_epi8` with this picks byte 15 of each half for every byte
+  // of that half.
+  let last_of_each_half = _mm256_set1_epi8(15);
+  let mut blocks = filtered_row.chunks_exact_mut(32);
+  // the last reconstructed byte, repeated across the whole register.
+  let mut a: __m256i = _mm256_setzero_si256();
+  (&mut blocks).for_each(|block| {
+    let mut x: __m256i = _mm256_loadu_si256(block.as_ptr().cast());
+    x = _mm256_add_epi8(x, _mm256_slli_si256::<1>(x));
+    x = _mm256_add_epi8(x, _mm256_slli_si256::<2>(x));
+    x = _mm256_add_epi8(x, _mm256_slli_si256::<4>(x));
+    x = _mm256_add_epi8(x, _mm256_slli_si256::<8>(x));
+    // The low half's total, in the high half only (`0x08` zeroes the low half).
+    let totals = _mm256_shuffle_epi8(x, last_of_each_half);
+    x = _mm256_add_epi8(x, _mm256_permute2x128_si256::<0x08>(totals, totals));
+    x = _mm256_add_epi8(x, a);
+    _mm256_storeu_si256(block.as_mut_ptr().cast(), x);
+    let last = _mm256_shuffle_epi8(x, last_of_each_half);
+    a = _mm256_permute2x128_si256::<0x11>(last, last);
+  });
+  let mut a: u8 = as_mut_lanes::<u8, _>(&mut a)[0];
+  blocks.into_remainder().iter_mut().for_each(|x| {
+    *x = x.wrapping_add(a);
+    a = *x;
+  });
+}
+
+/// Like [`recon_average_top`](crate::fallbacks::recon_average_top) at 8 bytes
+/// per pixel (such as RGBA16), but specialized to `avx2`.
+///
+/// On the top line each pixel is `Filt(x) + Recon(a) / 2`, so every pixel
+/// depends on the one before it and only one pixel can be done at a time. The
+/// halving is done on the bytes directly (a 16-bit shift, then masking off the
+/// bit that came down from the next byte), which can't overflow, so nothing is
+/// widened to `i16`. That keeps each pixel to a three instruction chain in the
+/// low 64 bits of an `xmm` register. Widening two pixels into a 256-bit
+/// register would put a cross-half shuffle on that chain (an `avx2` paeth
+/// built that way was slower than the `sse4_1` one). What `avx2` adds is
+/// loading and storing 4 pixels (32 bytes) at a time, with the splitting and
+/// joining of the pixels done off the chain. Any pixels after the last full 4
+/// are done one at a time.
+///
+/// On a 4096 byte row (x86_64) this is 0.60us, vs 1.58us for
+/// [`sse4_1::recon_average_top`] and 2.34us for the fallback (see
+/// `bench_average_top_*`). Most of that is from not widening rather than from
+/// `avx2` itself. [`unfilter_lines`](crate::unfilter_lines) uses it for the top
+/// line at 8 bytes per pixel when `avx2` is available, as
+/// [`Backend::Avx2`](crate::Backend::Avx2).
+///
+/// [`sse4_1::recon_average_top`]: crate::sse4_1::recon_average_top
+///
+/// ## Panics
+/// * `debug_assert_eq!(filtered_row.len() % 8, 0);`
+///
+/// ## Safety
+/// * The `avx2` CPU feature must be available at runtime.
+#[target_feature(enable = "avx2")]
+pub unsafe fn recon_average_top_8(filtered_row: &mut [u8]) {
+  debug_assert_eq!(filtered_row.len() % 8, 0);
+  //
+  let low_7_bits = _mm_set1_epi8(0x7F);
+  // `floor(v / 2)` of each byte in the low 64 bits.
+  let half = |v: __m128i| _mm_and_si128(_mm_srli_epi16::<1>(v), low_7_bits);
+  // the last reconstructed pixel, in the low 64 bits.
+  let mut a: __m128i = _mm_setzero_si128();
+  let mut blocks = filtered_row.chunks_exact_mut(32);
+  (&mut blocks).for_each(|block| {
+    let x: __m256i = _mm256_loadu_si256(block.as_ptr().cast());
+    let low = _mm256_castsi256_si128(x);
+    let high = _mm256_extracti128_si256::<1>(x);
+    let p0 = _mm_add_epi8(low, half(a));
+    let p1 = _mm_add_epi8(_mm_srli_si128::<8>(low), half(p0));
+    let p2 = _mm_add_epi8(high, half(p1));
+    let p3 = _mm_add_epi8(_mm_srli_si128::<8>(high), half(p2));
+    a = p3;
+    let out = _mm256_set_m128i(_mm_unpacklo_epi64(p2, p3), _mm_unpacklo_epi64(p0, p1));
+    _mm256_storeu_si256(block.as_mut_ptr().cast(), out);
+  });
+  blocks.into_remainder().chunks_exact_mut(8).for_each(|pixel| {
+    a = _mm_add_epi8(_mm_loadl_epi64(pixel.as_ptr().cast()), half(a));
+    _mm_storel_epi64(pixel.as_mut_ptr().cast(), a);
+  });
+}
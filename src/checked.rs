@@ -0,0 +1,181 @@
+//! Unfiltering that reports every problem with the input as an error.
+//!
+//! The rest of the crate treats bad arguments (an unsupported bytes per pixel,
+//! lines that aren't a whole number of pixels) as bugs in the caller, and
+//! panics or `debug_assert!`s on them. That's fine when the image size comes
+//! from your own code, but when the size and data both come from an untrusted
+//! file you might want to be sure that no input can make the decoder panic.
+//!
+//! The functions here check every condition that depends on the input up
+//! front, and return an [`UnfilterError`] instead. Once the checks pass, the
+//! normal unfiltering functions are called, and all of their own asserts are
+//! already known to hold.
+
+use crate::{is_supported_bpp, options::UnfilterError};
+
+/// Unfilters `buf`, which is a whole number of lines of `line_len` bytes each.
+///
+/// Each line is a filter type byte followed by the filtered data, the same as
+/// for [`unfilter_lines`](crate::unfilter_lines) with
+/// `buf.chunks_exact_mut(line_len)`.
+///
+/// Returns the number of reconstructed bytes. The errors are:
+///
+/// * [`UnfilterError::UnsupportedBytesPerPixel`]
+/// * [`UnfilterError::BufferLength`]: `buf` isn't a whole number of lines (or
+///   `line_len` is 0 and `buf` isn't empty).
+/// * [`UnfilterError::RowShorterThanPixel`] and
+///   [`UnfilterError::PartialPixel`]: the data of each line isn't a whole
+///   number of pixels.
+/// * [`UnfilterError::InvalidFilterType`]: this stops at the first line with an
+///   invalid filter type, the same as
+///   [`try_unfilter_lines_inline`](crate::try_unfilter_lines_inline). The lines
+///   before it are unfiltered, and that line and the ones after it are left as
+///   they were.
+///
+/// For all the other errors none of the lines are changed.
+///
+/// This never panics.
+#[inline]
+pub fn unfilter_lines(
+  bytes_per_pixel: usize, buf: &mut [u8], line_len: usize,
+) -> Result<usize, UnfilterError> {
+  if check_layout(bytes_per_pixel, buf.len(), line_len)?.is_none() {
+    return Ok(0);
+  }
+  let lines = buf.chunks_exact_mut(line_len);
+  dispatch_bpp!(bytes_per_pixel, |N| crate::try_unfilter_lines_inline::<N>(lines))
+}
+
+/// As [`unfilter_lines`], but the first line of `buf` isn't the top of the
+/// image: `previous_row` is the reconstructed row just above it.
+///
+/// `previous_row` is the row's data, without a filter byte, so it has to be
+/// `line_len - 1` bytes. If it isn't this gives
+/// [`UnfilterError::PreviousRowLength`] (even when `buf` is empty), since the
+/// `recon_*` functions only `debug_assert!` the length of the previous row and
+/// a short one would leave the end of each line wrong. The other errors are the
+/// same as for [`unfilter_lines`].
+///
+/// This never panics.
+#[inline]
+pub fn unfilter_lines_after(
+  bytes_per_pixel: usize, previous_row: &[u8], buf: &mut [u8], line_len: usize,
+) -> Result<usize, UnfilterError> {
+  let Some(row_len) = check_layout(bytes_per_pixel, buf.len(), line_len)? else {
+    return Ok(0);
+  };
+  if previous_row.len() != row_len {
+    return Err(UnfilterError::PreviousRowLength { expected: row_len, actual: previous_row.len() });
+  }
+  //
+  let lines = buf.chunks_exact_mut(line_len);
+  dispatch_bpp!(bytes_per_pixel, |N| crate::try_unfilter_lines_inline_after::<N>(
+    Some(previous_row),
+    lines
+  ))
+}
+
+/// Unfilters a whole image into a newly allocated `Vec`, without the filter
+/// bytes.
+///
+/// `filtered` is `height` lines, each a filter type byte followed by `width`
+/// pixels of filtered data. It isn't changed. The returned `Vec` is the
+/// reconstructed pixels, `width * height * bytes_per_pixel` bytes, the same as
+/// [`unfilter_and_compact`](crate::unfilter_and_compact) would leave in a copy
+/// of `filtered`. The output is written exactly once, so it's never zeroed
+/// first.
+///
+/// The errors are:
+///
+/// * [`UnfilterError::UnsupportedBytesPerPixel`]
+/// * [`UnfilterError::ImageSize`]: `filtered` isn't the length of a `width` by
+///   `height` image.
+/// * [`UnfilterError::InvalidFilterType`]: the first line with an invalid
+///   filter type. All of the filter types are checked before anything is
+///   allocated.
+///
+/// This never panics (other than on allocation failure).
+#[inline]
+pub fn unfilter_to_vec(
+  filtered: &[u8], width: usize, height: usize, bytes_per_pixel: usize,
+) -> Result<Vec<u8>, UnfilterError> {
+  unfilter_to_vec_with_stats(filtered, width, height, bytes_per_pixel).map(|(pixels, _)| pixels)
+}
+
+/// As [`unfilter_to_vec`], but also returns how many lines had each filter
+/// type.
+///
+/// The counts are indexed by the filter type, the same as
+/// [`UnfilterOutcome::filter_counts`](crate::options::UnfilterOutcome::filter_counts).
+/// They're taken during the check of the filter types that
+/// [`unfilter_to_vec`] does anyway, so this costs no extra pass over the data.
+/// A count that doesn't fit in a `u32` stops at `u32::MAX`.
+///
+/// This never panics (other than on allocation failure).
+#[inline]
+pub fn unfilter_to_vec_with_stats(
+  filtered: &[u8], width: usize, height: usize, bytes_per_pixel: usize,
+) -> Result<(Vec<u8>, [u32; 5]), UnfilterError> {
+  if !is_supported_bpp(bytes_per_pixel) {
+    return Err(UnfilterError::UnsupportedBytesPerPixel(bytes_per_pixel));
+  }
+  let size_error = UnfilterError::ImageSize { len: filtered.len(), width, height };
+  let Some(row_len) = width.checked_mul(bytes_per_pixel) else {
+    return Err(size_error);
+  };
+  let Some(line_len) = row_len.checked_add(1) else {
+    return Err(size_error);
+  };
+  if line_len.checked_mul(height) != Some(filtered.len()) {
+    return Err(size_error);
+  }
+  // Won't panic: `line_len` is at least 1.
+  let lines = filtered.chunks_exact(line_len);
+  let mut counts = [0_u32; 5];
+  for (y, line) in lines.clone().enumerate() {
+    match line[0] {
+      filter @ 0..=4 => {
+        let count = &mut counts[usize::from(filter)];
+        *count = count.saturating_add(1);
+      }
+      filter => return Err(UnfilterError::InvalidFilterType { y, filter }),
+    }
+  }
+  //
+  let len = row_len * height;
+  let mut out = Vec::with_capacity(len);
+  dispatch_bpp!(bytes_per_pixel, |N| {
+    crate::unfilter_lines_to_uninit::<N>(lines, &mut out.spare_capacity_mut()[..len]);
+  });
+  // Safety: `unfilter_lines_to_uninit` initialized the first `len` bytes.
+  unsafe { out.set_len(len) };
+  Ok((out, counts))
+}
+
+/// Checks everything about the layout of `buf` that doesn't depend on its
+/// bytes.
+///
+/// Returns the length of each line minus the filter byte, or `None` if
+/// `line_len` is 0 (and `buf` is empty), when there's nothing to do.
+fn check_layout(
+  bytes_per_pixel: usize, len: usize, line_len: usize,
+) -> Result<Option<usize>, UnfilterError> {
+  if !is_supported_bpp(bytes_per_pixel) {
+    return Err(UnfilterError::UnsupportedBytesPerPixel(bytes_per_pixel));
+  }
+  if line_len == 0 {
+    return if len == 0 { Ok(None) } else { Err(UnfilterError::BufferLength { len, line_len }) };
+  }
+  if !len.is_multiple_of(line_len) {
+    return Err(UnfilterError::BufferLength { len, line_len });
+  }
+  let row_len = line_len - 1;
+  if row_len > 0 && row_len < bytes_per_pixel {
+    return Err(UnfilterError::RowShorterThanPixel { row_len, bytes_per_pixel });
+  }
+  if !row_len.is_multiple_of(bytes_per_pixel) {
+    return Err(UnfilterError::PartialPixel { row_len, bytes_per_pixel });
+  }
+  Ok(Some(row_len))
+}
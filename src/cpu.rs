@@ -0,0 +1,60 @@
+//! The CPU features that the crate's dispatch is based on.
+//!
+//! The features are detected once, the first time [`features`] is called, and
+//! then cached for the rest of the program. [`unfilter_lines`] and the other
+//! dispatching functions read the same cached value, so if you pick your own
+//! backends from [`features`] you're using the same facts the crate does.
+//!
+//! [`unfilter_lines`]: crate::unfilter_lines
+
+use std::sync::OnceLock;
+
+/// Which CPU features are available at runtime.
+///
+/// A feature for another architecture is always `false` (eg: `neon` on
+/// `x86_64`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Features {
+  /// The `sse2` feature, for the [`sse2`](crate::sse2) module.
+  pub sse2: bool,
+  /// The `sse4.1` feature, for the [`sse4_1`](crate::sse4_1) module.
+  pub sse4_1: bool,
+  /// The `avx2` feature, for the [`avx2`](crate::avx2) module.
+  pub avx2: bool,
+  /// The `avx512bw` feature, for the [`avx512`](crate::avx512) module.
+  pub avx512bw: bool,
+  /// The `neon` feature, for the `neon` module.
+  pub neon: bool,
+}
+impl Features {
+  /// Runs the feature detection.
+  fn detect() -> Self {
+    #[allow(unused_mut)]
+    let mut features =
+      Self { sse2: false, sse4_1: false, avx2: false, avx512bw: false, neon: false };
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+      features.sse2 = std::arch::is_x86_feature_detected!("sse2");
+      features.sse4_1 = std::arch::is_x86_feature_detected!("sse4.1");
+      features.avx2 = std::arch::is_x86_feature_detected!("avx2");
+      features.avx512bw = std::arch::is_x86_feature_detected!("avx512bw");
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+      features.neon = std::arch::is_aarch64_feature_detected!("neon");
+    }
+    features
+  }
+}
+
+/// The CPU features available at runtime.
+///
+/// The first call does the detection, and every call after that returns the
+/// same cached value.
+#[inline]
+#[must_use]
+pub fn features() -> Features {
+  static FEATURES: OnceLock<Features> = OnceLock::new();
+  *FEATURES.get_or_init(Features::detect)
+}
@@ -0,0 +1,42 @@
+//! Checks the output of the selected reconstruction functions against the
+//! fallbacks.
+//!
+//! This module only exists when the `debug-verify` feature is enabled. With the
+//! feature on, [`unfilter_lines`](crate::unfilter_lines) runs every line
+//! through [`verify_line`], so any SIMD bug turns into a panic instead of
+//! silently corrupted pixels. This is intended for tests and staging builds,
+//! it roughly doubles the cost of unfiltering.
+
+use crate::fallbacks;
+
+/// Runs `recon` on the line, and checks that the result matches the fallback
+/// function for the same filter type.
+///
+/// * `y` is the index of the line within the image, it's only used for the
+///   panic message.
+/// * `previous` is the reconstructed line above `line`, or `None` if `line` is
+///   the top line of the image.
+///
+/// ## Panics
+/// * If `recon` and the fallback give different output. The message has the row
+///   index, the filter type, and the filtered bytes.
+pub fn verify_line<const BYTES_PER_PIXEL: usize>(
+  y: usize, filter: u8, line: &mut [u8], previous: Option<&[u8]>, recon: impl FnOnce(&mut [u8]),
+) {
+  let filtered = line.to_vec();
+  let mut expected = line.to_vec();
+  match (filter, previous) {
+    (1, _) | (4, None) => fallbacks::recon_sub::<BYTES_PER_PIXEL>(&mut expected),
+    (2, Some(previous)) => fallbacks::recon_up(&mut expected, previous),
+    (3, Some(previous)) => fallbacks::recon_average::<BYTES_PER_PIXEL>(&mut expected, previous),
+    (3, None) => fallbacks::recon_average_top::<BYTES_PER_PIXEL>(&mut expected),
+    (4, Some(previous)) => fallbacks::recon_paeth::<BYTES_PER_PIXEL>(&mut expected, previous),
+    _ => (),
+  }
+  recon(line);
+  assert_eq!(
+    expected[..],
+    line[..],
+    "row {y} with filter type {filter} doesn't match the fallback, filtered bytes: {filtered:?}"
+  );
+}
@@ -0,0 +1,145 @@
+//! Records which reconstruction function and backend each row used.
+//!
+//! This module only exists when the `diagnostics` feature is enabled. With the
+//! feature on, [`unfilter_lines_diagnosed`] works like
+//! [`unfilter_lines`](crate::unfilter_lines) but also writes one byte per row
+//! saying what happened to that row, which you can then [`decode`]. This is
+//! for tracking down reports like "some rows look wrong": if the bad rows are
+//! exactly the ones that used one backend, that's where the bug is.
+//!
+//! Each byte has the row's filter type in the low 4 bits (or
+//! [`INVALID_FILTER`]) and the backend in the high 4 bits (0 when no function
+//! ran for the row).
+
+use std::cell::RefCell;
+
+use crate::{Backend, BackendReport, Kernel};
+
+/// The filter type recorded for a row whose filter type isn't 0 to 4.
+pub const INVALID_FILTER: u8 = 0xF;
+
+/// Encodes a row's filter type and the backend that reconstructed it.
+///
+/// `backend` is `None` when no function ran for the row: filter type 0, "up"
+/// on the top line, "sub" (or "paeth" on the top line) on a one pixel wide
+/// image, an invalid filter type, or a zero-width image.
+#[inline]
+#[must_use]
+pub const fn encode(filter: u8, backend: Option<Backend>) -> u8 {
+  let filter = if filter <= 4 { filter } else { INVALID_FILTER };
+  let backend = match backend {
+    None => 0,
+    Some(Backend::Fallback) => 1,
+    Some(Backend::Sse2) => 2,
+    Some(Backend::Sse4_1) => 3,
+    Some(Backend::Neon) => 4,
+    Some(Backend::Portable) => 5,
+    Some(Backend::Avx2) => 6,
+    Some(Backend::Avx512) => 7,
+  };
+  backend << 4 | filter
+}
+
+/// Splits a byte from [`encode`] back into the filter type and backend.
+///
+/// Returns `None` if `code` isn't something [`encode`] can give.
+#[inline]
+#[must_use]
+pub const fn decode(code: u8) -> Option<(u8, Option<Backend>)> {
+  let filter = code & 0xF;
+  if filter > 4 && filter != INVALID_FILTER {
+    return None;
+  }
+  let backend = match code >> 4 {
+    0 => None,
+    1 => Some(Backend::Fallback),
+    2 => Some(Backend::Sse2),
+    3 => Some(Backend::Sse4_1),
+    4 => Some(Backend::Neon),
+    5 => Some(Backend::Portable),
+    6 => Some(Backend::Avx2),
+    7 => Some(Backend::Avx512),
+    _ => return None,
+  };
+  Some((filter, backend))
+}
+
+/// The recording for the current [`unfilter_lines_diagnosed`] call.
+struct Recording {
+  /// The functions that were selected, once they have been.
+  report: Option<BackendReport>,
+  /// The code for each row so far.
+  rows: Vec<u8>,
+}
+
+thread_local! {
+  /// The recording of the current [`unfilter_lines_diagnosed`] call on this
+  /// thread, or `None` when there isn't one.
+  static RECORDING: RefCell<Option<Recording>> = const { RefCell::new(None) };
+}
+
+/// As [`unfilter_lines`](crate::unfilter_lines), but also writes what was done
+/// to each row into `rows`, encoded as with [`encode`].
+///
+/// ## Panics
+/// * As [`unfilter_lines`](crate::unfilter_lines)
+/// * `assert_eq!(rows.len(), lines.len());`
+#[track_caller]
+#[must_use = "the number of reconstructed bytes should be checked"]
+pub fn unfilter_lines_diagnosed<const BYTES_PER_PIXEL: usize>(
+  lines: core::slice::ChunksExactMut<'_, u8>, rows: &mut [u8],
+) -> usize {
+  /// Stops the recording even if the unfiltering panics.
+  struct Stop;
+  impl Drop for Stop {
+    fn drop(&mut self) {
+      RECORDING.with(|recording| recording.borrow_mut().take());
+    }
+  }
+  crate::assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+  assert_eq!(rows.len(), lines.len());
+  //
+  let lines: Vec<&mut [u8]> = lines.collect();
+  let reconstructed = lines.iter().map(|line| line.len() - 1).sum();
+  // Rows that never reach a function keep the code with no backend.
+  let codes = lines.iter().map(|line| encode(line[0], None)).collect();
+  RECORDING.with(|recording| {
+    *recording.borrow_mut() = Some(Recording { report: None, rows: codes });
+  });
+  let stop = Stop;
+  crate::unfilter_lines_with(None, lines.into_iter(), crate::ReconFns::<BYTES_PER_PIXEL>::select);
+  RECORDING.with(|recording| {
+    if let Some(recording) = recording.borrow().as_ref() {
+      rows.copy_from_slice(&recording.rows);
+    }
+  });
+  drop(stop);
+  reconstructed
+}
+
+/// Notes the functions that were selected for the current call.
+#[inline]
+pub(crate) fn selected(report: BackendReport) {
+  RECORDING.with(|recording| {
+    if let Some(recording) = recording.borrow_mut().as_mut() {
+      recording.report = Some(report);
+    }
+  });
+}
+
+/// Notes that line `y` is being reconstructed with `kernel`.
+#[inline]
+pub(crate) fn record(y: usize, filter: u8, kernel: Kernel) {
+  RECORDING.with(|recording| {
+    if let Some(Recording { report: Some(report), rows }) = recording.borrow_mut().as_mut() {
+      let backend = match kernel {
+        Kernel::Sub => report.sub,
+        Kernel::Up => report.up,
+        Kernel::Average => report.average,
+        Kernel::AverageTop => report.average_top,
+        Kernel::Paeth => report.paeth,
+      };
+      rows[y] = encode(filter, Some(backend));
+    }
+  });
+}
@@ -0,0 +1,353 @@
+//! Functions to apply the PNG filters when encoding an image.
+//!
+//! These are the inverse of the reconstruction functions: each takes a row of
+//! original image bytes (and usually the original row above it) and writes the
+//! filtered bytes to `dst`. There's also [`filter_lines_auto`] (and
+//! [`filter_image_best`], which writes into your own buffer), which picks a
+//! filter for each row of an image for you.
+
+use crate::{heuristic::estimate_deflate_cost, FilterType};
+
+/// How [`filter_lines_auto`] picks the filter type for each row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FilterHeuristic {
+  /// Always use the given filter type.
+  Fixed(FilterType),
+  /// Pick the filter type that gives the lowest sum of absolute values, when
+  /// each filtered byte is viewed as an `i8`.
+  ///
+  /// This is the heuristic recommended by the PNG spec, and it's what `libpng`
+  /// uses by default.
+  MinSumAbs,
+  /// Pick the filter type with the lowest [`estimate_deflate_cost`].
+  ///
+  /// This is slower than `MinSumAbs`, but it notices runs of the same byte
+  /// and rows that only use a few byte values, which is what deflate is good
+  /// at.
+  EstimatedDeflate,
+}
+
+/// `Filt(x) = Orig(x) - Orig(a)`
+///
+/// ## Panics
+/// * `assert!(BYTES_PER_PIXEL <= 8);`
+/// * `debug_assert_eq!(row.len() % BYTES_PER_PIXEL, 0);`
+/// * `debug_assert_eq!(dst.len(), row.len());`
+#[track_caller]
+#[inline]
+#[deny(unsafe_code)]
+pub fn filter_sub<const BYTES_PER_PIXEL: usize>(dst: &mut [u8], row: &[u8]) {
+  assert!(BYTES_PER_PIXEL <= 8);
+  debug_assert_eq!(row.len() % BYTES_PER_PIXEL, 0);
+  debug_assert_eq!(dst.len(), row.len());
+  //
+  let (dst_head, dst_tail) = dst.split_at_mut(BYTES_PER_PIXEL.min(dst.len()));
+  dst_head.copy_from_slice(&row[..dst_head.len()]);
+  dst_tail.iter_mut().zip(row.iter().skip(BYTES_PER_PIXEL)).zip(row.iter()).for_each(
+    |((d, x), a)| {
+      *d = x.wrapping_sub(*a);
+    },
+  );
+}
+
+/// `Filt(x) = Orig(x) - Orig(b)`
+///
+/// ## Panics
+/// * `debug_assert_eq!(row.len(), previous_row.len());`
+/// * `debug_assert_eq!(dst.len(), row.len());`
+#[inline]
+#[deny(unsafe_code)]
+pub fn filter_up(dst: &mut [u8], row: &[u8], previous_row: &[u8]) {
+  debug_assert_eq!(row.len(), previous_row.len());
+  debug_assert_eq!(dst.len(), row.len());
+  //
+  dst.iter_mut().zip(row.iter()).zip(previous_row.iter()).for_each(|((d, x), b)| {
+    *d = x.wrapping_sub(*b);
+  });
+}
+
+/// `Filt(x) = Orig(x) - floor((Orig(a) + Orig(b)) / 2)`
+///
+/// ## Panics
+/// * `assert!(BYTES_PER_PIXEL <= 8);`
+/// * `debug_assert_eq!(row.len() % BYTES_PER_PIXEL, 0);`
+/// * `debug_assert_eq!(row.len(), previous_row.len());`
+/// * `debug_assert_eq!(dst.len(), row.len());`
+#[track_caller]
+#[inline]
+#[deny(unsafe_code)]
+pub fn filter_average<const BYTES_PER_PIXEL: usize>(
+  dst: &mut [u8], row: &[u8], previous_row: &[u8],
+) {
+  assert!(BYTES_PER_PIXEL <= 8);
+  debug_assert_eq!(row.len() % BYTES_PER_PIXEL, 0);
+  debug_assert_eq!(row.len(), previous_row.len());
+  debug_assert_eq!(dst.len(), row.len());
+  //
+  dst.iter_mut().zip(row.iter()).zip(previous_row.iter()).enumerate().for_each(
+    |(i, ((d, x), b))| {
+      let a = if i >= BYTES_PER_PIXEL { row[i - BYTES_PER_PIXEL] } else { 0 };
+      let average: u8 = ((a as u16 + *b as u16) / 2) as u8;
+      *d = x.wrapping_sub(average);
+    },
+  );
+}
+
+/// As [`filter_average`], but for the top line of a PNG.
+#[track_caller]
+#[inline]
+#[deny(unsafe_code)]
+pub fn filter_average_top<const BYTES_PER_PIXEL: usize>(dst: &mut [u8], row: &[u8]) {
+  assert!(BYTES_PER_PIXEL <= 8);
+  debug_assert_eq!(row.len() % BYTES_PER_PIXEL, 0);
+  debug_assert_eq!(dst.len(), row.len());
+  //
+  dst.iter_mut().zip(row.iter()).enumerate().for_each(|(i, (d, x))| {
+    let a = if i >= BYTES_PER_PIXEL { row[i - BYTES_PER_PIXEL] } else { 0 };
+    *d = x.wrapping_sub(a / 2);
+  });
+}
+
+/// `Filt(x) = Orig(x) - PaethPredictor(Orig(a), Orig(b), Orig(c))`
+///
+/// ## Panics
+/// * `assert!(BYTES_PER_PIXEL <= 8);`
+/// * `debug_assert_eq!(row.len() % BYTES_PER_PIXEL, 0);`
+/// * `debug_assert_eq!(row.len(), previous_row.len());`
+/// * `debug_assert_eq!(dst.len(), row.len());`
+#[track_caller]
+#[inline]
+#[deny(unsafe_code)]
+pub fn filter_paeth<const BYTES_PER_PIXEL: usize>(dst: &mut [u8], row: &[u8], previous_row: &[u8]) {
+  assert!(BYTES_PER_PIXEL <= 8);
+  debug_assert_eq!(row.len() % BYTES_PER_PIXEL, 0);
+  debug_assert_eq!(row.len(), previous_row.len());
+  debug_assert_eq!(dst.len(), row.len());
+  //
+  dst.iter_mut().zip(row.iter()).zip(previous_row.iter()).enumerate().for_each(
+    |(i, ((d, x), b))| {
+      let (a, c) = if i >= BYTES_PER_PIXEL {
+        (row[i - BYTES_PER_PIXEL], previous_row[i - BYTES_PER_PIXEL])
+      } else {
+        (0, 0)
+      };
+      *d = x.wrapping_sub(paeth_predictor(a, *b, c));
+    },
+  );
+}
+
+/// Filters an image in place, with the filter type already set on each line.
+///
+/// `buf` has the same layout as the input to
+/// [`unfilter_flat`](crate::unfilter_flat): each line is a filter type byte and
+/// then a row of the *original* image. The filter type bytes are read, and
+/// each row is overwritten with its filtered bytes, so afterwards `buf` can be
+/// compressed as-is.
+///
+/// * `row_len` is the length of each line *including* the filter byte.
+///
+/// No memory is allocated. Each row is predicted from the original row above
+/// it, so the lines are done from the bottom of the image to the top (the row
+/// above is only overwritten after every row that needs it), and each row is
+/// done from right to left for the same reason with the pixel to the left.
+///
+/// A filter type above 4 is set to 0, and that row is left as it is.
+///
+/// A `row_len` of 0 can only describe an image with no data at all, so in that
+/// case this does nothing.
+///
+/// ## Panics
+/// * `assert!(BYTES_PER_PIXEL <= 8);`
+/// * `assert_eq!(buf.len() % row_len, 0);` (when `row_len` isn't 0)
+/// * `debug_assert_eq!((row_len - 1) % BYTES_PER_PIXEL, 0);`
+/// * If `row_len` is 0 and `buf` isn't empty.
+#[track_caller]
+pub fn filter_lines_in_place<const BYTES_PER_PIXEL: usize>(buf: &mut [u8], row_len: usize) {
+  assert!(BYTES_PER_PIXEL <= 8);
+  if row_len == 0 {
+    assert!(buf.is_empty(), "row_len is 0 but buf has data");
+    return;
+  }
+  assert_eq!(buf.len() % row_len, 0);
+  debug_assert_eq!((row_len - 1) % BYTES_PER_PIXEL, 0);
+  //
+  for y in (0..buf.len() / row_len).rev() {
+    let (above, rest) = buf.split_at_mut(y * row_len);
+    let previous: Option<&[u8]> = above.rchunks_exact(row_len).next().map(|line| &line[1..]);
+    let (filter, row) = rest[..row_len].split_first_mut().unwrap();
+    match (*filter, previous) {
+      (0, _) | (2, None) => (),
+      (1, _) | (4, None) => filter_row_in_place::<BYTES_PER_PIXEL>(row, None, |a, _, _| a),
+      (2, Some(previous)) => {
+        filter_row_in_place::<BYTES_PER_PIXEL>(row, Some(previous), |_, b, _| b)
+      }
+      (3, previous) => filter_row_in_place::<BYTES_PER_PIXEL>(row, previous, |a, b, _| {
+        ((a as u16 + b as u16) / 2) as u8
+      }),
+      (4, Some(previous)) => {
+        filter_row_in_place::<BYTES_PER_PIXEL>(row, Some(previous), paeth_predictor)
+      }
+      _ => *filter = 0,
+    }
+  }
+}
+
+/// Subtracts `predict(a, b, c)` from each byte of `row`, going from right to
+/// left so that `a` is still the original byte.
+///
+/// With no `previous` row, `b` and `c` are 0.
+#[inline(always)]
+fn filter_row_in_place<const BYTES_PER_PIXEL: usize>(
+  row: &mut [u8], previous: Option<&[u8]>, predict: impl Fn(u8, u8, u8) -> u8,
+) {
+  for i in (0..row.len()).rev() {
+    let above = |i: usize| previous.map_or(0, |previous| previous[i]);
+    let (a, c) = if i >= BYTES_PER_PIXEL {
+      (row[i - BYTES_PER_PIXEL], above(i - BYTES_PER_PIXEL))
+    } else {
+      (0, 0)
+    };
+    row[i] = row[i].wrapping_sub(predict(a, above(i), c));
+  }
+}
+
+#[inline]
+fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+  let p: i16 = a as i16 + b as i16 - c as i16;
+  let pa: i16 = (p - a as i16).abs();
+  let pb: i16 = (p - b as i16).abs();
+  let pc: i16 = (p - c as i16).abs();
+  if pa <= pb && pa <= pc {
+    a
+  } else if pb <= pc {
+    b
+  } else {
+    c
+  }
+}
+
+/// Sum of absolute values, with each byte viewed as an `i8`.
+#[inline]
+fn sum_abs(filtered: &[u8]) -> u64 {
+  filtered.iter().map(|byte| (*byte as i8).unsigned_abs() as u64).sum()
+}
+
+/// [`estimate_deflate_cost`], as a score for
+/// [`FilterHeuristic::EstimatedDeflate`].
+#[inline]
+fn deflate_cost(filtered: &[u8]) -> u64 {
+  estimate_deflate_cost(filtered) as u64
+}
+
+/// Filters every row of an image, picking a filter type for each row.
+///
+/// * `rows` is the original image data, with no filter bytes.
+/// * `row_len` is the number of bytes in each row (so, the width of the image
+///   times `BYTES_PER_PIXEL`).
+///
+/// The output has a filter type byte before each row of filtered data, so it's
+/// exactly the format that [`unfilter_lines`](crate::unfilter_lines) expects
+/// with `chunks_exact_mut(1 + row_len)`.
+///
+/// The candidate filters for each row are computed into four scratch buffers
+/// of `row_len` bytes each, which are reused for every row. On the top row the
+/// "up" filter is the same as filter type 0 and the "paeth" filter is the same
+/// as the "sub" filter, so only filter types 0, 1, and 3 are considered.
+///
+/// ## Panics
+/// * As [`filter_image_best`]
+#[track_caller]
+#[must_use]
+pub fn filter_lines_auto<const BYTES_PER_PIXEL: usize>(
+  strategy: FilterHeuristic, rows: &[u8], row_len: usize,
+) -> Vec<u8> {
+  let mut out = Vec::new();
+  filter_image_best::<BYTES_PER_PIXEL>(rows, row_len, strategy, &mut out);
+  out
+}
+
+/// As [`filter_lines_auto`], but appends the output to `out`.
+///
+/// This lets an encoder reuse one output buffer for many images, or write the
+/// filtered data right after something else it's already put in `out`. The
+/// existing contents of `out` are left alone.
+///
+/// Each row is predicted from the *original* row above it, never from the
+/// filtered bytes that were written for that row. That's what the decoder will
+/// have when it reconstructs the row, since it undoes the filters top to
+/// bottom.
+///
+/// ## Panics
+/// * `assert!(BYTES_PER_PIXEL <= 8);`
+/// * If `row_len` is 0.
+/// * `assert_eq!(row_len % BYTES_PER_PIXEL, 0);`
+/// * `assert_eq!(original.len() % row_len, 0);`
+#[track_caller]
+pub fn filter_image_best<const BYTES_PER_PIXEL: usize>(
+  original: &[u8], row_len: usize, strategy: FilterHeuristic, out: &mut Vec<u8>,
+) {
+  assert!(BYTES_PER_PIXEL <= 8);
+  assert_eq!(row_len % BYTES_PER_PIXEL, 0);
+  assert_eq!(original.len() % row_len, 0);
+  //
+  out.reserve(original.len() + original.len() / row_len);
+  // Scratch space for filter types 1 through 4.
+  let mut scratch: Vec<u8> = vec![0; row_len * 4];
+  let score: fn(&[u8]) -> u64 = match strategy {
+    FilterHeuristic::EstimatedDeflate => deflate_cost,
+    _ => sum_abs,
+  };
+  let mut previous: Option<&[u8]> = None;
+  for row in original.chunks_exact(row_len) {
+    let (sub, rest) = scratch.split_at_mut(row_len);
+    let (up, rest) = rest.split_at_mut(row_len);
+    let (average, paeth) = rest.split_at_mut(row_len);
+    let filter: u8 = match strategy {
+      FilterHeuristic::Fixed(filter) => filter.into(),
+      FilterHeuristic::MinSumAbs | FilterHeuristic::EstimatedDeflate => {
+        filter_sub::<BYTES_PER_PIXEL>(sub, row);
+        let mut best: (u8, u64) = (0, score(row));
+        let mut consider = |filter: u8, candidate: &[u8]| {
+          let score = score(candidate);
+          if score < best.1 {
+            best = (filter, score);
+          }
+        };
+        consider(1, sub);
+        if let Some(previous) = previous {
+          filter_up(up, row, previous);
+          filter_average::<BYTES_PER_PIXEL>(average, row, previous);
+          filter_paeth::<BYTES_PER_PIXEL>(paeth, row, previous);
+          consider(2, up);
+          consider(3, average);
+          consider(4, paeth);
+        } else {
+          filter_average_top::<BYTES_PER_PIXEL>(average, row);
+          consider(3, average);
+        }
+        best.0
+      }
+    };
+    out.push(filter);
+    let start = out.len();
+    out.resize(start + row_len, 0);
+    let dst = &mut out[start..];
+    // With a fixed filter we haven't computed the candidate yet, so we filter
+    // directly into the output. With a heuristic we already have it.
+    let heuristic = !matches!(strategy, FilterHeuristic::Fixed(_));
+    match (heuristic, filter, previous) {
+      (_, 0, _) | (_, 2, None) => dst.copy_from_slice(row),
+      (true, 1, _) => dst.copy_from_slice(sub),
+      (true, 2, _) => dst.copy_from_slice(up),
+      (true, 3, _) => dst.copy_from_slice(average),
+      (true, _, _) => dst.copy_from_slice(paeth),
+      (_, 3, None) => filter_average_top::<BYTES_PER_PIXEL>(dst, row),
+      (_, 2, Some(previous)) => filter_up(dst, row, previous),
+      (_, 3, Some(previous)) => filter_average::<BYTES_PER_PIXEL>(dst, row, previous),
+      (_, 4, Some(previous)) => filter_paeth::<BYTES_PER_PIXEL>(dst, row, previous),
+      // filter type 1, or paeth on the top row
+      (_, _, _) => filter_sub::<BYTES_PER_PIXEL>(dst, row),
+    }
+    previous = Some(row);
+  }
+}
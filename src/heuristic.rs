@@ -0,0 +1,56 @@
+//! Cheap estimates of how well filtered data will compress.
+//!
+//! Picking a filter type by actually compressing every candidate gives the
+//! best results, but it needs a deflate implementation and it's slow. The
+//! functions here are a rough stand-in: they only look at the bytes
+//! themselves, so you can use them to compare the candidate filters for a
+//! row without deflating anything. That's what
+//! [`FilterHeuristic::EstimatedDeflate`](crate::encode::FilterHeuristic::EstimatedDeflate)
+//! does.
+
+/// Estimates how many bytes `bytes` would take after deflate compression.
+///
+/// This is only an approximation, and it's only meant for comparing buffers
+/// of the same length against each other (such as the candidate filters of
+/// one row). The model is:
+///
+/// * A run of 4 or more of the same byte is one literal followed by one match
+///   at distance 1 (up to 258 bytes per match), and each match costs 13 bits.
+/// * Every other byte is a literal, and literals cost the order-0 entropy of
+///   all the literals (so a few distinct byte values are cheaper than many).
+///
+/// It doesn't find matches at any other distance, and it ignores block and
+/// Huffman table overhead, so real deflate output will usually be smaller
+/// for repetitive data and a little bigger for small buffers.
+#[must_use]
+pub fn estimate_deflate_cost(bytes: &[u8]) -> usize {
+  /// Bits for a length/distance pair at distance 1.
+  const MATCH_BITS: f64 = 13.0;
+  /// Deflate's shortest and longest match lengths.
+  const MIN_MATCH: usize = 3;
+  const MAX_MATCH: usize = 258;
+  //
+  let mut counts = [0_usize; 256];
+  let mut literals = 0_usize;
+  let mut matches = 0_usize;
+  let mut rest = bytes;
+  while let Some(&byte) = rest.first() {
+    let run = rest.iter().take_while(|b| **b == byte).count();
+    let repeats = run - 1;
+    if repeats >= MIN_MATCH {
+      counts[usize::from(byte)] += 1;
+      literals += 1;
+      matches += repeats.div_ceil(MAX_MATCH);
+    } else {
+      counts[usize::from(byte)] += run;
+      literals += run;
+    }
+    rest = &rest[run..];
+  }
+  let literal_bits: f64 = counts
+    .iter()
+    .filter(|count| **count > 0)
+    .map(|&count| count as f64 * (literals as f64 / count as f64).log2())
+    .sum();
+  ((literal_bits + matches as f64 * MATCH_BITS) / 8.0).ceil() as usize
+}
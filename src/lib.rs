@@ -1,4 +1,5 @@
 #![warn(missing_docs)]
+#![cfg_attr(feature = "portable_simd", feature(portable_simd))]
 
 //! Functions to remove the [PNG Filters][png-filters] from encoded bytes.
 //!
@@ -11,17 +12,291 @@
 //!
 //! Generally you should just call [`unfilter_lines`], which will handle an
 //! entire image all at once, and it will automatically select the best
-//! functions based on the bytes per pixel.
+//! functions based on the bytes per pixel. If you want to always use the
+//! fallbacks (for example, to rule out the SIMD code when tracking down a bug)
+//! enable the `no-simd` feature.
+//!
+//! To go the other way, and filter an image for encoding, use the [`encode`]
+//! module. The [`heuristic`] module can estimate how well the filtered bytes
+//! will compress.
+//!
+//! If you need more control than that (forcing a particular backend, starting
+//! from a previous row, or counting the filter types) see the [`options`]
+//! module.
+//!
+//! The CPU features that the automatic selection is based on are in the
+//! [`cpu`] module, if you want to make your own choices from them.
+//!
+//! To see which backends actually get used on a particular machine, enable the
+//! `trace` feature and see the `trace` module. To see how long each filter
+//! type takes, enable the `timing` feature and see the `timing` module. To see
+//! what was done to each row, enable the `diagnostics` feature and see the
+//! `diagnostics` module.
+//!
+//! To check at runtime that every backend gives the right bytes on the
+//! current machine, enable the `self-test` feature and see the `self_test`
+//! module.
+//!
+//! For input that comes from an untrusted file, the [`checked`] module returns
+//! an error for every problem with the input instead of panicking. It also has
+//! [`checked::unfilter_to_vec`], which takes the filtered image by reference
+//! and returns just the pixels in a new `Vec`.
+//!
+//! On a nightly compiler, the `portable_simd` feature adds a `portable` module
+//! written with `core::simd`, and `unfilter_lines` uses it wherever there's no
+//! hand-written SIMD function that's faster.
+//!
+//! Images with a bit depth below 8 can be expanded to one sample per byte
+//! after unfiltering by using the [`unpack`] module.
 
-use core::slice::ChunksExactMut;
+use core::{
+  mem::MaybeUninit,
+  slice::{ChunksExact, ChunksExactMut},
+};
+
+/// Turns a runtime bytes per pixel into a call of a const generic function.
+///
+/// `dispatch_bpp!(bpp, |N| expr)` expands to a match on `bpp` with one arm for
+/// each of `1..=8`, and in each arm `N` is a `const N: usize` with that value.
+/// Any other value panics.
+macro_rules! dispatch_bpp {
+  ($bpp:expr, |$n:ident| $body:expr) => {
+    match $bpp {
+      1 => {
+        const $n: usize = 1;
+        $body
+      }
+      2 => {
+        const $n: usize = 2;
+        $body
+      }
+      3 => {
+        const $n: usize = 3;
+        $body
+      }
+      4 => {
+        const $n: usize = 4;
+        $body
+      }
+      5 => {
+        const $n: usize = 5;
+        $body
+      }
+      6 => {
+        const $n: usize = 6;
+        $body
+      }
+      7 => {
+        const $n: usize = 7;
+        $body
+      }
+      8 => {
+        const $n: usize = 8;
+        $body
+      }
+      // The arms above have to match `SUPPORTED_BYTES_PER_PIXEL`, which the
+      // tests check.
+      other => panic!("bytes_per_pixel must be in SUPPORTED_BYTES_PER_PIXEL, got {other}"),
+    }
+  };
+}
 
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub mod avx2;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub mod avx512;
+pub mod checked;
+pub mod cpu;
+#[cfg(feature = "debug-verify")]
+pub mod debug_verify;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+pub mod encode;
 pub mod fallbacks;
+pub mod heuristic;
 #[cfg(target_arch = "aarch64")]
 pub mod neon;
+pub mod options;
+#[cfg(feature = "portable_simd")]
+pub mod portable;
+#[cfg(feature = "self-test")]
+pub mod self_test;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+mod simd_bytes;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 pub mod sse2;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 pub mod sse4_1;
+#[cfg(feature = "timing")]
+pub mod timing;
+#[cfg(feature = "trace")]
+pub mod trace;
+pub mod unpack;
+mod util;
+
+#[cfg(feature = "debug-verify")]
+use debug_verify::verify_line;
+/// Without the `debug-verify` feature we just run `recon`.
+#[cfg(not(feature = "debug-verify"))]
+#[inline(always)]
+fn verify_line<const BYTES_PER_PIXEL: usize>(
+  _y: usize, _filter: u8, line: &mut [u8], _previous: Option<&[u8]>, recon: impl FnOnce(&mut [u8]),
+) {
+  recon(line)
+}
+
+/// Which reconstruction function a line is using, for the `timing` feature.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Kernel {
+  Sub,
+  Up,
+  Average,
+  AverageTop,
+  Paeth,
+}
+
+/// Runs `recon`, the `kernel` function on line `y`.
+///
+/// This is where the `diagnostics` and `timing` features see each call. Without
+/// them it just runs `recon`.
+#[inline(always)]
+fn run_kernel(_y: usize, _filter: u8, _kernel: Kernel, recon: impl FnOnce()) {
+  #[cfg(feature = "diagnostics")]
+  diagnostics::record(_y, _filter, _kernel);
+  #[cfg(feature = "timing")]
+  timing::time_kernel(_kernel, recon);
+  #[cfg(not(feature = "timing"))]
+  recon()
+}
+
+/// Every bytes per pixel that the unfiltering functions support.
+///
+/// This is `1..=8`: from 8-bit grayscale up to 16-bit RGBA. The
+/// `BYTES_PER_PIXEL` of the const generic functions, and the `bytes_per_pixel`
+/// of [`unfilter_lines_dynamic`], must be one of these.
+pub const SUPPORTED_BYTES_PER_PIXEL: [usize; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+/// If `n` is in [`SUPPORTED_BYTES_PER_PIXEL`].
+#[inline]
+#[must_use]
+pub const fn is_supported_bpp(n: usize) -> bool {
+  // The supported values are always `1..=N`.
+  n >= 1 && n <= SUPPORTED_BYTES_PER_PIXEL.len()
+}
+
+/// One of the five PNG filter types.
+///
+/// The unfiltering functions read the filter type of each line straight from
+/// its filter byte, as a `u8`, since that's what's in the decompressed data.
+/// Anywhere else in the API that you pick or get back a filter type, it's this
+/// enum, so it's always a valid one.
+///
+/// `FilterType::Sub as u8` (or `u8::from`) gives the filter byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum FilterType {
+  /// Filter type 0, the bytes are unchanged.
+  None = 0,
+  /// Filter type 1, predicts from the pixel to the left (`a`).
+  Sub = 1,
+  /// Filter type 2, predicts from the pixel above (`b`).
+  Up = 2,
+  /// Filter type 3, predicts from the average of `a` and `b`.
+  Average = 3,
+  /// Filter type 4, predicts with the paeth predictor of `a`, `b`, and `c`.
+  Paeth = 4,
+}
+impl FilterType {
+  /// Every filter type, in order.
+  pub const ALL: [Self; 5] = [Self::None, Self::Sub, Self::Up, Self::Average, Self::Paeth];
+}
+impl From<FilterType> for u8 {
+  #[inline]
+  fn from(filter: FilterType) -> Self {
+    filter as u8
+  }
+}
+impl TryFrom<u8> for FilterType {
+  type Error = InvalidFilterType;
+  #[inline]
+  fn try_from(filter: u8) -> Result<Self, Self::Error> {
+    match filter {
+      0 => Ok(Self::None),
+      1 => Ok(Self::Sub),
+      2 => Ok(Self::Up),
+      3 => Ok(Self::Average),
+      4 => Ok(Self::Paeth),
+      other => Err(InvalidFilterType(other)),
+    }
+  }
+}
+
+/// A filter byte above 4, which isn't a [`FilterType`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InvalidFilterType(pub u8);
+impl core::fmt::Display for InvalidFilterType {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(f, "{} isn't a filter type", self.0)
+  }
+}
+impl std::error::Error for InvalidFilterType {}
+
+/// The smallest bytes per pixel at which `unfilter_lines` picks a SIMD function
+/// for each filter type.
+///
+/// Below these, setting up the SIMD registers costs more than it saves and the
+/// fallbacks are used instead. [`DispatchThresholds::DEFAULT`] has the values
+/// that were measured to be best on each target, see `bench_paeth_3_*` for
+/// paeth at ByPP 3. To try other values on your own machine and images, use
+/// [`UnfilterOptions::thresholds`](options::UnfilterOptions::thresholds).
+///
+/// A threshold only allows SIMD, it doesn't add a SIMD function where there
+/// isn't one. The `neon` paeth, sub, and average are never used at ByPP 3 (the
+/// scalar versions are faster there), and "up" has no threshold since it
+/// doesn't depend on the bytes per pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DispatchThresholds {
+  /// Filter type 1.
+  pub sub: usize,
+  /// Filter type 3, including the top line.
+  pub average: usize,
+  /// Filter type 4.
+  pub paeth: usize,
+}
+impl DispatchThresholds {
+  /// The thresholds that `unfilter_lines` uses.
+  #[cfg(all(not(target_arch = "aarch64"), not(feature = "portable_simd")))]
+  pub const DEFAULT: Self = Self { sub: 1, average: 8, paeth: 3 };
+  /// The thresholds that `unfilter_lines` uses.
+  ///
+  /// The `portable` average is faster than the fallback from ByPP 4, unlike
+  /// the `sse2` and `sse4_1` ones.
+  #[cfg(all(not(target_arch = "aarch64"), feature = "portable_simd"))]
+  pub const DEFAULT: Self = Self { sub: 1, average: 4, paeth: 3 };
+  /// The thresholds that `unfilter_lines` uses.
+  #[cfg(target_arch = "aarch64")]
+  pub const DEFAULT: Self = Self { sub: 2, average: 2, paeth: 2 };
+}
+impl Default for DispatchThresholds {
+  #[inline]
+  fn default() -> Self {
+    Self::DEFAULT
+  }
+}
+
+/// The `assert!(is_supported_bpp(BYTES_PER_PIXEL));` of the public functions.
+///
+/// This is checked up front, so it panics no matter which filter types the
+/// lines have, and with `#[track_caller]` the panic points at the user's call
+/// instead of a fallback deep inside the crate.
+#[inline(always)]
+#[track_caller]
+pub(crate) fn assert_bytes_per_pixel<const BYTES_PER_PIXEL: usize>() {
+  assert!(
+    is_supported_bpp(BYTES_PER_PIXEL),
+    "BYTES_PER_PIXEL must be in SUPPORTED_BYTES_PER_PIXEL, got {BYTES_PER_PIXEL}"
+  );
+}
 
 /// Given the bytes for each filtered line, unfilters the data in place.
 ///
@@ -29,113 +304,1452 @@ pub mod sse4_1;
 /// following bytes will be the image data. The number of following bytes should
 /// evenly divide by `BYTES_PER_PIXEL`.
 ///
+/// Degenerate images are allowed:
+/// * An image with zero lines (an empty iterator) does nothing.
+/// * An image with a width of zero has lines that are only the filter byte
+///   (`chunks_exact_mut(1)`). The filter bytes are all set to 0 and nothing
+///   else happens.
+///
+/// Once a line is done its filter byte is set to 0, so afterwards every line is
+/// marked as filter type 0 ("none") and the buffer is a valid unfiltered PNG
+/// image that can be re-encoded as-is. This includes lines with an invalid
+/// filter type, which are left as they were except for the filter byte. The
+/// other in-place functions in this crate do the same, except where their docs
+/// say otherwise. To keep the filter types, use
+/// [`unfilter_lines_keep_filters`].
+///
+/// Returns the number of reconstructed bytes, which is the total length of the
+/// lines minus one filter byte per line (so, height * width * bytes per pixel).
+/// You can use this to check that the image is the size you expected.
+///
+/// `chunks_exact_mut` leaves out any partial line at the end of the buffer, so
+/// a wrong line length can lose the last line without any other sign. The
+/// returned length shows this, or use [`unfilter_flat`] (which panics) or
+/// [`try_unfilter_flat`] (which returns an error) to have the whole buffer
+/// checked.
+///
 /// ## Panics
-/// * `assert!(BYTES_PER_PIXEL <= 8);`
+/// * `assert!(is_supported_bpp(BYTES_PER_PIXEL));` (see
+///   [`SUPPORTED_BYTES_PER_PIXEL`])
 /// * `debug_assert_eq!(following_bytes.len() % BYTES_PER_PIXEL, 0);`
+#[track_caller]
 #[inline]
-#[allow(unused_mut)]
-pub fn unfilter_lines<const BYTES_PER_PIXEL: usize>(lines: ChunksExactMut<'_, u8>) {
-  let mut sub: unsafe fn(&mut [u8]) = fallbacks::recon_sub::<BYTES_PER_PIXEL>;
-  let mut up: unsafe fn(&mut [u8], &[u8]) = fallbacks::recon_up;
-  let mut average: unsafe fn(&mut [u8], &[u8]) = fallbacks::recon_average::<BYTES_PER_PIXEL>;
-  let mut average_top: unsafe fn(&mut [u8]) = fallbacks::recon_average_top::<BYTES_PER_PIXEL>;
-  let mut paeth: unsafe fn(&mut [u8], &[u8]) = fallbacks::recon_paeth::<BYTES_PER_PIXEL>;
-
-  #[cfg(FALSE)]
-  if is_x86_feature_detected!("sse4.1") {
-    sub = sse4_1::recon_sub::<BYTES_PER_PIXEL>;
-    up = sse4_1::recon_up;
-    average = sse4_1::recon_average::<BYTES_PER_PIXEL>;
-    average_top = sse4_1::recon_average_top::<BYTES_PER_PIXEL>;
-    paeth = sse4_1::recon_paeth::<BYTES_PER_PIXEL>;
-  }
-  #[cfg(FALSE)]
-  if is_x86_feature_detected!("sse2") {
-    sub = sse2::recon_sub::<BYTES_PER_PIXEL>;
-    up = sse2::recon_up;
-    average = sse2::recon_average::<BYTES_PER_PIXEL>;
-    average_top = sse2::recon_average_top::<BYTES_PER_PIXEL>;
-    paeth = sse2::recon_paeth::<BYTES_PER_PIXEL>;
-  }
-  #[cfg(FALSE)]
-  if std::arch::is_aarch64_feature_detected!("neon") {
-    sub = neon::recon_sub::<BYTES_PER_PIXEL>;
-    up = neon::recon_up;
-    average = neon::recon_average::<BYTES_PER_PIXEL>;
-    average_top = neon::recon_average_top::<BYTES_PER_PIXEL>;
-    paeth = neon::recon_paeth::<BYTES_PER_PIXEL>;
-  }
-  //#[cfg(FALSE)]
-  #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-  {
-    let has_sse4_1 = is_x86_feature_detected!("sse4.1");
-    let has_sse2 = is_x86_feature_detected!("sse2");
-    if BYTES_PER_PIXEL >= 8 && has_sse4_1 {
-      average = sse4_1::recon_average::<BYTES_PER_PIXEL>;
-      average_top = sse4_1::recon_average_top::<BYTES_PER_PIXEL>;
-    } else if BYTES_PER_PIXEL >= 8 && has_sse2 {
-      average = sse2::recon_average::<BYTES_PER_PIXEL>;
-      average_top = sse2::recon_average_top::<BYTES_PER_PIXEL>;
-    }
-    if BYTES_PER_PIXEL >= 3 && has_sse4_1 {
-      paeth = sse4_1::recon_paeth::<BYTES_PER_PIXEL>;
-    } else if BYTES_PER_PIXEL >= 3 && has_sse2 {
-      paeth = sse2::recon_paeth::<BYTES_PER_PIXEL>;
-    }
-    if BYTES_PER_PIXEL >= 4 && has_sse2 {
-      sub = sse2::recon_sub::<BYTES_PER_PIXEL>;
-      // This only affects i586 targets running with sse2, but we might as well
-      // put it here.
-      up = sse2::recon_up;
-    }
-  }
-  //#[cfg(FALSE)]
-  #[cfg(target_arch = "aarch64")]
-  {
-    let has_neon = std::arch::is_aarch64_feature_detected!("neon");
-    if (BYTES_PER_PIXEL == 2 || BYTES_PER_PIXEL >= 4) && has_neon {
+#[must_use = "the number of reconstructed bytes should be checked"]
+pub fn unfilter_lines<const BYTES_PER_PIXEL: usize>(lines: ChunksExactMut<'_, u8>) -> usize {
+  assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+  //
+  let mut lines = lines.peekable();
+  // All lines are the same length, so the first line gives us the width.
+  let width_bytes = lines.peek().map_or(0, |line| line.len() - 1);
+  let reconstructed = width_bytes * lines.len();
+  unfilter_lines_with(None, lines, ReconFns::<BYTES_PER_PIXEL>::select);
+  reconstructed
+}
+
+/// Unfilters one frame of an animated PNG (APNG) in place.
+///
+/// `frame` is the frame's decompressed data: `frame_height` lines, each of
+/// which is a filter type byte followed by `frame_width` pixels. In APNG each
+/// frame is filtered on its own, relative to the frame rather than the canvas,
+/// so the frame's first line is always a top line (the previous row is all 0)
+/// no matter where the frame's sub-rectangle is on the canvas.
+///
+/// This only does the unfiltering. Placing the frame on the canvas (with its
+/// offsets, dispose op, and blend op) is up to you.
+///
+/// Returns the number of reconstructed bytes, the same as [`unfilter_lines`].
+///
+/// ## Panics
+/// * As [`unfilter_lines`]
+/// * `assert_eq!(frame.len(), frame_height * (1 + frame_width *
+///   BYTES_PER_PIXEL));`
+#[track_caller]
+#[inline]
+#[must_use = "the number of reconstructed bytes should be checked"]
+pub fn unfilter_frame<const BYTES_PER_PIXEL: usize>(
+  frame: &mut [u8], frame_width: usize, frame_height: usize,
+) -> usize {
+  let line_len = 1 + frame_width * BYTES_PER_PIXEL;
+  assert_eq!(frame.len(), frame_height * line_len);
+  //
+  unfilter_lines::<BYTES_PER_PIXEL>(frame.chunks_exact_mut(line_len))
+}
+
+/// As [`unfilter_lines`], but calls `hook` on each line as soon as it's been
+/// reconstructed.
+///
+/// The `hook` gets the index of the line and the line's reconstructed data
+/// (without the filter byte). It's called for every line in order, including
+/// lines with filter type 0, before the next line is reconstructed. This lets
+/// you update a running checksum, collect statistics, or stream rows out
+/// while each row is still in the cache, instead of with a second pass over
+/// the image.
+///
+/// ## Panics
+/// * As [`unfilter_lines`]
+#[track_caller]
+#[inline]
+#[must_use = "the number of reconstructed bytes should be checked"]
+pub fn unfilter_lines_with_hook<const BYTES_PER_PIXEL: usize>(
+  lines: ChunksExactMut<'_, u8>, hook: &mut impl FnMut(usize, &[u8]),
+) -> usize {
+  assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+  //
+  let mut lines = lines.peekable();
+  let width_bytes = lines.peek().map_or(0, |line| line.len() - 1);
+  let reconstructed = width_bytes * lines.len();
+  // Won't panic: `chunk_size` is always non-zero (ChunksExactMut invariant).
+  let lines = lines.map(|line| line.split_first_mut().unwrap());
+  unfilter_split_with(None, lines, ReconFns::<BYTES_PER_PIXEL>::select, hook);
+  reconstructed
+}
+
+/// Something that receives each row as [`unfilter_lines_to_sink`] reconstructs
+/// it.
+///
+/// This is the trait object version of the hook for
+/// [`unfilter_lines_with_hook`]. A sink can carry its own state, and which sink
+/// gets used can be picked at runtime (a transcoder, a checksum, and so on).
+pub trait RowSink {
+  /// Receives the index and reconstructed data (without the filter byte) of
+  /// one row.
+  fn row(&mut self, index: usize, data: &[u8]);
+}
+
+/// As [`unfilter_lines_with_hook`], but sends each row to a [`RowSink`].
+///
+/// ## Panics
+/// * As [`unfilter_lines`]
+#[track_caller]
+#[inline]
+#[must_use = "the number of reconstructed bytes should be checked"]
+pub fn unfilter_lines_to_sink<const BYTES_PER_PIXEL: usize>(
+  lines: ChunksExactMut<'_, u8>, sink: &mut dyn RowSink,
+) -> usize {
+  unfilter_lines_with_hook::<BYTES_PER_PIXEL>(lines, &mut |index, data| sink.row(index, data))
+}
+
+/// A filter type outside of PNG's five, for [`unfilter_lines_custom`].
+///
+/// Some research and proprietary formats use the PNG layout (a filter type byte
+/// at the start of each line) with extra filter types of their own.
+pub trait RowFilter {
+  /// Reconstructs one line in place.
+  ///
+  /// * `filtered` is the line's data, without the filter byte.
+  /// * `previous` is the reconstructed row above it, the same length as
+  ///   `filtered`. For the top line of the image it's all 0.
+  fn reconstruct(&self, filtered: &mut [u8], previous: &[u8]);
+}
+
+/// As [`unfilter_lines`], but with extra filter types.
+///
+/// `custom[i]` is the filter for filter type `5 + i`, so `custom` can have up
+/// to 251 entries. Filter types 0 to 4 use the same functions as
+/// [`unfilter_lines`], and any filter type past the end of `custom` is invalid
+/// (the line is left as it was, other than the filter byte being set to 0).
+///
+/// Each run of lines that don't use a custom filter is done the same as with
+/// [`unfilter_lines`], so an image that only uses a custom filter now and then
+/// is about as fast as a normal image.
+///
+/// ## Panics
+/// * As [`unfilter_lines`]
+#[track_caller]
+#[must_use = "the number of reconstructed bytes should be checked"]
+pub fn unfilter_lines_custom<const BYTES_PER_PIXEL: usize, F: RowFilter>(
+  lines: ChunksExactMut<'_, u8>, custom: &[F],
+) -> usize {
+  assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+  //
+  let mut rows: Vec<&mut [u8]> = lines.collect();
+  let width_bytes = rows.first().map_or(0, |row| row.len() - 1);
+  let reconstructed = width_bytes * rows.len();
+  let custom_filter = |filter: u8| custom.get(usize::from(filter.checked_sub(5)?));
+  // Only selected once there's a line that needs it, as in `unfilter_lines`.
+  let mut fns = None;
+  let mut start = 0;
+  while start < rows.len() {
+    let (done, rest) = rows.split_at_mut(start);
+    let previous: Option<&[u8]> = done.last().map(|row| &row[1..]);
+    let run = rest.iter().take_while(|row| custom_filter(row[0]).is_none()).count();
+    if run > 0 {
+      let lines = rest[..run].iter_mut().map(|row| &mut **row);
+      unfilter_lines_with(previous, lines, || {
+        *fns.get_or_insert_with(ReconFns::<BYTES_PER_PIXEL>::select)
+      });
+      start += run;
+    } else {
+      // Won't panic: every line has at least the filter byte.
+      let (filter, data) = rest[0].split_first_mut().unwrap();
+      let zero_row;
+      let previous = match previous {
+        Some(previous) => previous,
+        None => {
+          zero_row = vec![0; data.len()];
+          &zero_row
+        }
+      };
+      // Won't panic: `run` is 0, so this line has a custom filter type.
+      custom_filter(*filter).unwrap().reconstruct(data, previous);
+      *filter = 0;
+      start += 1;
+    }
+  }
+  reconstructed
+}
+
+/// As [`unfilter_lines`], but the filter bytes are left as they were instead of
+/// being set to 0.
+///
+/// This is for when the image will be filtered again with the same filter
+/// types, such as a re-encoder that only changes some pixels. The filter type
+/// of each line is copied out first (one allocation of a few bytes per line),
+/// so afterwards each line is its original filter type followed by the
+/// reconstructed data.
+///
+/// ## Panics
+/// * As [`unfilter_lines`]
+#[track_caller]
+#[inline]
+#[must_use = "the number of reconstructed bytes should be checked"]
+pub fn unfilter_lines_keep_filters<const BYTES_PER_PIXEL: usize>(
+  lines: ChunksExactMut<'_, u8>,
+) -> usize {
+  assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+  //
+  let mut rows: Vec<(u8, &mut [u8])> = lines
+    .map(|line| {
+      // Won't panic: `chunk_size` is always non-zero (ChunksExactMut invariant).
+      let (filter, data) = line.split_first_mut().unwrap();
+      (*filter, data)
+    })
+    .collect();
+  let reconstructed = rows.first().map_or(0, |(_, data)| data.len()) * rows.len();
+  // The copies of the filter bytes are what get set to 0.
+  let lines = rows.iter_mut().map(|(filter, data)| (filter, &mut **data));
+  unfilter_split_with(None, lines, ReconFns::<BYTES_PER_PIXEL>::select, |_, _| ());
+  reconstructed
+}
+
+/// Reconstructs `count` lines, starting after a row that's already been
+/// reconstructed.
+///
+/// This is for when you only need some rows of an image, such as for
+/// progressive rendering or a thumbnail. Rows filtered with "up", "average",
+/// or "paeth" depend on the reconstructed row above them, so to do rows
+/// `start..end` you need row `start - 1` already reconstructed.
+///
+/// * `previous` is the reconstructed row just above the first line, without a
+///   filter byte. For the top rows of an image, use [`unfilter_lines`].
+/// * `lines` starts at the first line to reconstruct. Only the first `count` of
+///   them are used, so this can be the rest of the image.
+///
+/// Returns the number of reconstructed bytes.
+///
+/// ## Panics
+/// * As [`unfilter_lines`]
+/// * `assert!(count <= lines.len());`
+/// * `assert_eq!(previous.len(), line_len - 1);` (when `count` isn't 0)
+#[track_caller]
+#[inline]
+#[must_use = "the number of reconstructed bytes should be checked"]
+pub fn unfilter_row_range<const BYTES_PER_PIXEL: usize>(
+  previous: &[u8], lines: ChunksExactMut<'_, u8>, count: usize,
+) -> usize {
+  assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+  assert!(count <= lines.len());
+  let mut lines = lines.take(count).peekable();
+  let width_bytes = match lines.peek() {
+    Some(line) => line.len() - 1,
+    None => return 0,
+  };
+  assert_eq!(previous.len(), width_bytes);
+  //
+  unfilter_lines_with(Some(previous), lines, ReconFns::<BYTES_PER_PIXEL>::select);
+  width_bytes * count
+}
+
+/// Reconstructs one row, with the row above it given separately.
+///
+/// This is for when the rows aren't next to each other in one buffer, such as
+/// a decoder that only keeps the last few rows of a huge image in a ring
+/// buffer. Call it once per row, in order, passing the slot that holds the
+/// reconstructed row above.
+///
+/// * `filter` is the row's filter type. Invalid filter types leave the row
+///   as-is, the same as [`unfilter_lines`].
+/// * `row` is the row's data, without the filter byte.
+/// * `previous` is the reconstructed row above `row`, or empty if `row` is the
+///   top row of the image.
+///
+/// This is the same loop as [`unfilter_lines`] with a single line, so it gives
+/// the same output, but the backend is looked up again for each row. For rows
+/// that are all in one buffer, [`unfilter_lines`] or [`unfilter_row_range`]
+/// only do that once.
+///
+/// ## Panics
+/// * `assert!(is_supported_bpp(BYTES_PER_PIXEL));` (see
+///   [`SUPPORTED_BYTES_PER_PIXEL`])
+/// * `assert!(previous.is_empty() || previous.len() == row.len());`
+/// * `debug_assert_eq!(row.len() % BYTES_PER_PIXEL, 0);`
+#[track_caller]
+#[inline]
+pub fn unfilter_row_with_prev<const BYTES_PER_PIXEL: usize>(
+  filter: u8, row: &mut [u8], previous: &[u8],
+) {
+  assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+  assert!(previous.is_empty() || previous.len() == row.len());
+  debug_assert_eq!(row.len() % BYTES_PER_PIXEL, 0);
+  //
+  let previous = if previous.is_empty() { None } else { Some(previous) };
+  let mut filter = filter;
+  unfilter_split_with(
+    previous,
+    core::iter::once((&mut filter, row)),
+    ReconFns::<BYTES_PER_PIXEL>::select,
+    |_, _| (),
+  );
+}
+
+/// As [`unfilter_lines`], but problems with the input are returned as an error
+/// instead of being ignored.
+///
+/// This is the same as running the default [`options::UnfilterOptions`], see
+/// [`options::UnfilterError`] for the possible errors and which of them still
+/// unfilter the lines.
+///
+/// ## Panics
+/// * As [`unfilter_lines`]
+#[track_caller]
+#[inline]
+pub fn try_unfilter_lines<const BYTES_PER_PIXEL: usize>(
+  lines: ChunksExactMut<'_, u8>,
+) -> Result<usize, options::UnfilterError> {
+  let mut lines = lines.peekable();
+  let width_bytes = lines.peek().map_or(0, |line| line.len() - 1);
+  let reconstructed = width_bytes * lines.len();
+  options::UnfilterOptions::new().run_lines::<BYTES_PER_PIXEL>(lines).result.map(|()| reconstructed)
+}
+
+/// As [`try_unfilter_lines`], but stops at the first line with an invalid
+/// filter type.
+///
+/// [`try_unfilter_lines`] unfilters every line and then reports the first
+/// invalid filter type. This instead checks each filter byte just before that
+/// line would be unfiltered, in the same pass, and returns
+/// [`options::UnfilterError::InvalidFilterType`] right away. The lines before
+/// the bad line are unfiltered, and the bad line and every line after it are
+/// left exactly as they were (including their filter bytes).
+///
+/// On success this returns the number of reconstructed bytes, the same as
+/// [`unfilter_lines`].
+///
+/// ## Panics
+/// * As [`unfilter_lines`]
+#[track_caller]
+#[inline]
+pub fn try_unfilter_lines_inline<const BYTES_PER_PIXEL: usize>(
+  lines: ChunksExactMut<'_, u8>,
+) -> Result<usize, options::UnfilterError> {
+  try_unfilter_lines_inline_after::<BYTES_PER_PIXEL>(None, lines)
+}
+
+/// As [`try_unfilter_lines_inline`], but starting from a `previous` row.
+///
+/// * `previous` must be the same length as each line minus its filter byte.
+///   Callers check this themselves, since the `recon_*` functions only
+///   `debug_assert!` it.
+#[track_caller]
+#[inline]
+pub(crate) fn try_unfilter_lines_inline_after<'a, const BYTES_PER_PIXEL: usize>(
+  previous: Option<&'a [u8]>, lines: ChunksExactMut<'a, u8>,
+) -> Result<usize, options::UnfilterError> {
+  assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+  //
+  let mut lines = lines.peekable();
+  let width_bytes = lines.peek().map_or(0, |line| line.len() - 1);
+  let reconstructed = width_bytes * lines.len();
+  let mut invalid = None;
+  let lines = lines.enumerate().map_while(|(y, line)| match line[0] {
+    0..=4 => Some(line),
+    filter => {
+      invalid = Some(options::UnfilterError::InvalidFilterType { y, filter });
+      None
+    }
+  });
+  unfilter_lines_with(previous, lines, ReconFns::<BYTES_PER_PIXEL>::select);
+  match invalid {
+    Some(error) => Err(error),
+    None => Ok(reconstructed),
+  }
+}
+
+/// As [`unfilter_lines`], but the bytes per pixel is picked at runtime.
+///
+/// This is for when the bytes per pixel comes from the image header rather
+/// than being known at compile time.
+///
+/// ## Panics
+/// * If `bytes_per_pixel` isn't in [`SUPPORTED_BYTES_PER_PIXEL`].
+/// * As [`unfilter_lines`]
+#[track_caller]
+#[inline]
+#[must_use = "the number of reconstructed bytes should be checked"]
+pub fn unfilter_lines_dynamic(bytes_per_pixel: usize, lines: ChunksExactMut<'_, u8>) -> usize {
+  dispatch_bpp!(bytes_per_pixel, |N| unfilter_lines::<N>(lines))
+}
+
+/// As [`unfilter_lines`], but writes the reconstructed data to a separate
+/// buffer instead of working in place.
+///
+/// Each line of `lines` is a filter type byte followed by the filtered data,
+/// and the matching line of `dst` gets the reconstructed data (without any
+/// filter byte). This means that the `dst` lines should be one byte shorter
+/// than the `lines` lines. The filtered data is never modified, so unlike
+/// [`unfilter_lines`] the filter bytes in `lines` keep their filter types.
+///
+/// This always uses the [`fallbacks`] module's `_to` functions.
+///
+/// Like [`unfilter_lines`], zero lines is a no-op. For a zero-width image each
+/// `dst` line would be empty, which `chunks_exact_mut` can't produce, so those
+/// images should just skip this call entirely.
+///
+/// ## Panics
+/// * `assert!(is_supported_bpp(BYTES_PER_PIXEL));` (see
+///   [`SUPPORTED_BYTES_PER_PIXEL`])
+/// * `debug_assert_eq!(following_bytes.len() % BYTES_PER_PIXEL, 0);`
+/// * If a `dst` line isn't exactly one byte shorter than the matching line of
+///   `lines`.
+#[track_caller]
+#[inline]
+pub fn unfilter_lines_to<const BYTES_PER_PIXEL: usize>(
+  lines: ChunksExact<'_, u8>, dst: ChunksExactMut<'_, u8>,
+) {
+  assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+  //
+  let mut previous = PreviousRow::Zero;
+  lines.zip(dst).for_each(|(line, dst)| {
+    // Won't panic: `chunk_size` is always non-zero (ChunksExact invariant).
+    let (filter, line) = line.split_first().unwrap();
+    assert_eq!(line.len(), dst.len());
+    match (filter, previous) {
+      (1, _) | (4, PreviousRow::Zero) => fallbacks::recon_sub_to::<BYTES_PER_PIXEL>(dst, line),
+      (2, PreviousRow::Data(b)) => fallbacks::recon_up_to(dst, line, b),
+      (3, PreviousRow::Data(b)) => fallbacks::recon_average_to::<BYTES_PER_PIXEL>(dst, line, b),
+      (3, PreviousRow::Zero) => fallbacks::recon_average_top_to::<BYTES_PER_PIXEL>(dst, line),
+      (4, PreviousRow::Data(b)) => fallbacks::recon_paeth_to::<BYTES_PER_PIXEL>(dst, line, b),
+      _ => dst.copy_from_slice(line),
+    }
+    previous = PreviousRow::Data(dst);
+  });
+}
+
+/// As [`unfilter_lines_to`], but `dst` doesn't need to be initialized first.
+///
+/// `dst` holds all the reconstructed lines back to back, with no filter bytes,
+/// so it should be `lines.len()` times one less than the line length. Every
+/// byte of `dst` is written exactly once (lines with filter type 0 or an
+/// invalid filter type are copied), and then it's returned as initialized
+/// bytes. This lets a decoder use a freshly allocated buffer, such as from
+/// [`Box::new_uninit_slice`], without zeroing it first.
+///
+/// This always uses the [`fallbacks`] module's `_to_uninit` functions.
+///
+/// ## Panics
+/// * `assert!(is_supported_bpp(BYTES_PER_PIXEL));` (see
+///   [`SUPPORTED_BYTES_PER_PIXEL`])
+/// * `assert_eq!(following_bytes.len() % BYTES_PER_PIXEL, 0);`
+/// * `assert_eq!(dst.len(), lines.len() * (line_len - 1));`
+#[track_caller]
+#[inline]
+pub fn unfilter_lines_to_uninit<'d, const BYTES_PER_PIXEL: usize>(
+  lines: ChunksExact<'_, u8>, dst: &'d mut [MaybeUninit<u8>],
+) -> &'d mut [u8] {
+  assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+  let row_len = lines.clone().next().map_or(0, |line| line.len() - 1);
+  assert_eq!(dst.len(), lines.len() * row_len);
+  if dst.is_empty() {
+    // Zero lines, or a zero-width image: there's nothing to write. This also
+    // avoids `chunks_exact_mut(0)` below.
+    return &mut [];
+  }
+  //
+  let mut previous = PreviousRow::Zero;
+  lines.zip(dst.chunks_exact_mut(row_len)).for_each(|(line, dst)| {
+    // Won't panic: `chunk_size` is always non-zero (ChunksExact invariant).
+    let (filter, line) = line.split_first().unwrap();
+    let dst = match (filter, previous) {
+      (1, _) | (4, PreviousRow::Zero) => {
+        fallbacks::recon_sub_to_uninit::<BYTES_PER_PIXEL>(dst, line)
+      }
+      (2, PreviousRow::Data(b)) => fallbacks::recon_up_to_uninit(dst, line, b),
+      (3, PreviousRow::Data(b)) => {
+        fallbacks::recon_average_to_uninit::<BYTES_PER_PIXEL>(dst, line, b)
+      }
+      (3, PreviousRow::Zero) => {
+        fallbacks::recon_average_top_to_uninit::<BYTES_PER_PIXEL>(dst, line)
+      }
+      (4, PreviousRow::Data(b)) => {
+        fallbacks::recon_paeth_to_uninit::<BYTES_PER_PIXEL>(dst, line, b)
+      }
+      _ => fallbacks::copy_to_uninit(dst, line),
+    };
+    previous = PreviousRow::Data(dst);
+  });
+  // Safety: `dst` is exactly `lines.len()` chunks of `row_len`, and each chunk
+  // was written by one of the functions above, which all write every byte.
+  unsafe { &mut *(dst as *mut [MaybeUninit<u8>] as *mut [u8]) }
+}
+
+/// As [`unfilter_lines`], but with the filter bytes in their own slice instead
+/// of at the start of each line.
+///
+/// Some decoders keep each row's filter type separate from the row data. With
+/// this you don't need to copy the data into one interleaved buffer first.
+///
+/// * `filters` has the filter type of each row, in order. Like the filter bytes
+///   for [`unfilter_lines`], each is set to 0 once that row is done.
+/// * `rows` has the filtered data of each row, with no filter byte.
+///
+/// Zero-width images can't be given as `rows`, since `chunks_exact_mut` needs
+/// a non-zero size, so those should skip this call.
+///
+/// Returns the number of reconstructed bytes, the same as [`unfilter_lines`].
+///
+/// ## Panics
+/// * As [`unfilter_lines`]
+/// * `assert_eq!(filters.len(), rows.len());`
+#[track_caller]
+#[inline]
+#[must_use = "the number of reconstructed bytes should be checked"]
+pub fn unfilter_split<const BYTES_PER_PIXEL: usize>(
+  filters: &mut [u8], rows: ChunksExactMut<'_, u8>,
+) -> usize {
+  assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+  assert_eq!(filters.len(), rows.len());
+  //
+  let mut rows = rows.peekable();
+  let reconstructed = rows.peek().map_or(0, |row| row.len()) * filters.len();
+  unfilter_split_with(
+    None,
+    filters.iter_mut().zip(rows),
+    ReconFns::<BYTES_PER_PIXEL>::select,
+    |_, _| (),
+  );
+  reconstructed
+}
+
+/// As [`unfilter_split`], but for an image with a size known at compile time.
+///
+/// * `filters` has the filter type of each row, and each is set to 0 once that
+///   row is done.
+/// * `rows` has the filtered data of each row, `ROW_LEN` bytes each (so, the
+///   width times `BYTES_PER_PIXEL`).
+///
+/// This is meant for small assets of a fixed size, such as 16x16 tiles. There
+/// is no feature detection or runtime dispatch: each line calls the fastest
+/// of the [`fallbacks`] for `BYTES_PER_PIXEL` directly, so the calls can be
+/// inlined. Only "up" uses the fixed row length
+/// ([`fallbacks::recon_up_fixed`]), which lets it be fully unrolled and
+/// auto-vectorized. The other filters take slices, as usual.
+///
+/// ## Panics
+/// * `assert!(is_supported_bpp(BYTES_PER_PIXEL));` (see
+///   [`SUPPORTED_BYTES_PER_PIXEL`])
+/// * `debug_assert_eq!(ROW_LEN % BYTES_PER_PIXEL, 0);`
+#[track_caller]
+#[inline]
+pub fn unfilter_lines_fixed<
+  const BYTES_PER_PIXEL: usize,
+  const ROW_LEN: usize,
+  const HEIGHT: usize,
+>(
+  filters: &mut [u8; HEIGHT], rows: &mut [[u8; ROW_LEN]; HEIGHT],
+) {
+  assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+  //
+  for (y, filter) in filters.iter_mut().enumerate() {
+    let (above, rest) = rows.split_at_mut(y);
+    let row = &mut rest[0];
+    // the same cases as `unfilter_remaining_lines`, with `None` as the top line.
+    match (*filter, above.last()) {
+      (1, _) | (4, None) => fallbacks::recon_sub::<BYTES_PER_PIXEL>(row),
+      (2, Some(b)) => fallbacks::recon_up_fixed(row, b),
+      (3, Some(b)) => recon_average_fallback::<BYTES_PER_PIXEL>(row, b),
+      (3, None) => fallbacks::recon_average_top::<BYTES_PER_PIXEL>(row),
+      (4, Some(b)) => recon_paeth_fallback::<BYTES_PER_PIXEL>(row, b),
+      _ => (),
+    }
+    *filter = 0;
+  }
+}
+
+/// As [`unfilter_lines`], but then removes the filter bytes so that `buf` is
+/// only the pixel data.
+///
+/// * `row_len` is the length of each line *including* the filter byte (the same
+///   as the `chunk_size` you'd pass to `chunks_exact_mut` for
+///   [`unfilter_lines`]).
+///
+/// After reconstruction each row is moved down over the filter bytes before
+/// it. A row's new position always ends before the next row's data starts, so
+/// going from the top down never overwrites data that hasn't been moved yet.
+/// Then `buf` is truncated to the compacted length, which is returned.
+///
+/// Since the filter bytes are removed there are no filter type 0 markers left
+/// in `buf`, unlike with [`unfilter_lines`].
+///
+/// ## Panics
+/// * As [`unfilter_lines`]
+/// * `assert_eq!(buf.len() % row_len, 0);` (when `row_len` isn't 0)
+/// * If `row_len` is 0 and `buf` isn't empty.
+#[track_caller]
+pub fn unfilter_and_compact<const BYTES_PER_PIXEL: usize>(
+  buf: &mut Vec<u8>, row_len: usize,
+) -> usize {
+  if row_len == 0 {
+    assert!(buf.is_empty(), "row_len is 0 but buf has data");
+    return 0;
+  }
+  assert_eq!(buf.len() % row_len, 0);
+  //
+  let compacted = unfilter_lines::<BYTES_PER_PIXEL>(buf.chunks_exact_mut(row_len));
+  let width_bytes = row_len - 1;
+  for y in 0..buf.len() / row_len {
+    let start = y * row_len + 1;
+    buf.copy_within(start..start + width_bytes, y * width_bytes);
+  }
+  buf.truncate(compacted);
+  compacted
+}
+
+/// As [`unfilter_lines`], but each line has padding bytes after the image
+/// data.
+///
+/// * `stride` is the length of each line in `buf`: the filter byte, the
+///   filtered data, and then the padding.
+/// * `used_bytes_per_row` is the length of the filtered data (so, the width
+///   times `BYTES_PER_PIXEL`), not counting the filter byte.
+///
+/// Only the filter byte and the filtered data of each line are touched. The
+/// padding is left as-is, and it's never used as part of the previous row.
+///
+/// A `stride` of 0 can only describe an image with no data at all, so in that
+/// case this does nothing.
+///
+/// Returns the number of reconstructed bytes, which is `used_bytes_per_row`
+/// for each line.
+///
+/// ## Panics
+/// * As [`unfilter_lines`]
+/// * `assert!(used_bytes_per_row < stride);` (when `stride` isn't 0)
+/// * `assert_eq!(buf.len() % stride, 0);` (when `stride` isn't 0)
+/// * If `stride` is 0 and `buf` isn't empty.
+#[track_caller]
+#[inline]
+#[must_use = "the number of reconstructed bytes should be checked"]
+pub fn unfilter_lines_strided<const BYTES_PER_PIXEL: usize>(
+  buf: &mut [u8], stride: usize, used_bytes_per_row: usize,
+) -> usize {
+  assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+  if stride == 0 {
+    assert!(buf.is_empty(), "stride is 0 but buf has data");
+    return 0;
+  }
+  assert!(used_bytes_per_row < stride);
+  assert_eq!(buf.len() % stride, 0);
+  //
+  let lines = buf.chunks_exact_mut(stride).map(|line| &mut line[..1 + used_bytes_per_row]);
+  unfilter_lines_with(None, lines, ReconFns::<BYTES_PER_PIXEL>::select);
+  used_bytes_per_row * (buf.len() / stride)
+}
+
+/// As [`unfilter_lines`], but takes the whole buffer and splits it into lines
+/// for you.
+///
+/// * `row_len` is the length of each line *including* the filter byte (the same
+///   as the `chunk_size` you'd pass to `chunks_exact_mut` for
+///   [`unfilter_lines`]).
+///
+/// A `row_len` of 0 can only describe an image with no data at all, so in that
+/// case this does nothing. To get an error instead of a panic for a bad
+/// `row_len`, use [`try_unfilter_flat`].
+///
+/// ## Panics
+/// * As [`unfilter_lines`]
+/// * `assert_eq!(buf.len() % row_len, 0);` (when `row_len` isn't 0)
+/// * If `row_len` is 0 and `buf` isn't empty.
+#[track_caller]
+#[inline]
+#[must_use = "the number of reconstructed bytes should be checked"]
+pub fn unfilter_flat<const BYTES_PER_PIXEL: usize>(buf: &mut [u8], row_len: usize) -> usize {
+  assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+  if row_len == 0 {
+    assert!(buf.is_empty(), "row_len is 0 but buf has data");
+    return 0;
+  }
+  assert_eq!(buf.len() % row_len, 0);
+  //
+  unfilter_lines::<BYTES_PER_PIXEL>(buf.chunks_exact_mut(row_len))
+}
+
+/// As [`unfilter_flat`], but a `buf` that isn't a whole number of lines is an
+/// error instead of a panic, and so is an invalid filter type.
+///
+/// This catches a `row_len` that's off by one (usually from leaving out the
+/// filter byte) before anything is changed, where [`unfilter_lines`] with
+/// `chunks_exact_mut` would quietly skip the partial line at the end. The
+/// errors are:
+///
+/// * [`options::UnfilterError::BufferLength`]: `buf.len()` isn't a multiple of
+///   `row_len` (or `row_len` is 0 and `buf` isn't empty). None of the lines are
+///   changed.
+/// * [`options::UnfilterError::InvalidFilterType`]: as [`try_unfilter_lines`],
+///   every line is still unfiltered.
+///
+/// On success this returns the number of reconstructed bytes, the same as
+/// [`unfilter_flat`].
+///
+/// ## Panics
+/// * As [`unfilter_lines`]
+#[track_caller]
+#[inline]
+pub fn try_unfilter_flat<const BYTES_PER_PIXEL: usize>(
+  buf: &mut [u8], row_len: usize,
+) -> Result<usize, options::UnfilterError> {
+  assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+  let error = options::UnfilterError::BufferLength { len: buf.len(), line_len: row_len };
+  if row_len == 0 {
+    return if buf.is_empty() { Ok(0) } else { Err(error) };
+  }
+  if !buf.len().is_multiple_of(row_len) {
+    return Err(error);
+  }
+  //
+  try_unfilter_lines::<BYTES_PER_PIXEL>(buf.chunks_exact_mut(row_len))
+}
+
+/// Unfilters several images that all have the same line length.
+///
+/// Each image is its own independent image, with its own top line, but the
+/// reconstruction functions are only selected once for all of them. This
+/// helps when you have many small images, such as the frames of a sprite
+/// atlas.
+///
+/// * `row_len` is the length of each line *including* the filter byte (the same
+///   as the `chunk_size` you'd pass to `chunks_exact_mut` for
+///   [`unfilter_lines`]).
+///
+/// A `row_len` of 0 can only describe images with no data at all, so in that
+/// case this does nothing.
+///
+/// ## Panics
+/// * As [`unfilter_lines`]
+/// * If `row_len` is 0 and any image isn't empty.
+#[track_caller]
+#[inline]
+pub fn unfilter_many<const BYTES_PER_PIXEL: usize>(images: &mut [&mut [u8]], row_len: usize) {
+  assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+  if row_len == 0 {
+    assert!(images.iter().all(|image| image.is_empty()), "row_len is 0 but an image has data");
+    return;
+  }
+  let fns = ReconFns::<BYTES_PER_PIXEL>::select();
+  images
+    .iter_mut()
+    .for_each(|image| unfilter_lines_with(None, image.chunks_exact_mut(row_len), || fns));
+}
+
+/// Unfilters the `lines` in place, and also writes the reconstructed image to
+/// `dst` transposed.
+///
+/// In `dst` the pixel at `(x, y)` of the image goes to pixel `x * height + y`,
+/// so each *column* of the image becomes a row of `dst`, with no filter bytes.
+///
+/// The work is done in bands of 16 lines: each band is unfiltered and then
+/// immediately copied out while it's still in the cache. Within a band we go
+/// one column at a time, so each read is from the same 16 cache lines as the
+/// column before it, and each write is 16 contiguous pixels of `dst`. A plain
+/// double loop over the whole image would instead write every output pixel to
+/// a different cache line.
+///
+/// ## Panics
+/// * As [`unfilter_lines`]
+/// * `assert_eq!(lines.len(), height);`
+/// * If any line isn't `1 + width * BYTES_PER_PIXEL` bytes.
+/// * `assert_eq!(dst.len(), width * height * BYTES_PER_PIXEL);`
+#[track_caller]
+pub fn unfilter_then_transpose<const BYTES_PER_PIXEL: usize>(
+  lines: ChunksExactMut<'_, u8>, dst: &mut [u8], width: usize, height: usize,
+) {
+  /// The number of lines in each band.
+  const BAND: usize = 16;
+  assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+  assert_eq!(lines.len(), height);
+  assert_eq!(dst.len(), width * height * BYTES_PER_PIXEL);
+  //
+  let mut rows: Vec<&mut [u8]> = lines.collect();
+  rows.iter().for_each(|row| assert_eq!(row.len(), 1 + width * BYTES_PER_PIXEL));
+  let fns = ReconFns::<BYTES_PER_PIXEL>::select();
+  for band_start in (0..height).step_by(BAND) {
+    let band_end = (band_start + BAND).min(height);
+    let (done, rest) = rows.split_at_mut(band_start);
+    let band = &mut rest[..band_end - band_start];
+    let previous: Option<&[u8]> = done.last().map(|row| &row[1..]);
+    unfilter_lines_with(previous, band.iter_mut().map(|row| &mut **row), || fns);
+    for x in 0..width {
+      let dst_column = &mut dst[(x * height + band_start) * BYTES_PER_PIXEL..];
+      band.iter().zip(dst_column.chunks_exact_mut(BYTES_PER_PIXEL)).for_each(|(row, out)| {
+        out.copy_from_slice(&row[1 + x * BYTES_PER_PIXEL..][..BYTES_PER_PIXEL])
+      });
+    }
+  }
+}
+
+/// Reports which backend [`unfilter_lines`] will use for a given bytes per
+/// pixel.
+///
+/// The report is based on the CPU features detected at runtime, so it can
+/// differ between machines running the same binary.
+///
+/// ## Panics
+/// * If `bytes_per_pixel` isn't in [`SUPPORTED_BYTES_PER_PIXEL`].
+#[track_caller]
+#[must_use]
+pub fn selected_backends(bytes_per_pixel: usize) -> BackendReport {
+  dispatch_bpp!(bytes_per_pixel, |N| ReconFns::<N>::select().report)
+}
+
+/// As [`selected_backends`], but for other [`DispatchThresholds`].
+///
+/// ## Panics
+/// * If `bytes_per_pixel` isn't in [`SUPPORTED_BYTES_PER_PIXEL`].
+#[track_caller]
+#[must_use]
+pub fn selected_backends_with(
+  bytes_per_pixel: usize, thresholds: DispatchThresholds,
+) -> BackendReport {
+  dispatch_bpp!(bytes_per_pixel, |N| ReconFns::<N>::select_with(thresholds).report)
+}
+
+/// An implementation of the reconstruction functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backend {
+  /// The [`fallbacks`] module.
+  Fallback,
+  /// The `sse2` module.
+  Sse2,
+  /// The `sse4_1` module.
+  Sse4_1,
+  /// The `avx2` module.
+  ///
+  /// It only has functions for some filter types and widths, and asking for
+  /// it by name uses `sse4_1` for the rest, so this needs both features.
+  Avx2,
+  /// The `avx512` module.
+  ///
+  /// Like [`Backend::Avx2`], it only has some of the functions, so this needs
+  /// both `avx512bw` and `sse4_1`.
+  Avx512,
+  /// The `neon` module.
+  Neon,
+  /// The `portable` module, which is only available with the `portable_simd`
+  /// feature.
+  Portable,
+}
+
+/// Which [`Backend`] is used for each filter type.
+///
+/// See [`selected_backends`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BackendReport {
+  /// Filter type 1, on all lines.
+  pub sub: Backend,
+  /// Filter type 2.
+  pub up: Backend,
+  /// Filter type 3, below the top line.
+  pub average: Backend,
+  /// Filter type 3, on the top line.
+  pub average_top: Backend,
+  /// Filter type 4, below the top line (the top line uses `sub`).
+  pub paeth: Backend,
+}
+impl BackendReport {
+  /// If every filter type uses [`Backend::Fallback`]
+  #[inline]
+  #[must_use]
+  pub fn all_fallbacks(&self) -> bool {
+    [self.sub, self.up, self.average, self.average_top, self.paeth]
+      .iter()
+      .all(|backend| *backend == Backend::Fallback)
+  }
+}
+
+/// The reconstruction functions that have been selected for the current CPU.
+#[derive(Clone, Copy)]
+struct ReconFns<const BYTES_PER_PIXEL: usize> {
+  sub: unsafe fn(&mut [u8]),
+  up: unsafe fn(&mut [u8], &[u8]),
+  average: unsafe fn(&mut [u8], &[u8]),
+  /// "average" with a [`PreviousRow::Zero`]. This is kept apart from `average`
+  /// (rather than passing `average` a row of zeroes) because not loading the
+  /// previous row is what makes [`PreviousRow::skip_if_zero`] pay off.
+  average_top: unsafe fn(&mut [u8]),
+  paeth: unsafe fn(&mut [u8], &[u8]),
+  /// Where each function above came from.
+  report: BackendReport,
+}
+impl<const BYTES_PER_PIXEL: usize> ReconFns<BYTES_PER_PIXEL> {
+  /// Picks the best functions based on the bytes per pixel and the CPU
+  /// features available at runtime.
+  #[inline]
+  fn select() -> Self {
+    Self::select_with(DispatchThresholds::DEFAULT)
+  }
+
+  /// As [`select`](Self::select), with other thresholds.
+  #[inline]
+  #[allow(unused_mut, unused_variables)]
+  fn select_with(thresholds: DispatchThresholds) -> Self {
+    let mut sub: unsafe fn(&mut [u8]) = fallbacks::recon_sub::<BYTES_PER_PIXEL>;
+    let mut up: unsafe fn(&mut [u8], &[u8]) = fallbacks::recon_up;
+    let mut average: unsafe fn(&mut [u8], &[u8]) = recon_average_fallback::<BYTES_PER_PIXEL>;
+    let mut average_top: unsafe fn(&mut [u8]) = fallbacks::recon_average_top::<BYTES_PER_PIXEL>;
+    let mut paeth: unsafe fn(&mut [u8], &[u8]) = recon_paeth_fallback::<BYTES_PER_PIXEL>;
+    let mut report = BackendReport {
+      sub: Backend::Fallback,
+      up: Backend::Fallback,
+      average: Backend::Fallback,
+      average_top: Backend::Fallback,
+      paeth: Backend::Fallback,
+    };
+    #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), not(feature = "no-simd")))]
+    {
+      let cpu::Features {
+        sse4_1: has_sse4_1,
+        sse2: has_sse2,
+        avx2: has_avx2,
+        avx512bw: has_avx512bw,
+        ..
+      } = cpu::features();
+      let DispatchThresholds { sub: min_sub, average: min_average, paeth: min_paeth } = thresholds;
+      // The SIMD average only wins at ByPP 8. Below that the fallback is faster
+      // (for `average_top` on a 4096 byte row, ByPP 1: 2.8us vs 11.5us, ByPP 3:
+      // 1.6us vs 3.9us, ByPP 4: 2.1us vs 2.9us, ByPP 8: 2.1us vs 1.4us).
+      if BYTES_PER_PIXEL >= min_average && has_sse4_1 {
+        average = sse4_1::recon_average::<BYTES_PER_PIXEL>;
+        average_top = sse4_1::recon_average_top::<BYTES_PER_PIXEL>;
+        report.average = Backend::Sse4_1;
+        report.average_top = Backend::Sse4_1;
+      } else if BYTES_PER_PIXEL >= min_average && has_sse2 {
+        average = sse2::recon_average::<BYTES_PER_PIXEL>;
+        average_top = sse2::recon_average_top::<BYTES_PER_PIXEL>;
+        report.average = Backend::Sse2;
+        report.average_top = Backend::Sse2;
+      }
+      if BYTES_PER_PIXEL == 8 && BYTES_PER_PIXEL >= min_average && has_avx2 {
+        // On a 4096 byte row, 0.60us vs 1.58us for `sse4_1` (see
+        // `bench_average_top_*`).
+        average_top = avx2::recon_average_top_8;
+        report.average_top = Backend::Avx2;
+      }
+      if BYTES_PER_PIXEL >= min_paeth && has_sse4_1 {
+        paeth = sse4_1::recon_paeth::<BYTES_PER_PIXEL>;
+        report.paeth = Backend::Sse4_1;
+      } else if BYTES_PER_PIXEL >= min_paeth && has_sse2 {
+        paeth = sse2::recon_paeth::<BYTES_PER_PIXEL>;
+        report.paeth = Backend::Sse2;
+      }
+      if BYTES_PER_PIXEL < min_sub {
+        // keep the fallback
+      } else if BYTES_PER_PIXEL == 1 && has_avx2 {
+        // On a 4096 byte row, 260ns vs 434ns for `sse2::recon_sub_prefix_runs`.
+        // That one still wins on a row of all zeroes (181ns vs 261ns), but
+        // that's a smaller gap on a row that's cheap either way (see
+        // `bench_sub_1_*`).
+        sub = avx2::recon_sub_prefix;
+        report.sub = Backend::Avx2;
+      } else if (BYTES_PER_PIXEL == 1 || BYTES_PER_PIXEL == 2) && has_sse2 {
+        // Skipping zero blocks is free at these widths, but not at ByPP 4 (see
+        // `sse2::recon_sub_prefix_runs`).
+        sub = sse2::recon_sub_prefix_runs::<BYTES_PER_PIXEL>;
+        report.sub = Backend::Sse2;
+      } else if BYTES_PER_PIXEL == 4 && has_sse2 {
+        sub = sse2::recon_sub_prefix::<BYTES_PER_PIXEL>;
+        report.sub = Backend::Sse2;
+      } else if matches!(BYTES_PER_PIXEL, 3 | 5 | 7) && has_sse4_1 {
+        // On a 4200 byte row: ByPP 3 0.41us (vs 0.84us for the fallback), ByPP
+        // 5 0.32us (vs 0.62us for `sse2::recon_sub`), ByPP 7 0.39us (vs
+        // 1.1us). At ByPP 6 it ties with `sse2::recon_sub`.
+        sub = sse4_1::recon_sub_odd::<BYTES_PER_PIXEL>;
+        report.sub = Backend::Sse4_1;
+      } else if BYTES_PER_PIXEL >= 4 && has_sse2 {
+        sub = sse2::recon_sub::<BYTES_PER_PIXEL>;
+        report.sub = Backend::Sse2;
+      }
+      // "up" is the same at every ByPP, so unlike the others it has no width
+      // condition. It's limited by memory, so the 16-byte adds only win by a
+      // little (on a 4096 byte row, 93ns vs 106ns for the fallback, see
+      // `bench_up_*`), and `sse4_1` has nothing better for it than `sse2`. The
+      // 64-byte adds of `avx512` win by a bit more (66ns vs 75ns for `sse2`, in
+      // a later run where the fallback was 89ns).
+      if has_avx512bw {
+        up = avx512::recon_up;
+        report.up = Backend::Avx512;
+      } else if has_sse2 {
+        up = sse2::recon_up;
+        report.up = Backend::Sse2;
+      }
+    }
+    #[cfg(all(target_arch = "aarch64", not(feature = "no-simd")))]
+    {
+      let has_neon = cpu::features().neon;
+      let DispatchThresholds { sub: min_sub, average: min_average, paeth: min_paeth } = thresholds;
       // Note(Lokathor): I'm not sure why, but at ByPP==3 the scalar versions
       // actually work faster than the Neon versions even though Neon runs
       // better at ByPP==2. Might be something to do with register+op
       // scheduling, or something like that.
-      paeth = neon::recon_paeth::<BYTES_PER_PIXEL>;
-      sub = neon::recon_sub::<BYTES_PER_PIXEL>;
-      average = neon::recon_average::<BYTES_PER_PIXEL>;
-      average_top = neon::recon_average_top::<BYTES_PER_PIXEL>;
+      if BYTES_PER_PIXEL != 3 && BYTES_PER_PIXEL >= min_paeth && has_neon {
+        paeth = neon::recon_paeth::<BYTES_PER_PIXEL>;
+        report.paeth = Backend::Neon;
+      }
+      if BYTES_PER_PIXEL != 3 && BYTES_PER_PIXEL >= min_sub && has_neon {
+        sub = neon::recon_sub::<BYTES_PER_PIXEL>;
+        report.sub = Backend::Neon;
+      }
+      // `neon::recon_average` is correct at ByPP 1 and 3, and
+      // `neon::recon_sub_prefix` at ByPP 1, but they aren't picked there until
+      // `benches/neon_bench.rs` has been run on aarch64 hardware.
+      if BYTES_PER_PIXEL != 3 && BYTES_PER_PIXEL >= min_average && has_neon {
+        average = neon::recon_average::<BYTES_PER_PIXEL>;
+        average_top = neon::recon_average_top::<BYTES_PER_PIXEL>;
+        report.average = Backend::Neon;
+        report.average_top = Backend::Neon;
+      }
+      if has_neon {
+        up = neon::recon_up;
+        report.up = Backend::Neon;
+      }
+    }
+    #[cfg(all(feature = "portable_simd", not(feature = "no-simd")))]
+    {
+      // The portable functions fill in wherever the blocks above left a
+      // fallback. On a 4200 byte row (x86_64), `sub` at ByPP 4 is 0.49us (vs
+      // 1.7us for the fallback, 0.50us for `sse4_1`), ByPP 8 0.24us (vs 1.4us,
+      // 0.23us). `paeth` at ByPP 3 is 10.2us (vs 49us, 9.9us), ByPP 4 7.3us
+      // (vs 12us, 6.4us), ByPP 8 3.6us (vs 11us, 3.2us). Below those widths the
+      // fallbacks win, so they're the least that the thresholds can go down to.
+      if BYTES_PER_PIXEL >= thresholds.sub.max(4) && report.sub == Backend::Fallback {
+        sub = portable::recon_sub::<BYTES_PER_PIXEL>;
+        report.sub = Backend::Portable;
+      }
+      if BYTES_PER_PIXEL >= thresholds.paeth.max(3) && report.paeth == Backend::Fallback {
+        paeth = portable::recon_paeth::<BYTES_PER_PIXEL>;
+        report.paeth = Backend::Portable;
+      }
+      if report.up == Backend::Fallback {
+        up = portable::recon_up;
+        report.up = Backend::Portable;
+      }
+      // Average is the exception: it also beats `sse4_1`, so it replaces that
+      // too (ByPP 4: 1.7us vs 3.4us for the fallback and 3.5us for `sse4_1`,
+      // ByPP 8: 0.82us vs 3.2us and 1.8us). At ByPP 8 it also replaces the
+      // `avx2` average_top, which it ties with (0.65us vs 0.61us). It hasn't
+      // been measured against `neon`, so that's left alone.
+      if BYTES_PER_PIXEL >= thresholds.average.max(4) && report.average != Backend::Neon {
+        average = portable::recon_average::<BYTES_PER_PIXEL>;
+        average_top = portable::recon_average_top::<BYTES_PER_PIXEL>;
+        report.average = Backend::Portable;
+        report.average_top = Backend::Portable;
+      }
     }
-    if has_neon {
-      up = neon::recon_up;
+
+    Self { sub, up, average, average_top, paeth, report }
+  }
+
+  /// Every function from one specific backend, or `None` if that backend isn't
+  /// available on the current CPU.
+  ///
+  /// This ignores the `no-simd` feature, since asking for a backend by name is
+  /// like calling its module directly.
+  ///
+  /// [`Backend::Avx2`] and [`Backend::Avx512`] are the `sse4_1` functions with
+  /// their own ones swapped in where there are any, and the report says which
+  /// is which.
+  #[inline]
+  fn for_backend(backend: Backend) -> Option<Self> {
+    let report = BackendReport {
+      sub: backend,
+      up: backend,
+      average: backend,
+      average_top: backend,
+      paeth: backend,
+    };
+    match backend {
+      Backend::Fallback => Some(Self {
+        sub: fallbacks::recon_sub::<BYTES_PER_PIXEL>,
+        up: fallbacks::recon_up,
+        average: recon_average_fallback::<BYTES_PER_PIXEL>,
+        average_top: fallbacks::recon_average_top::<BYTES_PER_PIXEL>,
+        paeth: recon_paeth_fallback::<BYTES_PER_PIXEL>,
+        report,
+      }),
+      #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+      Backend::Sse2 if cpu::features().sse2 => Some(Self {
+        sub: sse2::recon_sub::<BYTES_PER_PIXEL>,
+        up: sse2::recon_up,
+        average: sse2::recon_average::<BYTES_PER_PIXEL>,
+        average_top: sse2::recon_average_top::<BYTES_PER_PIXEL>,
+        paeth: sse2::recon_paeth::<BYTES_PER_PIXEL>,
+        report,
+      }),
+      #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+      Backend::Sse4_1 if cpu::features().sse4_1 => Some(Self {
+        sub: sse4_1::recon_sub::<BYTES_PER_PIXEL>,
+        up: sse4_1::recon_up,
+        average: sse4_1::recon_average::<BYTES_PER_PIXEL>,
+        average_top: sse4_1::recon_average_top::<BYTES_PER_PIXEL>,
+        paeth: sse4_1::recon_paeth::<BYTES_PER_PIXEL>,
+        report,
+      }),
+      #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+      Backend::Avx2 if cpu::features().avx2 && cpu::features().sse4_1 => {
+        let mut fns = Self::for_backend(Backend::Sse4_1)?;
+        if BYTES_PER_PIXEL == 1 {
+          fns.sub = avx2::recon_sub_prefix;
+          fns.report.sub = Backend::Avx2;
+        }
+        if BYTES_PER_PIXEL == 8 {
+          fns.average_top = avx2::recon_average_top_8;
+          fns.report.average_top = Backend::Avx2;
+        }
+        Some(fns)
+      }
+      #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+      Backend::Avx512 if cpu::features().avx512bw && cpu::features().sse4_1 => {
+        let mut fns = Self::for_backend(Backend::Sse4_1)?;
+        fns.up = avx512::recon_up;
+        fns.report.up = Backend::Avx512;
+        Some(fns)
+      }
+      #[cfg(target_arch = "aarch64")]
+      Backend::Neon if cpu::features().neon => Some(Self {
+        sub: neon::recon_sub::<BYTES_PER_PIXEL>,
+        up: neon::recon_up,
+        average: neon::recon_average::<BYTES_PER_PIXEL>,
+        average_top: neon::recon_average_top::<BYTES_PER_PIXEL>,
+        paeth: neon::recon_paeth::<BYTES_PER_PIXEL>,
+        report,
+      }),
+      #[cfg(feature = "portable_simd")]
+      Backend::Portable => Some(Self {
+        sub: portable::recon_sub::<BYTES_PER_PIXEL>,
+        up: portable::recon_up,
+        average: portable::recon_average::<BYTES_PER_PIXEL>,
+        average_top: portable::recon_average_top::<BYTES_PER_PIXEL>,
+        paeth: portable::recon_paeth::<BYTES_PER_PIXEL>,
+        report,
+      }),
+      _ => None,
     }
   }
+}
 
+/// The fallback paeth function that's fastest for the bytes per pixel.
+///
+/// The branchless version is faster at small widths (on a 4096 byte row,
+/// ByPP 1: 13.9us vs 16.6us, ByPP 4: 8.4us vs 9.2us) but not at ByPP 8. At
+/// ByPP 4 the four channel version is faster again (6.8us vs 10.0us for the
+/// branchless version on the same machine). At ByPP 1 the lookup table version
+/// is faster still (8.3us), but not at ByPP 2 (8.4us for both) or ByPP 3
+/// (10.3us vs 8.4us).
+#[inline(always)]
+fn recon_paeth_fallback<const BYTES_PER_PIXEL: usize>(
+  filtered_row: &mut [u8], previous_row: &[u8],
+) {
+  if BYTES_PER_PIXEL == 1 {
+    fallbacks::recon_paeth_table::<BYTES_PER_PIXEL>(filtered_row, previous_row)
+  } else if BYTES_PER_PIXEL == 4 {
+    fallbacks::recon_paeth_rgba8(filtered_row, previous_row)
+  } else if BYTES_PER_PIXEL <= 4 {
+    fallbacks::recon_paeth_branchless::<BYTES_PER_PIXEL>(filtered_row, previous_row)
+  } else {
+    fallbacks::recon_paeth::<BYTES_PER_PIXEL>(filtered_row, previous_row)
+  }
+}
+
+/// The fallback average function that's fastest for the bytes per pixel.
+///
+/// At ByPP 4 the four channel version is faster (2.45us vs 2.84us on a 4096
+/// byte row).
+#[inline(always)]
+fn recon_average_fallback<const BYTES_PER_PIXEL: usize>(
+  filtered_row: &mut [u8], previous_row: &[u8],
+) {
+  if BYTES_PER_PIXEL == 4 {
+    fallbacks::recon_average_swar_rgba8(filtered_row, previous_row)
+  } else {
+    fallbacks::recon_average::<BYTES_PER_PIXEL>(filtered_row, previous_row)
+  }
+}
+
+/// Unfilters all `lines`, only calling `select` if any line actually needs
+/// reconstruction.
+///
+/// * `previous` is the line before the first of `lines`. With `None` the first
+///   of `lines` is the top line of the image.
+/// * Every line must have at least the filter byte (`ChunksExactMut` already
+///   ensures this).
+#[inline]
+fn unfilter_lines_with<'a, const BYTES_PER_PIXEL: usize>(
+  previous: Option<&'a [u8]>, lines: impl Iterator<Item = &'a mut [u8]>,
+  select: impl FnOnce() -> ReconFns<BYTES_PER_PIXEL>,
+) {
   // Won't panic: `chunk_size` is always non-zero (ChunksExactMut invariant).
-  let mut lines = lines.map(|line| line.split_first_mut().unwrap());
+  // The unwrap is a well predicted branch, and `unwrap_unchecked` measured no
+  // faster even with 2 byte lines (see `bench_tiny_rows_*`).
+  let lines = lines.map(|line| line.split_first_mut().unwrap());
+  unfilter_split_with(previous, lines, select, |_, _| ())
+}
 
-  // most filters run differently or not at all on the top line.
-  let mut previous: &[u8] = if let Some((filter, line)) = lines.next() {
-    match filter {
-      1 => unsafe { sub(line) },
-      2 => (),
-      3 => unsafe { average_top(line) },
-      4 => unsafe { sub(line) },
-      _ => (),
+/// As [`unfilter_lines_with`], but each line is given as its filter byte and
+/// its data separately.
+///
+/// * `hook` is called with the index and data of each line, once it's been
+///   reconstructed.
+#[inline]
+fn unfilter_split_with<'a, const BYTES_PER_PIXEL: usize>(
+  mut previous: Option<&'a [u8]>, mut lines: impl Iterator<Item = (&'a mut u8, &'a mut [u8])>,
+  select: impl FnOnce() -> ReconFns<BYTES_PER_PIXEL>, mut hook: impl FnMut(usize, &[u8]),
+) {
+  // Lines with filter type 0 need no work at all (and their filter byte is
+  // already 0), so we skip over any leading run of them before doing any
+  // feature detection. An image that's filter type 0 on every line never
+  // reaches the dispatch logic at all.
+  let mut y = 0;
+  let (filter, line) = loop {
+    match lines.next() {
+      Some((&mut 0, line)) => {
+        hook(y, line);
+        previous = Some(line);
+        y += 1;
+      }
+      Some(filter_and_line) => break filter_and_line,
+      None => return,
     }
+  };
+
+  // A zero-width image has only filter bytes on each line, so there's nothing
+  // to reconstruct. We still clear the filter bytes, like we would for any
+  // other image, but we don't need to pick any functions to do it.
+  if line.is_empty() {
     *filter = 0;
-    line
-  } else {
+    hook(y, line);
+    lines.for_each(|(filter, line)| {
+      *filter = 0;
+      y += 1;
+      hook(y, line);
+    });
     return;
-  };
+  }
 
-  // now handle all other lines
-  lines.for_each(|(filter, line)| {
-    match filter {
-      1 => unsafe { sub(line) },
-      2 => unsafe { up(line, previous) },
-      3 => unsafe { average(line, previous) },
-      4 => unsafe { paeth(line, previous) },
-      _ => (),
+  let ReconFns { sub, up, average, average_top, paeth, report } = select();
+  #[cfg(feature = "trace")]
+  trace::emit(BYTES_PER_PIXEL, report);
+  #[cfg(feature = "diagnostics")]
+  diagnostics::selected(report);
+  if report.all_fallbacks() {
+    // When we didn't pick any SIMD functions we call the fallbacks directly
+    // instead of through `unsafe fn` pointers, which lets them be inlined and
+    // auto-vectorized along with the rest of the loop.
+    unfilter_remaining_lines::<BYTES_PER_PIXEL>(
+      y,
+      previous,
+      filter,
+      line,
+      lines,
+      fallbacks::recon_sub::<BYTES_PER_PIXEL>,
+      fallbacks::recon_up,
+      recon_average_fallback::<BYTES_PER_PIXEL>,
+      fallbacks::recon_average_top::<BYTES_PER_PIXEL>,
+      recon_paeth_fallback::<BYTES_PER_PIXEL>,
+      hook,
+    )
+  } else {
+    // Calling through the pointers costs one indirect call per line. We tried
+    // a copy of this loop compiled with `#[target_feature(enable = "sse4.1")]`
+    // that calls the default `sse2`/`sse4_1` choices directly, so they could be
+    // inlined. It made no difference outside of the run to run noise (x86_64,
+    // random filter types): 1024x256 ByPP 4 was 391-432us vs 439-442us, ByPP 3
+    // 387-410us vs 383-409us, and 200KB of 4 pixel wide ByPP 4 lines 203-215us
+    // vs 202-206us. A line is always much more work than the call, so this
+    // stays as one loop for every backend.
+    //
+    // Safety: each function pointer is either a fallback or a SIMD function
+    // that we've checked the CPU features for.
+    unfilter_remaining_lines::<BYTES_PER_PIXEL>(
+      y,
+      previous,
+      filter,
+      line,
+      lines,
+      |line| unsafe { sub(line) },
+      |line, previous| unsafe { up(line, previous) },
+      |line, previous| unsafe { average(line, previous) },
+      |line| unsafe { average_top(line) },
+      |line, previous| unsafe { paeth(line, previous) },
+      hook,
+    )
+  }
+}
+
+/// The row above the line being unfiltered.
+///
+/// The top line of an image acts as if the row above it is all 0. With that,
+/// "up" does nothing, "average" only uses half of `a` (which is what the
+/// `average_top` functions do), and "paeth" always predicts `a` (so it's the
+/// same as "sub"). Instead of passing around an actual row of zeroes we use
+/// `Zero`, and the `match` in [`unfilter_remaining_lines`] is the one place
+/// that picks the right function for each case.
+#[derive(Debug, Clone, Copy)]
+enum PreviousRow<'a> {
+  /// The line is the top line of the image.
+  Zero,
+  /// The reconstructed row above the line.
+  Data(&'a [u8]),
+}
+impl<'a> PreviousRow<'a> {
+  #[inline(always)]
+  fn new(previous: Option<&'a [u8]>) -> Self {
+    match previous {
+      Some(previous) => Self::Data(previous),
+      None => Self::Zero,
     }
-    *filter = 0;
+  }
+  /// Turns a row of all zeroes into `Zero` when that's cheaper for `filter`.
+  ///
+  /// Long runs of zero rows are common (fully transparent or black areas). On
+  /// those rows "average" is the same as `average_top`, which doesn't need to
+  /// load the previous row at all, and "paeth" is the same as "sub", which
+  /// doesn't need the whole predictor. Checking the row first is much cheaper
+  /// than either filter, and when the row isn't zero the check usually stops
+  /// within the first 64 bytes.
+  ///
+  /// With every other row zero (1024 pixels wide, 256 lines), bpp 4 "average"
+  /// went from 495us to 291us and bpp 4 "paeth" from 656us to 72us. Images
+  /// with no zero rows were unchanged.
+  #[inline(always)]
+  fn skip_if_zero(self, filter: u8) -> Self {
+    match self {
+      Self::Data(previous) if matches!(filter, 3 | 4) && util::is_all_zero(previous) => Self::Zero,
+      other => other,
+    }
+  }
+  #[inline(always)]
+  fn as_option(self) -> Option<&'a [u8]> {
+    match self {
+      Self::Data(previous) => Some(previous),
+      Self::Zero => None,
+    }
+  }
+}
+
+/// Unfilters the first line that needs work, and then all lines after it.
+///
+/// * `y` is the index of `line` within the image.
+/// * `previous` is the line before `line`, if any. When there isn't one then
+///   `line` is the top line of the image.
+#[inline(always)]
+#[allow(clippy::too_many_arguments)]
+fn unfilter_remaining_lines<'a, const BYTES_PER_PIXEL: usize>(
+  mut y: usize, previous: Option<&'a [u8]>, filter: &'a mut u8, line: &'a mut [u8],
+  lines: impl Iterator<Item = (&'a mut u8, &'a mut [u8])>, sub: impl Fn(&mut [u8]),
+  up: impl Fn(&mut [u8], &[u8]), average: impl Fn(&mut [u8], &[u8]),
+  average_top: impl Fn(&mut [u8]), paeth: impl Fn(&mut [u8], &[u8]),
+  mut hook: impl FnMut(usize, &[u8]),
+) {
+  let mut unfilter_line =
+    |y: usize, filter: &mut u8, line: &mut [u8], previous: PreviousRow<'_>| {
+      let previous = previous.skip_if_zero(*filter);
+      verify_line::<BYTES_PER_PIXEL>(y, *filter, line, previous.as_option(), |line| {
+        let f = *filter;
+        match (f, previous) {
+          // On a one pixel wide line "sub" adds 0 to the only pixel, so there's
+          // nothing to do. For a 1x10000 bpp 4 column of "sub" lines this took
+          // the whole image from 48us to 33us.
+          (1, _) | (4, PreviousRow::Zero) if line.len() > BYTES_PER_PIXEL => {
+            run_kernel(y, f, Kernel::Sub, || sub(line))
+          }
+          (2, PreviousRow::Data(b)) => run_kernel(y, f, Kernel::Up, || up(line, b)),
+          (3, PreviousRow::Data(b)) => run_kernel(y, f, Kernel::Average, || average(line, b)),
+          (3, PreviousRow::Zero) => run_kernel(y, f, Kernel::AverageTop, || average_top(line)),
+          (4, PreviousRow::Data(b)) => run_kernel(y, f, Kernel::Paeth, || paeth(line, b)),
+          // filter type 0, "up" on the top line, "sub" on a one pixel wide
+          // line, and invalid filter types.
+          _ => (),
+        }
+      });
+      *filter = 0;
+      hook(y, line);
+    };
+
+  unfilter_line(y, filter, line, PreviousRow::new(previous));
+  // `previous` and the next `line` are usually right next to each other in the
+  // caller's buffer. That's fine because each is its own `&mut` chunk handed
+  // out by the iterator (chunks never overlap), and `previous` only ever
+  // reborrows a line once we're done writing to it. Don't "simplify" this into
+  // indexing back into the whole buffer or doing pointer math to find the row
+  // above, since that would make a shared borrow overlap the `&mut` we're
+  // writing through. `test_unfilter_lines_adjacent_rows` covers this case and
+  // is small enough to run under Miri.
+  let mut previous: &[u8] = line;
+  // This is always row by row, even for an image that's all "up". We tried
+  // going down the image in vertical strips of 64 or 128 bytes instead (keeping
+  // each strip's previous row in registers), but the strided access defeats
+  // the prefetcher: on a 4096x2048 byte image that took 1.7-1.9ms, against
+  // 1.0ms row by row, and it was still slower with 64KiB rows.
+  //
+  // Each line also does its own loads of `previous`, even when an "average"
+  // line is followed by a "paeth" line. There's no load for the two to share:
+  // the second line's `previous` is the first line's *output*, not the same
+  // row. Keeping that output widened to `i16` for the next line would mean
+  // writing twice the row's bytes, to save a widening that takes 0.13us on a
+  // 4096 byte row (x86_64, `pmovzxbw`), next to 5.1us for the `sse4_1` paeth
+  // at ByPP 4 and 2.5us for the average.
+  lines.for_each(|(filter, line)| {
+    y += 1;
+    unfilter_line(y, filter, line, PreviousRow::Data(previous));
     previous = line;
   });
 }
+
+#[cfg(test)]
+mod tests {
+  #[test]
+  fn test_dispatch_bpp() {
+    fn width<const BYTES_PER_PIXEL: usize>() -> usize {
+      BYTES_PER_PIXEL
+    }
+    for bytes_per_pixel in 1..=8 {
+      assert_eq!(dispatch_bpp!(bytes_per_pixel, |N| width::<N>()), bytes_per_pixel);
+      assert_eq!(dispatch_bpp!(bytes_per_pixel, |N| N * 2), bytes_per_pixel * 2);
+    }
+  }
+
+  #[test]
+  fn test_dispatch_bpp_matches_supported() {
+    for n in 0..=20 {
+      let dispatched = std::panic::catch_unwind(|| dispatch_bpp!(n, |N| N)).is_ok();
+      assert_eq!(dispatched, super::is_supported_bpp(n), "n: {n}");
+      assert_eq!(dispatched, super::SUPPORTED_BYTES_PER_PIXEL.contains(&n), "n: {n}");
+    }
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_dispatch_bpp_out_of_range() {
+    let bytes_per_pixel = core::hint::black_box(9);
+    let _ = dispatch_bpp!(bytes_per_pixel, |N| N);
+  }
+}
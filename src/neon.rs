@@ -4,21 +4,7 @@
 
 use core::arch::aarch64::*;
 
-#[inline]
-#[must_use]
-fn uint8x8_t_as_mut_slice(m: &mut uint8x8_t) -> &mut [u8] {
-  let data = m as *mut uint8x8_t as *mut u8;
-  let len = core::mem::size_of::<uint8x8_t>();
-  unsafe { core::slice::from_raw_parts_mut(data, len) }
-}
-
-#[inline]
-#[must_use]
-fn int16x8_t_as_mut_slice(m: &mut int16x8_t) -> &mut [i16] {
-  let data = m as *mut int16x8_t as *mut i16;
-  let len = core::mem::size_of::<int16x8_t>();
-  unsafe { core::slice::from_raw_parts_mut(data, len) }
-}
+use crate::simd_bytes::as_mut_lanes;
 
 #[inline]
 #[target_feature(enable = "neon")]
@@ -26,8 +12,8 @@ unsafe fn uint8x8_t_load<const BYTES_PER_PIXEL: usize>(chunk: &[u8]) -> uint8x8_
   if BYTES_PER_PIXEL == 8 {
     vld1_u8(chunk.as_ptr())
   } else {
-    let mut x: uint8x8_t = unsafe { core::mem::zeroed() };
-    uint8x8_t_as_mut_slice(&mut x)[..BYTES_PER_PIXEL].copy_from_slice(chunk);
+    let mut x: uint8x8_t = vdup_n_u8(0);
+    as_mut_lanes::<u8, _>(&mut x)[..BYTES_PER_PIXEL].copy_from_slice(chunk);
     x
   }
 }
@@ -37,7 +23,7 @@ unsafe fn uint8x8_t_store<const BYTES_PER_PIXEL: usize>(chunk: &mut [u8], mut x:
   if BYTES_PER_PIXEL == 8 {
     vst1_u8(chunk.as_mut_ptr(), x)
   } else {
-    chunk.copy_from_slice(&uint8x8_t_as_mut_slice(&mut x)[..BYTES_PER_PIXEL]);
+    chunk.copy_from_slice(&as_mut_lanes::<u8, _>(&mut x)[..BYTES_PER_PIXEL]);
   }
 }
 
@@ -51,25 +37,79 @@ pub unsafe fn recon_sub<const BYTES_PER_PIXEL: usize>(filtered_row: &mut [u8]) {
   assert!(BYTES_PER_PIXEL <= 8);
   debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
   //
-  let mut a: uint8x8_t = unsafe { core::mem::zeroed() };
+  // Note: we always zero vectors with `vdup` rather than `mem::zeroed`, so
+  // there's no unsafe type punning for Miri (or a reader) to worry about.
+  let mut a: uint8x8_t = vdup_n_u8(0);
   filtered_row.chunks_exact_mut(BYTES_PER_PIXEL).for_each(|chunk| {
     let mut x: uint8x8_t = uint8x8_t_load::<BYTES_PER_PIXEL>(chunk);
-    x = unsafe { vadd_u8(x, a) };
+    x = vadd_u8(x, a);
     uint8x8_t_store::<BYTES_PER_PIXEL>(chunk, x);
     a = x;
   })
 }
 
+/// Like [`recon_sub`] at 1 byte per pixel, but using a prefix sum to process
+/// 16 bytes at a time.
+///
+/// Within each 16 byte block, adding the block to itself shifted up by 1 lane
+/// (with `vextq_u8` against zero), then 2, then 4, then 8, gives each lane the
+/// sum of itself and every lane before it. Then the last lane of the previous
+/// block is added to all of them, which is the only step that has to wait on
+/// the block before. Any bytes after the last full block are done one at a
+/// time.
+///
+/// This isn't selected by [`unfilter_lines`](crate::unfilter_lines) yet, see
+/// `benches/neon_bench.rs`.
+///
+/// ## Safety
+/// * The `neon` CPU feature must be available at runtime.
+#[target_feature(enable = "neon")]
+pub unsafe fn recon_sub_prefix(filtered_row: &mut [u8]) {
+  let zero: uint8x16_t = vdupq_n_u8(0);
+  let mut chunks = filtered_row.chunks_exact_mut(16);
+  // the last reconstructed byte, repeated across the whole register.
+  let mut a: uint8x16_t = zero;
+  (&mut chunks).for_each(|chunk| {
+    let mut x: uint8x16_t = vld1q_u8(chunk.as_ptr());
+    x = vaddq_u8(x, vextq_u8::<15>(zero, x));
+    x = vaddq_u8(x, vextq_u8::<14>(zero, x));
+    x = vaddq_u8(x, vextq_u8::<12>(zero, x));
+    x = vaddq_u8(x, vextq_u8::<8>(zero, x));
+    x = vaddq_u8(x, a);
+    vst1q_u8(chunk.as_mut_ptr(), x);
+    a = vdupq_laneq_u8::<15>(x);
+  });
+  let mut a: u8 = vgetq_lane_u8::<0>(a);
+  chunks.into_remainder().iter_mut().for_each(|x| {
+    *x = x.wrapping_add(a);
+    a = *x;
+  });
+}
+
 /// Like [`recon_up_fallback`](super::recon_up_fallback), but specialized to
 /// `neon`.
 ///
+/// This works on 16 bytes at a time, rather than relying on the
+/// auto-vectorizer. Any bytes after the last full 16 are done one at a time.
+///
 /// ## Safety
 /// * The `neon` CPU feature must be available at runtime.
 #[target_feature(enable = "neon")]
 pub unsafe fn recon_up(filtered_row: &mut [u8], previous_row: &[u8]) {
   debug_assert_eq!(filtered_row.len(), previous_row.len());
   //
-  filtered_row.iter_mut().zip(previous_row.iter()).for_each(|(x, b)| *x = x.wrapping_add(*b))
+  let mut x_chunks = filtered_row.chunks_exact_mut(16);
+  let mut b_chunks = previous_row.chunks_exact(16);
+  (&mut x_chunks).zip(&mut b_chunks).for_each(|(x_chunk, b_chunk)| {
+    let x: uint8x16_t = vld1q_u8(x_chunk.as_ptr());
+    let b: uint8x16_t = vld1q_u8(b_chunk.as_ptr());
+    vst1q_u8(x_chunk.as_mut_ptr(), vaddq_u8(x, b));
+  });
+  x_chunks
+    .into_remainder()
+    .iter_mut()
+    .zip(b_chunks.remainder().iter())
+    .for_each(|(x, b)| *x = x.wrapping_add(*b))
 }
 
 /// Like [`recon_average_fallback`](super::recon_average_fallback), but
@@ -80,30 +120,56 @@ pub unsafe fn recon_up(filtered_row: &mut [u8], previous_row: &[u8]) {
 #[target_feature(enable = "neon")]
 pub unsafe fn recon_average<const BYTES_PER_PIXEL: usize>(
   filtered_row: &mut [u8], previous_row: &[u8],
+) {
+  debug_assert_eq!(filtered_row.len(), previous_row.len());
+  //
+  recon_average_rows::<BYTES_PER_PIXEL>(filtered_row, Some(previous_row))
+}
+
+/// The kernel of [`recon_average`] and [`recon_average_top`].
+///
+/// The top line is the same as any other line with `b` as 0, so both use this
+/// one per-pixel step, and only the loop over `previous_row` differs.
+#[inline]
+#[target_feature(enable = "neon")]
+unsafe fn recon_average_rows<const BYTES_PER_PIXEL: usize>(
+  filtered_row: &mut [u8], previous_row: Option<&[u8]>,
 ) {
   assert!(BYTES_PER_PIXEL <= 8);
   debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
-  debug_assert_eq!(filtered_row.len(), previous_row.len());
   //
   // Recon(x) = Filt(x) + floor((Recon(a) + Recon(b)) / 2)
   //
   // * (a + b)/2 has to be done with 9-bit precision
   // * x + ave is done with u8_wrapping
   //
-  let mut a: uint8x8_t = unsafe { core::mem::zeroed() };
-  filtered_row
-    .chunks_exact_mut(BYTES_PER_PIXEL)
-    .zip(previous_row.chunks_exact(BYTES_PER_PIXEL))
-    .for_each(|(x_chunk, b_chunk)| {
-      let mut x: uint8x8_t = uint8x8_t_load::<BYTES_PER_PIXEL>(x_chunk);
-      let b: uint8x8_t = uint8x8_t_load::<BYTES_PER_PIXEL>(b_chunk);
-      {
-        let ab_half = vhadd_u8(a, b);
-        x = unsafe { vadd_u8(x, ab_half) };
-      }
-      uint8x8_t_store::<BYTES_PER_PIXEL>(x_chunk, x);
-      a = x;
-    })
+  // `vhadd_u8` is a halving add: it computes `(a + b) >> 1` with the
+  // intermediate sum kept at 9 bits, so it's exactly the spec's floor for all
+  // inputs (`255 + 255` gives `255`, not `127`). Note that this is *not*
+  // `vrhadd_u8`, which would round up instead. Each `a` is the reconstructed
+  // `x` from the previous pixel, same as the fallback, so this is correct at
+  // every `BYTES_PER_PIXEL`, including 1 and 3. With `b` as 0 it's `a >> 1`,
+  // which is the top line's average.
+  //
+  let mut a: uint8x8_t = vdup_n_u8(0);
+  let mut average_pixel = |x_chunk: &mut [u8], b: uint8x8_t| {
+    let mut x: uint8x8_t = uint8x8_t_load::<BYTES_PER_PIXEL>(x_chunk);
+    {
+      let ab_half = vhadd_u8(a, b);
+      x = vadd_u8(x, ab_half);
+    }
+    uint8x8_t_store::<BYTES_PER_PIXEL>(x_chunk, x);
+    a = x;
+  };
+  let x_chunks = filtered_row.chunks_exact_mut(BYTES_PER_PIXEL);
+  match previous_row {
+    Some(previous_row) => {
+      x_chunks.zip(previous_row.chunks_exact(BYTES_PER_PIXEL)).for_each(|(x_chunk, b_chunk)| {
+        average_pixel(x_chunk, uint8x8_t_load::<BYTES_PER_PIXEL>(b_chunk))
+      })
+    }
+    None => x_chunks.for_each(|x_chunk| average_pixel(x_chunk, vdup_n_u8(0))),
+  }
 }
 
 /// As [`recon_average_top_fallback`](super::recon_average_top_fallback), but
@@ -113,16 +179,7 @@ pub unsafe fn recon_average<const BYTES_PER_PIXEL: usize>(
 /// * The `neon` CPU feature must be available at runtime.
 #[target_feature(enable = "neon")]
 pub unsafe fn recon_average_top<const BYTES_PER_PIXEL: usize>(filtered_row: &mut [u8]) {
-  assert!(BYTES_PER_PIXEL <= 8);
-  debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
-  //
-  let mut a: uint8x8_t = unsafe { core::mem::zeroed() };
-  filtered_row.chunks_exact_mut(BYTES_PER_PIXEL).for_each(|chunk| {
-    let mut x: uint8x8_t = uint8x8_t_load::<BYTES_PER_PIXEL>(chunk);
-    x = unsafe { vadd_u8(x, vshr_n_u8::<1>(a)) };
-    uint8x8_t_store::<BYTES_PER_PIXEL>(chunk, x);
-    a = x;
-  })
+  recon_average_rows::<BYTES_PER_PIXEL>(filtered_row, None)
 }
 
 /// As [`recon_paeth_fallback`](super::recon_paeth_fallback), but
@@ -138,15 +195,15 @@ pub unsafe fn recon_paeth<const BYTES_PER_PIXEL: usize>(
   debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
   debug_assert_eq!(filtered_row.len(), previous_row.len());
   //
-  let mut a: int16x8_t = unsafe { core::mem::zeroed() };
-  let mut c: int16x8_t = unsafe { core::mem::zeroed() };
+  let mut a: int16x8_t = vdupq_n_s16(0);
+  let mut c: int16x8_t = vdupq_n_s16(0);
   filtered_row
     .chunks_exact_mut(BYTES_PER_PIXEL)
     .zip(previous_row.chunks_exact(BYTES_PER_PIXEL))
     .for_each(|(x_chunk, b_chunk)| {
       let mut x: uint8x8_t = uint8x8_t_load::<BYTES_PER_PIXEL>(x_chunk);
-      let mut b: int16x8_t = unsafe { core::mem::zeroed() }; // i16
-      int16x8_t_as_mut_slice(&mut b)
+      let mut b: int16x8_t = vdupq_n_s16(0); // i16
+      as_mut_lanes::<i16, _>(&mut b)
         .iter_mut()
         .zip(b_chunk.iter())
         .for_each(|(j, k)| *j = *k as i16);
@@ -162,12 +219,12 @@ pub unsafe fn recon_paeth<const BYTES_PER_PIXEL: usize>(
         let pick_b_or_c = vbslq_s16(pb_le_pc, b, c);
         let paeth_s16: int16x8_t = vbslq_s16(pa_le_pb_and_pa_le_pc, a, pick_b_or_c);
         let paeth_u8: uint8x16_t = vreinterpretq_u8_s16(paeth_s16);
-        let paeth: uint8x8_t = vget_low_u8(vuzp1q_u8(paeth_u8, unsafe { core::mem::zeroed() }));
+        let paeth: uint8x8_t = vget_low_u8(vuzp1q_u8(paeth_u8, vdupq_n_u8(0)));
         x = vadd_u8(x, paeth);
       }
       uint8x8_t_store::<BYTES_PER_PIXEL>(x_chunk, x);
-      let wide_x: uint8x16_t = vcombine_u8(x, unsafe { core::mem::zeroed() });
-      let zipped_x: uint8x16_t = vzip1q_u8(wide_x, unsafe { core::mem::zeroed() });
+      let wide_x: uint8x16_t = vcombine_u8(x, vdup_n_u8(0));
+      let zipped_x: uint8x16_t = vzip1q_u8(wide_x, vdupq_n_u8(0));
       a = vreinterpretq_s16_u8(zipped_x);
       c = b;
     })
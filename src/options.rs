@@ -0,0 +1,349 @@
+//! A configurable version of [`unfilter_lines`](crate::unfilter_lines).
+//!
+//! The bare `unfilter_lines` is what you want in the hot path, but sometimes
+//! you need a little more control. [`UnfilterOptions`] lets you pick the
+//! options you want and then [`run`](UnfilterOptions::run) on some lines, which
+//! gives back an [`UnfilterOutcome`].
+//!
+//! ```
+//! use png_filters::{options::UnfilterOptions, Backend};
+//! let mut image = [1, 1, 2, 3, 4, 2, 5, 6, 7, 8];
+//! let outcome = UnfilterOptions::new()
+//!   .backend(Backend::Fallback)
+//!   .count_filters(true)
+//!   .run::<1>(image.chunks_exact_mut(5));
+//! assert_eq!(outcome.result, Ok(()));
+//! assert_eq!(outcome.filter_counts, Some([0, 1, 1, 0, 0]));
+//! assert_eq!(image, [0, 1, 3, 6, 10, 0, 6, 9, 13, 18]);
+//! ```
+
+use core::{fmt, iter::Peekable, slice::ChunksExactMut};
+
+use crate::{
+  assert_bytes_per_pixel, unfilter_lines_with, Backend, DispatchThresholds, FilterType, ReconFns,
+};
+
+/// Options for unfiltering an image.
+///
+/// The default options do the same thing as
+/// [`unfilter_lines`](crate::unfilter_lines).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct UnfilterOptions<'p> {
+  backend: Option<Backend>,
+  previous_row: Option<&'p [u8]>,
+  count_filters: bool,
+  simd_min_row_len: usize,
+  thresholds: DispatchThresholds,
+}
+impl<'p> UnfilterOptions<'p> {
+  /// The default options.
+  #[inline]
+  #[must_use]
+  pub const fn new() -> Self {
+    Self {
+      backend: None,
+      previous_row: None,
+      count_filters: false,
+      simd_min_row_len: 0,
+      thresholds: DispatchThresholds::DEFAULT,
+    }
+  }
+
+  /// Use every function from the given backend, instead of automatically
+  /// picking the best function for each filter type.
+  ///
+  /// If the backend isn't available on the current CPU then `run` gives
+  /// [`UnfilterError::BackendUnavailable`]. This works even with the `no-simd`
+  /// feature, since it's like calling the backend's module directly.
+  #[inline]
+  #[must_use]
+  pub const fn backend(self, backend: Backend) -> Self {
+    Self { backend: Some(backend), ..self }
+  }
+
+  /// The row just above the first line, without a filter byte.
+  ///
+  /// This lets you unfilter an image in several parts: pass the last
+  /// reconstructed row of one part when you unfilter the next part. Without
+  /// this the first line is treated as the top line of the image.
+  #[inline]
+  #[must_use]
+  pub const fn previous_row(self, previous_row: &'p [u8]) -> Self {
+    Self { previous_row: Some(previous_row), ..self }
+  }
+
+  /// If the outcome should count how many lines use each filter type.
+  #[inline]
+  #[must_use]
+  pub const fn count_filters(self, count_filters: bool) -> Self {
+    Self { count_filters, ..self }
+  }
+
+  /// Only use the SIMD functions when each row (not counting the filter byte)
+  /// is at least this many bytes, and use the fallbacks for shorter rows.
+  ///
+  /// The default is 0, so the SIMD functions are always allowed, the same as
+  /// [`unfilter_lines`](crate::unfilter_lines). Even on tiny rows the
+  /// automatic selection is usually faster than the fallbacks. For images of
+  /// 256 lines (one filter type per line, cycling through all five):
+  ///
+  /// | bpp | row bytes | automatic | fallbacks |
+  /// |-----|-----------|-----------|-----------|
+  /// | 1   | 16        | 1.8us     | 2.2us     |
+  /// | 3   | 12        | 3.0us     | 3.4us     |
+  /// | 4   | 16        | 3.6us     | 3.0us     |
+  /// | 4   | 64        | 8.5us     | 9.9us     |
+  /// | 8   | 16        | 2.2us     | 4.9us     |
+  ///
+  /// (and with every line "paeth", bpp 4 at 16 bytes was 5.4us vs 5.1us). So
+  /// this is for tuning against your own workload, such as lots of small
+  /// icons, rather than something you should always set.
+  ///
+  /// This has no effect when a [`backend`](Self::backend) is given.
+  #[inline]
+  #[must_use]
+  pub const fn simd_min_row_len(self, simd_min_row_len: usize) -> Self {
+    Self { simd_min_row_len, ..self }
+  }
+
+  /// The smallest bytes per pixel at which each filter type can use a SIMD
+  /// function.
+  ///
+  /// The default is [`DispatchThresholds::DEFAULT`], the same as
+  /// [`unfilter_lines`](crate::unfilter_lines). This is for tuning, see
+  /// [`selected_backends_with`](crate::selected_backends_with) for what a set
+  /// of thresholds picks.
+  ///
+  /// This has no effect when a [`backend`](Self::backend) is given.
+  #[inline]
+  #[must_use]
+  pub const fn thresholds(self, thresholds: DispatchThresholds) -> Self {
+    Self { thresholds, ..self }
+  }
+
+  /// Unfilters the `lines` in place, using these options.
+  ///
+  /// The lines are in the same format as for
+  /// [`unfilter_lines`](crate::unfilter_lines).
+  ///
+  /// ## Panics
+  /// * As [`unfilter_lines`](crate::unfilter_lines)
+  #[track_caller]
+  pub fn run<'a, const BYTES_PER_PIXEL: usize>(
+    self, lines: ChunksExactMut<'a, u8>,
+  ) -> UnfilterOutcome
+  where
+    'p: 'a,
+  {
+    self.run_lines::<BYTES_PER_PIXEL>(lines.peekable())
+  }
+
+  /// As [`run`](Self::run), for lines that are already `Peekable`.
+  ///
+  /// * Every line must have at least the filter byte, and they must all be the
+  ///   same length.
+  #[track_caller]
+  pub(crate) fn run_lines<'a, const BYTES_PER_PIXEL: usize>(
+    self, mut lines: Peekable<ChunksExactMut<'a, u8>>,
+  ) -> UnfilterOutcome
+  where
+    'p: 'a,
+  {
+    assert_bytes_per_pixel::<BYTES_PER_PIXEL>();
+    //
+    let fail = |error: UnfilterError| UnfilterOutcome { filter_counts: None, result: Err(error) };
+    // Each line has the filter byte, `row_len` doesn't.
+    let row_len = lines.peek().map(|line| line.len() - 1);
+    let fns = match self.backend {
+      Some(backend) => match ReconFns::<BYTES_PER_PIXEL>::for_backend(backend) {
+        Some(fns) => Some(fns),
+        None => return fail(UnfilterError::BackendUnavailable(backend)),
+      },
+      None if row_len.unwrap_or(0) < self.simd_min_row_len => {
+        ReconFns::for_backend(Backend::Fallback)
+      }
+      None => None,
+    };
+    if let Some(row_len) = row_len {
+      if row_len > 0 && row_len < BYTES_PER_PIXEL {
+        return fail(UnfilterError::RowShorterThanPixel {
+          row_len,
+          bytes_per_pixel: BYTES_PER_PIXEL,
+        });
+      }
+    }
+    if let (Some(previous_row), Some(row_len)) = (self.previous_row, row_len) {
+      if previous_row.len() != row_len {
+        return fail(UnfilterError::PreviousRowLength {
+          expected: row_len,
+          actual: previous_row.len(),
+        });
+      }
+    }
+    //
+    let mut counts = [0_usize; 5];
+    let mut invalid: Option<UnfilterError> = None;
+    let mut y = 0;
+    let lines = lines.inspect(|line| {
+      match line[0] {
+        filter @ 0..=4 => counts[usize::from(filter)] += 1,
+        filter => {
+          invalid.get_or_insert(UnfilterError::InvalidFilterType { y, filter });
+        }
+      }
+      y += 1;
+    });
+    let select =
+      || fns.unwrap_or_else(|| ReconFns::<BYTES_PER_PIXEL>::select_with(self.thresholds));
+    unfilter_lines_with(self.previous_row, lines, select);
+    UnfilterOutcome {
+      filter_counts: if self.count_filters { Some(counts) } else { None },
+      result: match invalid {
+        Some(error) => Err(error),
+        None => Ok(()),
+      },
+    }
+  }
+}
+
+/// What happened during [`UnfilterOptions::run`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UnfilterOutcome {
+  /// How many lines had each filter type, indexed by the filter type (see
+  /// [`filter_count`](Self::filter_count)).
+  ///
+  /// This is only `Some` when [`UnfilterOptions::count_filters`] was set and
+  /// the lines were actually unfiltered. Lines with an invalid filter type
+  /// aren't counted.
+  pub filter_counts: Option<[usize; 5]>,
+  /// If there was a problem.
+  pub result: Result<(), UnfilterError>,
+}
+impl UnfilterOutcome {
+  /// How many lines had `filter`, when the filter types were counted.
+  ///
+  /// This is [`filter_counts`](Self::filter_counts) indexed by `filter`.
+  #[inline]
+  #[must_use]
+  pub fn filter_count(&self, filter: FilterType) -> Option<usize> {
+    self.filter_counts.map(|counts| counts[usize::from(u8::from(filter))])
+  }
+}
+
+/// A problem found by [`UnfilterOptions::run`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnfilterError {
+  /// The backend asked for isn't available on this CPU.
+  ///
+  /// None of the lines were changed.
+  BackendUnavailable(Backend),
+  /// The previous row isn't the same length as each line (minus the filter
+  /// byte).
+  ///
+  /// None of the lines were changed.
+  PreviousRowLength {
+    /// The length of each line, minus the filter byte.
+    expected: usize,
+    /// The length of the previous row.
+    actual: usize,
+  },
+  /// Each line has some data, but less than one pixel of it.
+  ///
+  /// Every line of a valid image has a whole number of pixels, so this is a
+  /// malformed stream (such as a bad `IHDR`). An image with a width of 0 is
+  /// fine, since its lines have no data at all.
+  ///
+  /// None of the lines were changed.
+  RowShorterThanPixel {
+    /// The length of each line, minus the filter byte.
+    row_len: usize,
+    /// The `BYTES_PER_PIXEL` of the call.
+    bytes_per_pixel: usize,
+  },
+  /// Each line has at least one pixel of data, but not a whole number of
+  /// pixels.
+  ///
+  /// Only the [`checked`](crate::checked) functions check for this, elsewhere
+  /// it's a `debug_assert!`. None of the lines were changed.
+  PartialPixel {
+    /// The length of each line, minus the filter byte.
+    row_len: usize,
+    /// The bytes per pixel of the call.
+    bytes_per_pixel: usize,
+  },
+  /// The bytes per pixel isn't in
+  /// [`SUPPORTED_BYTES_PER_PIXEL`](crate::SUPPORTED_BYTES_PER_PIXEL).
+  ///
+  /// Only the [`checked`](crate::checked) functions give this, elsewhere it's a
+  /// panic. None of the lines were changed.
+  UnsupportedBytesPerPixel(usize),
+  /// The buffer isn't a whole number of lines, or the line length is 0 but
+  /// the buffer isn't empty.
+  ///
+  /// Only the [`checked`](crate::checked) functions and
+  /// [`try_unfilter_flat`](crate::try_unfilter_flat) give this. None of the
+  /// lines were changed.
+  BufferLength {
+    /// The length of the buffer.
+    len: usize,
+    /// The length of each line, including the filter byte.
+    line_len: usize,
+  },
+  /// The buffer isn't the length of an image of the given size: `height`
+  /// lines, each a filter type byte followed by `width` pixels. This includes
+  /// a size whose length doesn't fit in a `usize`.
+  ///
+  /// Only [`checked::unfilter_to_vec`](crate::checked::unfilter_to_vec) gives
+  /// this.
+  ImageSize {
+    /// The length of the buffer.
+    len: usize,
+    /// The width of the image, in pixels.
+    width: usize,
+    /// The height of the image, in lines.
+    height: usize,
+  },
+  /// A line had a filter type above 4.
+  ///
+  /// That line is left as-is (like filter type 0) and all the other lines are
+  /// still unfiltered, the same as `unfilter_lines` does. This is only the
+  /// first such line.
+  InvalidFilterType {
+    /// The index of the line.
+    y: usize,
+    /// The filter type byte of the line.
+    filter: u8,
+  },
+}
+impl fmt::Display for UnfilterError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::BackendUnavailable(backend) => write!(f, "the {backend:?} backend isn't available"),
+      Self::PreviousRowLength { expected, actual } => {
+        write!(f, "the previous row has {actual} bytes, expected {expected}")
+      }
+      Self::RowShorterThanPixel { row_len, bytes_per_pixel } => {
+        write!(f, "each row has {row_len} bytes, less than one pixel of {bytes_per_pixel} bytes")
+      }
+      Self::PartialPixel { row_len, bytes_per_pixel } => {
+        write!(
+          f,
+          "each row has {row_len} bytes, not a whole number of {bytes_per_pixel} byte pixels"
+        )
+      }
+      Self::UnsupportedBytesPerPixel(bytes_per_pixel) => {
+        write!(f, "{bytes_per_pixel} bytes per pixel isn't supported")
+      }
+      Self::BufferLength { len, line_len } => {
+        write!(f, "a buffer of {len} bytes isn't a whole number of {line_len} byte lines")
+      }
+      Self::ImageSize { len, width, height } => {
+        write!(f, "a buffer of {len} bytes isn't a {width}x{height} image")
+      }
+      Self::InvalidFilterType { y, filter } => {
+        write!(f, "row {y} has invalid filter type {filter}")
+      }
+    }
+  }
+}
+impl std::error::Error for UnfilterError {}
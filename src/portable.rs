@@ -0,0 +1,160 @@
+//! PNG filter functions written with [`core::simd`].
+//!
+//! This module only exists when the `portable_simd` feature is enabled, which
+//! needs a nightly compiler. The same code compiles to SIMD on every target
+//! that `core::simd` supports (x86, aarch64, wasm, riscv, ...), so it's a
+//! backend for targets that have no hand-written module, and a reference point
+//! for the hand-written modules on the targets that do.
+//!
+//! Unlike the other SIMD modules these functions are safe, since `core::simd`
+//! falls back to scalar code for whatever the target doesn't have.
+
+use core::simd::{
+  cmp::SimdPartialOrd,
+  num::{SimdInt, SimdUint},
+  Select, Simd,
+};
+
+/// One pixel of bytes, zero-extended to 8 lanes.
+type Pixel = Simd<u8, 8>;
+
+/// Loads one pixel of `BYTES_PER_PIXEL` bytes.
+#[inline(always)]
+fn load_pixel<const BYTES_PER_PIXEL: usize>(chunk: &[u8]) -> Pixel {
+  let mut bytes = [0_u8; 8];
+  bytes[..BYTES_PER_PIXEL].copy_from_slice(chunk);
+  Pixel::from_array(bytes)
+}
+
+/// Stores the first `BYTES_PER_PIXEL` lanes of `pixel` to `chunk`.
+#[inline(always)]
+fn store_pixel<const BYTES_PER_PIXEL: usize>(chunk: &mut [u8], pixel: Pixel) {
+  chunk.copy_from_slice(&pixel.to_array()[..BYTES_PER_PIXEL]);
+}
+
+/// `floor((a + b) / 2)` without widening: the bits both have, plus half of the
+/// bits only one of them has.
+#[inline(always)]
+fn floor_average(a: Pixel, b: Pixel) -> Pixel {
+  (a & b) + ((a ^ b) >> 1)
+}
+
+/// Like [`recon_sub`](crate::fallbacks::recon_sub), but with `core::simd`.
+///
+/// ## Panics
+/// * `assert!(BYTES_PER_PIXEL <= 8);`
+/// * `debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);`
+#[track_caller]
+#[inline]
+pub fn recon_sub<const BYTES_PER_PIXEL: usize>(filtered_row: &mut [u8]) {
+  assert!(BYTES_PER_PIXEL <= 8);
+  debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
+  //
+  let mut a = Pixel::splat(0);
+  filtered_row.chunks_exact_mut(BYTES_PER_PIXEL).for_each(|chunk| {
+    let x = load_pixel::<BYTES_PER_PIXEL>(chunk) + a;
+    store_pixel::<BYTES_PER_PIXEL>(chunk, x);
+    a = x;
+  })
+}
+
+/// Like [`recon_up`](crate::fallbacks::recon_up), but with `core::simd`.
+///
+/// ## Panics
+/// * `debug_assert_eq!(filtered_row.len(), previous_row.len());`
+#[inline]
+pub fn recon_up(filtered_row: &mut [u8], previous_row: &[u8]) {
+  debug_assert_eq!(filtered_row.len(), previous_row.len());
+  //
+  let mut x_chunks = filtered_row.chunks_exact_mut(16);
+  let mut b_chunks = previous_row.chunks_exact(16);
+  (&mut x_chunks).zip(&mut b_chunks).for_each(|(x_chunk, b_chunk)| {
+    let x = Simd::<u8, 16>::from_slice(x_chunk) + Simd::<u8, 16>::from_slice(b_chunk);
+    x.copy_to_slice(x_chunk);
+  });
+  x_chunks
+    .into_remainder()
+    .iter_mut()
+    .zip(b_chunks.remainder().iter())
+    .for_each(|(x, b)| *x = x.wrapping_add(*b))
+}
+
+/// Like [`recon_average`](crate::fallbacks::recon_average), but with
+/// `core::simd`.
+///
+/// ## Panics
+/// * `assert!(BYTES_PER_PIXEL <= 8);`
+/// * `debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);`
+/// * `debug_assert_eq!(filtered_row.len(), previous_row.len());`
+#[track_caller]
+#[inline]
+pub fn recon_average<const BYTES_PER_PIXEL: usize>(filtered_row: &mut [u8], previous_row: &[u8]) {
+  assert!(BYTES_PER_PIXEL <= 8);
+  debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
+  debug_assert_eq!(filtered_row.len(), previous_row.len());
+  //
+  let mut a = Pixel::splat(0);
+  filtered_row
+    .chunks_exact_mut(BYTES_PER_PIXEL)
+    .zip(previous_row.chunks_exact(BYTES_PER_PIXEL))
+    .for_each(|(x_chunk, b_chunk)| {
+      let b = load_pixel::<BYTES_PER_PIXEL>(b_chunk);
+      let x = load_pixel::<BYTES_PER_PIXEL>(x_chunk) + floor_average(a, b);
+      store_pixel::<BYTES_PER_PIXEL>(x_chunk, x);
+      a = x;
+    })
+}
+
+/// Like [`recon_average_top`](crate::fallbacks::recon_average_top), but with
+/// `core::simd`.
+///
+/// ## Panics
+/// * `assert!(BYTES_PER_PIXEL <= 8);`
+/// * `debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);`
+#[track_caller]
+#[inline]
+pub fn recon_average_top<const BYTES_PER_PIXEL: usize>(filtered_row: &mut [u8]) {
+  assert!(BYTES_PER_PIXEL <= 8);
+  debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
+  //
+  let mut a = Pixel::splat(0);
+  filtered_row.chunks_exact_mut(BYTES_PER_PIXEL).for_each(|chunk| {
+    let x = load_pixel::<BYTES_PER_PIXEL>(chunk) + (a >> 1);
+    store_pixel::<BYTES_PER_PIXEL>(chunk, x);
+    a = x;
+  })
+}
+
+/// Like [`recon_paeth`](crate::fallbacks::recon_paeth), but with `core::simd`.
+///
+/// The predictor is picked with `i16` lanes, the same as the `sse4_1` version.
+///
+/// ## Panics
+/// * `assert!(BYTES_PER_PIXEL <= 8);`
+/// * `debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);`
+/// * `debug_assert_eq!(filtered_row.len(), previous_row.len());`
+#[track_caller]
+#[inline]
+pub fn recon_paeth<const BYTES_PER_PIXEL: usize>(filtered_row: &mut [u8], previous_row: &[u8]) {
+  assert!(BYTES_PER_PIXEL <= 8);
+  debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
+  debug_assert_eq!(filtered_row.len(), previous_row.len());
+  //
+  let mut a = Simd::<i16, 8>::splat(0);
+  let mut c = Simd::<i16, 8>::splat(0);
+  filtered_row
+    .chunks_exact_mut(BYTES_PER_PIXEL)
+    .zip(previous_row.chunks_exact(BYTES_PER_PIXEL))
+    .for_each(|(x_chunk, b_chunk)| {
+      let b = load_pixel::<BYTES_PER_PIXEL>(b_chunk).cast::<i16>();
+      let pa = (b - c).abs();
+      let pb = (a - c).abs();
+      let pc = (a + b - c - c).abs();
+      let b_or_c = pb.simd_le(pc).select(b, c);
+      let paeth = (pa.simd_le(pb) & pa.simd_le(pc)).select(a, b_or_c);
+      let x = load_pixel::<BYTES_PER_PIXEL>(x_chunk) + paeth.cast::<u8>();
+      store_pixel::<BYTES_PER_PIXEL>(x_chunk, x);
+      a = x.cast::<i16>();
+      c = b;
+    })
+}
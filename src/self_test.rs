@@ -0,0 +1,324 @@
+//! Checks at runtime that unfiltering gives the right bytes on this machine.
+//!
+//! This module only exists when the `self-test` feature is enabled, so that the
+//! test data isn't in every binary. With the feature on, [`self_test`] runs
+//! every backend that the CPU supports (and the automatic selection that
+//! [`unfilter_lines`](crate::unfilter_lines) uses) over some known images. A
+//! device can call it at startup to catch a miscompiled kernel or a CPU
+//! erratum before it decodes anything that matters.
+
+use core::fmt;
+
+use crate::{
+  options::{UnfilterError, UnfilterOptions},
+  Backend, SUPPORTED_BYTES_PER_PIXEL,
+};
+
+/// A case of [`self_test`] that gave the wrong bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SelfTestError {
+  /// Which case it was.
+  pub case: &'static str,
+  /// The bytes per pixel of the case.
+  pub bytes_per_pixel: usize,
+  /// The backend that was used, or `None` for the automatic selection.
+  pub backend: Option<Backend>,
+}
+impl fmt::Display for SelfTestError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let Self { case, bytes_per_pixel, backend } = self;
+    match backend {
+      Some(backend) => write!(
+        f,
+        "{case} at {bytes_per_pixel} bytes per pixel is wrong with the {backend:?} backend"
+      ),
+      None => write!(
+        f,
+        "{case} at {bytes_per_pixel} bytes per pixel is wrong with the automatic selection"
+      ),
+    }
+  }
+}
+impl std::error::Error for SelfTestError {}
+
+/// Unfilters some known images with each backend that's available, and checks
+/// the output.
+///
+/// There are two kinds of case:
+///
+/// * Small images with the expected output written out by hand (the same
+///   vectors as the crate's own tests). These check every backend, including
+///   the fallbacks.
+/// * A larger generated image for each bytes per pixel, with every filter type
+///   and rows long enough to go through all of the SIMD loops. These check
+///   every other backend against the fallbacks.
+///
+/// Returns the first case that gave the wrong bytes, or that gave an error.
+/// Backends that the CPU doesn't have are skipped.
+pub fn self_test() -> Result<(), SelfTestError> {
+  for vector in VECTORS {
+    for backend in BACKENDS {
+      let error =
+        SelfTestError { case: vector.name, bytes_per_pixel: vector.bytes_per_pixel, backend };
+      let mut actual = vector.filtered.to_vec();
+      let ran = run(vector.bytes_per_pixel, backend, vector.previous, &mut actual, vector.line_len)
+        .map_err(|_| error)?;
+      if ran && actual != vector.expected {
+        return Err(error);
+      }
+    }
+  }
+  for bytes_per_pixel in SUPPORTED_BYTES_PER_PIXEL {
+    let (filtered, previous, line_len) = generated(bytes_per_pixel);
+    let mut expected = filtered.clone();
+    let backend = Some(Backend::Fallback);
+    run(bytes_per_pixel, backend, Some(&previous), &mut expected, line_len)
+      .map_err(|_| SelfTestError { case: "generated", bytes_per_pixel, backend })?;
+    for backend in BACKENDS {
+      let error = SelfTestError { case: "generated", bytes_per_pixel, backend };
+      let mut actual = filtered.clone();
+      let ran =
+        run(bytes_per_pixel, backend, Some(&previous), &mut actual, line_len).map_err(|_| error)?;
+      if ran && actual != expected {
+        return Err(error);
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Every backend, and `None` for the automatic selection.
+const BACKENDS: [Option<Backend>; 8] = [
+  None,
+  Some(Backend::Fallback),
+  Some(Backend::Sse2),
+  Some(Backend::Sse4_1),
+  Some(Backend::Neon),
+  Some(Backend::Portable),
+  Some(Backend::Avx2),
+  Some(Backend::Avx512),
+];
+
+/// Unfilters `lines`, returns `Ok(false)` if the backend isn't available.
+///
+/// Any other error means the case itself went wrong, so it's passed on.
+fn run(
+  bytes_per_pixel: usize, backend: Option<Backend>, previous: Option<&[u8]>, lines: &mut [u8],
+  line_len: usize,
+) -> Result<bool, UnfilterError> {
+  let mut options = UnfilterOptions::new();
+  if let Some(backend) = backend {
+    options = options.backend(backend);
+  }
+  if let Some(previous) = previous {
+    options = options.previous_row(previous);
+  }
+  let lines = lines.chunks_exact_mut(line_len);
+  let outcome = dispatch_bpp!(bytes_per_pixel, |N| options.run::<N>(lines));
+  match outcome.result {
+    Ok(_) => Ok(true),
+    Err(UnfilterError::BackendUnavailable(_)) => Ok(false),
+    Err(error) => Err(error),
+  }
+}
+
+/// A generated image: the filtered lines, the row above them, and the line
+/// length.
+///
+/// It's 67 pixels wide (so the SIMD loops all have a tail), and it has two
+/// lines of each filter type, in an order where each filter type follows each
+/// other one at least once.
+fn generated(bytes_per_pixel: usize) -> (Vec<u8>, Vec<u8>, usize) {
+  const FILTERS: [u8; 10] = [1, 2, 3, 4, 0, 4, 2, 0, 3, 1];
+  let line_len = 1 + 67 * bytes_per_pixel;
+  // `xorshift64*`, so the image is the same every time.
+  let mut state: u64 = 0x5EED_0000 | bytes_per_pixel as u64;
+  let mut next_byte = || {
+    state ^= state >> 12;
+    state ^= state << 25;
+    state ^= state >> 27;
+    (state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+  };
+  let previous: Vec<u8> = (0..line_len - 1).map(|_| next_byte()).collect();
+  let mut filtered: Vec<u8> = (0..line_len * FILTERS.len()).map(|_| next_byte()).collect();
+  filtered.chunks_exact_mut(line_len).zip(FILTERS).for_each(|(line, filter)| line[0] = filter);
+  (filtered, previous, line_len)
+}
+
+/// An image with the expected output written out by hand.
+struct Vector {
+  name: &'static str,
+  bytes_per_pixel: usize,
+  /// Bytes per line, including the filter byte.
+  line_len: usize,
+  /// The row above the first line, or `None` for the top of the image.
+  previous: Option<&'static [u8]>,
+  filtered: &'static [u8],
+  /// The unfiltered lines, with each filter byte set to 0.
+  expected: &'static [u8],
+}
+
+const LAST_ROW: &[u8] = &[12, 17, 127, 128, 255, 250, 7, 54];
+
+const VECTORS: &[Vector] = &[
+  Vector {
+    name: "sub",
+    bytes_per_pixel: 1,
+    line_len: 9,
+    previous: None,
+    filtered: &[1, 1, 2, 3, 255, 5, 6, 7, 8],
+    expected: &[0, 1, 3, 6, 5, 10, 16, 23, 31],
+  },
+  Vector {
+    name: "sub",
+    bytes_per_pixel: 2,
+    line_len: 9,
+    previous: None,
+    filtered: &[1, 1, 2, 3, 255, 5, 6, 7, 8],
+    expected: &[0, 1, 2, 4, 1, 9, 7, 16, 15],
+  },
+  Vector {
+    name: "sub",
+    bytes_per_pixel: 4,
+    line_len: 9,
+    previous: None,
+    filtered: &[1, 1, 2, 3, 255, 5, 6, 7, 8],
+    expected: &[0, 1, 2, 3, 255, 6, 8, 10, 7],
+  },
+  Vector {
+    name: "up",
+    bytes_per_pixel: 1,
+    line_len: 9,
+    previous: Some(LAST_ROW),
+    filtered: &[2, 1, 2, 3, 255, 5, 6, 7, 8],
+    expected: &[0, 13, 19, 130, 127, 4, 0, 14, 62],
+  },
+  Vector {
+    name: "average",
+    bytes_per_pixel: 1,
+    line_len: 9,
+    previous: Some(LAST_ROW),
+    filtered: &[3, 1, 2, 3, 255, 5, 6, 7, 8],
+    expected: &[0, 7, 14, 73, 99, 182, 222, 121, 95],
+  },
+  Vector {
+    name: "average",
+    bytes_per_pixel: 2,
+    line_len: 9,
+    previous: Some(LAST_ROW),
+    filtered: &[3, 1, 2, 3, 255, 5, 6, 7, 8],
+    expected: &[0, 7, 10, 70, 68, 167, 165, 94, 117],
+  },
+  Vector {
+    name: "average",
+    bytes_per_pixel: 4,
+    line_len: 9,
+    previous: Some(LAST_ROW),
+    filtered: &[3, 1, 2, 3, 255, 5, 6, 7, 8],
+    expected: &[0, 7, 10, 66, 63, 136, 136, 43, 66],
+  },
+  Vector {
+    name: "average, top line",
+    bytes_per_pixel: 1,
+    line_len: 9,
+    previous: None,
+    filtered: &[3, 1, 2, 3, 255, 5, 6, 7, 8],
+    expected: &[0, 1, 2, 4, 1, 5, 8, 11, 13],
+  },
+  Vector {
+    name: "average, top line",
+    bytes_per_pixel: 2,
+    line_len: 9,
+    previous: None,
+    filtered: &[3, 1, 2, 3, 255, 5, 6, 7, 8],
+    expected: &[0, 1, 2, 3, 0, 6, 6, 10, 11],
+  },
+  Vector {
+    name: "average, top line",
+    bytes_per_pixel: 3,
+    line_len: 10,
+    previous: None,
+    filtered: &[3, 1, 2, 3, 255, 5, 6, 7, 8, 9],
+    expected: &[0, 1, 2, 3, 255, 6, 7, 134, 11, 12],
+  },
+  Vector {
+    name: "average, top line",
+    bytes_per_pixel: 4,
+    line_len: 9,
+    previous: None,
+    filtered: &[3, 1, 2, 3, 255, 5, 6, 7, 8],
+    expected: &[0, 1, 2, 3, 255, 5, 7, 8, 135],
+  },
+  Vector {
+    name: "paeth",
+    bytes_per_pixel: 1,
+    line_len: 9,
+    previous: Some(LAST_ROW),
+    filtered: &[4, 1, 2, 3, 255, 5, 6, 7, 8],
+    expected: &[0, 13, 19, 130, 129, 4, 10, 14, 62],
+  },
+  Vector {
+    name: "paeth",
+    bytes_per_pixel: 2,
+    line_len: 9,
+    previous: Some(LAST_ROW),
+    filtered: &[4, 1, 2, 3, 255, 5, 6, 7, 8],
+    expected: &[0, 13, 19, 130, 127, 4, 0, 11, 8],
+  },
+  Vector {
+    name: "paeth",
+    bytes_per_pixel: 4,
+    line_len: 9,
+    previous: Some(LAST_ROW),
+    filtered: &[4, 1, 2, 3, 255, 5, 6, 7, 8],
+    expected: &[0, 13, 19, 130, 127, 4, 0, 14, 62],
+  },
+  Vector {
+    name: "average, width 3, rgb8",
+    bytes_per_pixel: 3,
+    line_len: 10,
+    previous: None,
+    filtered: &[
+      3, 200, 100, 255, 1, 2, 3, 255, 255, 255, //
+      3, 10, 250, 0, 128, 128, 128, 7, 8, 9,
+    ],
+    expected: &[
+      0, 200, 100, 255, 101, 52, 130, 49, 25, 64, //
+      0, 110, 44, 127, 233, 176, 0, 148, 108, 41,
+    ],
+  },
+  Vector {
+    name: "average, width 8, rgba8",
+    bytes_per_pixel: 4,
+    line_len: 33,
+    previous: None,
+    filtered: &[
+      3, 255, 0, 128, 1, 255, 0, 128, 1, 255, 0, 128, 1, 255, 0, 128, 1, 255, 0, 128, 1, 255, 0,
+      128, 1, 255, 0, 128, 1, 255, 0, 128, 1, //
+      3, 255, 127, 127, 255, 1, 127, 254, 128, 127, 0, 127, 0, 254, 1, 127, 255, 255, 128, 254,
+      127, 127, 254, 254, 128, 255, 255, 128, 255, 127, 254, 128, 0,
+    ],
+    expected: &[
+      0, 255, 0, 128, 1, 126, 0, 192, 1, 62, 0, 224, 1, 30, 0, 240, 1, 14, 0, 248, 1, 6, 0, 252, 1,
+      2, 0, 254, 1, 0, 0, 255, 1, //
+      0, 126, 127, 191, 255, 127, 190, 189, 0, 221, 95, 77, 0, 123, 48, 29, 255, 67, 152, 136, 255,
+      163, 74, 192, 0, 81, 36, 95, 255, 167, 16, 47, 128,
+    ],
+  },
+];
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_vectors_have_whole_lines() {
+    for vector in VECTORS {
+      assert_eq!(vector.filtered.len() % vector.line_len, 0, "{}", vector.name);
+      assert_eq!(vector.filtered.len(), vector.expected.len(), "{}", vector.name);
+      if let Some(previous) = vector.previous {
+        assert_eq!(previous.len(), vector.line_len - 1, "{}", vector.name);
+      }
+    }
+  }
+}
@@ -0,0 +1,79 @@
+//! Views of SIMD vector types as slices of their lanes.
+//!
+//! All the SIMD modules move partial pixels into and out of vectors through a
+//! slice of the vector's lanes, so that logic lives here, once, instead of a
+//! separate raw pointer cast per vector type and lane type.
+
+/// A SIMD vector type.
+///
+/// ## Safety
+/// * The type must be a plain bundle of integer lanes: no padding, and every
+///   bit pattern is a valid value.
+/// * The type's alignment must be at least that of every [`Lane`] type.
+pub(crate) unsafe trait Vector: Copy {}
+
+/// A lane type that a [`Vector`] can be viewed as.
+///
+/// ## Safety
+/// * The type must be a primitive integer (any bit pattern is valid).
+pub(crate) unsafe trait Lane: Copy {}
+unsafe impl Lane for u8 {}
+unsafe impl Lane for i16 {}
+
+#[cfg(target_arch = "x86")]
+use core::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::*;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+unsafe impl Vector for __m128i {}
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+unsafe impl Vector for __m256i {}
+
+#[cfg(target_arch = "aarch64")]
+use core::arch::aarch64::*;
+#[cfg(target_arch = "aarch64")]
+unsafe impl Vector for uint8x8_t {}
+#[cfg(target_arch = "aarch64")]
+unsafe impl Vector for int16x8_t {}
+
+/// Views a vector as a mutable slice of lanes.
+///
+/// The slice always covers exactly the bytes of the vector, so the length is
+/// `size_of::<V>() / size_of::<T>()` (for example, 16 `u8` lanes or 8 `i16`
+/// lanes of an `__m128i`).
+#[inline]
+#[must_use]
+pub(crate) fn as_mut_lanes<T: Lane, V: Vector>(v: &mut V) -> &mut [T] {
+  const { assert!(core::mem::size_of::<V>().is_multiple_of(core::mem::size_of::<T>())) };
+  const { assert!(core::mem::align_of::<V>() >= core::mem::align_of::<T>()) };
+  let data = v as *mut V as *mut T;
+  let len = core::mem::size_of::<V>() / core::mem::size_of::<T>();
+  // Safety: The pointer is aligned and valid for `size_of::<V>()` bytes, which
+  // is exactly `len` lanes, and any bit pattern is valid for both types.
+  unsafe { core::slice::from_raw_parts_mut(data, len) }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+  fn test_as_mut_lanes_m128i() {
+    let mut m: __m128i = unsafe { _mm_setzero_si128() };
+    assert_eq!(as_mut_lanes::<u8, _>(&mut m).len(), 16);
+    assert_eq!(as_mut_lanes::<i16, _>(&mut m).len(), 8);
+    as_mut_lanes::<i16, _>(&mut m)[7] = -1;
+    assert_eq!(as_mut_lanes::<u8, _>(&mut m)[14..], [u8::MAX, u8::MAX]);
+  }
+
+  #[test]
+  #[cfg(target_arch = "aarch64")]
+  fn test_as_mut_lanes_neon() {
+    let mut x: uint8x8_t = unsafe { vdup_n_u8(0) };
+    assert_eq!(as_mut_lanes::<u8, _>(&mut x).len(), 8);
+    let mut m: int16x8_t = unsafe { vdupq_n_s16(0) };
+    assert_eq!(as_mut_lanes::<u8, _>(&mut m).len(), 16);
+    assert_eq!(as_mut_lanes::<i16, _>(&mut m).len(), 8);
+  }
+}
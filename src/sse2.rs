@@ -7,20 +7,7 @@ use core::arch::x86::*;
 #[cfg(target_arch = "x86_64")]
 use core::arch::x86_64::*;
 
-const ZEROED: __m128i = unsafe { core::mem::transmute([0_u64; 2]) };
-
-#[inline]
-fn m128i_as_mut_u8s(m: &mut __m128i) -> &mut [u8] {
-  let data = m as *mut __m128i as *mut u8;
-  let len = core::mem::size_of::<__m128i>() / core::mem::size_of::<u8>();
-  unsafe { core::slice::from_raw_parts_mut(data, len) }
-}
-#[inline]
-fn m128i_as_mut_i16s(m: &mut __m128i) -> &mut [i16] {
-  let data = m as *mut __m128i as *mut i16;
-  let len = core::mem::size_of::<__m128i>() / core::mem::size_of::<i16>();
-  unsafe { core::slice::from_raw_parts_mut(data, len) }
-}
+use crate::simd_bytes::as_mut_lanes;
 
 /// We need to emulate this below `sse4.1`
 #[inline]
@@ -32,8 +19,8 @@ unsafe fn blend_tf_sse2(cond: __m128i, t: __m128i, f: __m128i) -> __m128i {
 #[inline]
 #[target_feature(enable = "sse2")]
 unsafe fn i16_abs_sse2(x: __m128i) -> __m128i {
-  let is_negative = _mm_cmplt_epi16(x, ZEROED);
-  blend_tf_sse2(is_negative, _mm_sub_epi16(ZEROED, x), x)
+  let is_negative = _mm_cmplt_epi16(x, _mm_setzero_si128());
+  blend_tf_sse2(is_negative, _mm_sub_epi16(_mm_setzero_si128(), x), x)
 }
 /// We always have to emulate this
 #[inline]
@@ -44,6 +31,25 @@ unsafe fn i16_le_sse2(a: __m128i, b: __m128i) -> __m128i {
   _mm_or_si128(lt, eq)
 }
 
+/// `a <= b` for unsigned bytes.
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn u8_le_sse2(a: __m128i, b: __m128i) -> __m128i {
+  _mm_cmpeq_epi8(_mm_min_epu8(a, b), a)
+}
+/// `|a - b|` for unsigned bytes.
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn u8_abs_diff_sse2(a: __m128i, b: __m128i) -> __m128i {
+  _mm_or_si128(_mm_subs_epu8(a, b), _mm_subs_epu8(b, a))
+}
+/// `floor((a + b) / 2)` for unsigned bytes (`_mm_avg_epu8` rounds up).
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn u8_floor_avg_sse2(a: __m128i, b: __m128i) -> __m128i {
+  _mm_sub_epi8(_mm_avg_epu8(a, b), _mm_and_si128(_mm_xor_si128(a, b), _mm_set1_epi8(1)))
+}
+
 /// Like [`recon_sub_fallback`](super::recon_sub_fallback), but specialized to
 /// `sse2`.
 ///
@@ -54,16 +60,144 @@ pub unsafe fn recon_sub<const BYTES_PER_PIXEL: usize>(filtered_row: &mut [u8]) {
   assert!(BYTES_PER_PIXEL <= 8);
   debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
   //
-  let mut a: __m128i = ZEROED;
+  // Note: we always zero vectors with `_mm_setzero_si128` rather than
+  // `mem::zeroed` or a transmute, so there's no unsafe type punning for Miri
+  // (or a reader) to worry about.
+  //
+  // The copies through `as_mut_lanes` look like a round trip through the
+  // stack, but with a constant `BYTES_PER_PIXEL` they compile to a single
+  // `movd`/`movq` (or a couple of moves) directly to and from the row. We
+  // tried loading each pixel with `_mm_loadl_epi64` straight from the row
+  // (copying only near the end of the row) and storing with `movd`/`movq`: on
+  // a 4032 byte row this was 2x slower for `sub` (bpp 4: 0.34us to 0.71us)
+  // and within noise for `average`, `average_top`, and `paeth`, so all the
+  // per-pixel functions keep the copies.
+  let mut a: __m128i = _mm_setzero_si128();
   filtered_row.chunks_exact_mut(BYTES_PER_PIXEL).for_each(|chunk| {
-    let mut x: __m128i = ZEROED;
-    m128i_as_mut_u8s(&mut x)[..BYTES_PER_PIXEL].copy_from_slice(chunk);
-    x = unsafe { _mm_add_epi8(x, a) };
-    chunk.copy_from_slice(&m128i_as_mut_u8s(&mut x)[..BYTES_PER_PIXEL]);
+    let mut x: __m128i = _mm_setzero_si128();
+    as_mut_lanes::<u8, _>(&mut x)[..BYTES_PER_PIXEL].copy_from_slice(chunk);
+    x = _mm_add_epi8(x, a);
+    chunk.copy_from_slice(&as_mut_lanes::<u8, _>(&mut x)[..BYTES_PER_PIXEL]);
     a = x;
   })
 }
 
+/// Like [`recon_sub`], but using a prefix sum to process 16 bytes at a time.
+///
+/// Within each 16 byte block we compute the running sum of the pixels with a
+/// prefix sum: adding the block to itself shifted by 1 pixel, then 2, then 4
+/// (and so on) gives each pixel the sum of itself and every pixel before it in
+/// the block. Then we add the last pixel of the previous block to all of them.
+/// Any bytes after the last full block are done one pixel at a time.
+///
+/// This only works when a pixel evenly divides the 16 byte block, so it's only
+/// available for 1, 2, and 4 bytes per pixel.
+///
+/// ## Panics
+/// * `assert!(BYTES_PER_PIXEL == 1 || BYTES_PER_PIXEL == 2 || BYTES_PER_PIXEL
+///   == 4);`
+/// * `debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);`
+///
+/// ## Safety
+/// * The `sse2` CPU feature must be available at runtime.
+#[target_feature(enable = "sse2")]
+pub unsafe fn recon_sub_prefix<const BYTES_PER_PIXEL: usize>(filtered_row: &mut [u8]) {
+  assert!(BYTES_PER_PIXEL == 1 || BYTES_PER_PIXEL == 2 || BYTES_PER_PIXEL == 4);
+  debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
+  //
+  let mut chunks = filtered_row.chunks_exact_mut(16);
+  // the last reconstructed pixel, repeated across the whole register.
+  let mut a: __m128i = _mm_setzero_si128();
+  (&mut chunks).for_each(|chunk| {
+    let mut x: __m128i = _mm_loadu_si128(chunk.as_ptr().cast());
+    if BYTES_PER_PIXEL <= 1 {
+      x = _mm_add_epi8(x, _mm_slli_si128::<1>(x));
+    }
+    if BYTES_PER_PIXEL <= 2 {
+      x = _mm_add_epi8(x, _mm_slli_si128::<2>(x));
+    }
+    x = _mm_add_epi8(x, _mm_slli_si128::<4>(x));
+    x = _mm_add_epi8(x, _mm_slli_si128::<8>(x));
+    x = _mm_add_epi8(x, a);
+    _mm_storeu_si128(chunk.as_mut_ptr().cast(), x);
+    a = match BYTES_PER_PIXEL {
+      1 => {
+        let last_u16 = _mm_unpackhi_epi8(x, x);
+        _mm_shuffle_epi32::<0xFF>(_mm_shufflehi_epi16::<0xFF>(last_u16))
+      }
+      2 => _mm_shuffle_epi32::<0xFF>(_mm_shufflehi_epi16::<0xFF>(x)),
+      _ => _mm_shuffle_epi32::<0xFF>(x),
+    };
+  });
+  let mut a: [u8; BYTES_PER_PIXEL] =
+    as_mut_lanes::<u8, _>(&mut a)[..BYTES_PER_PIXEL].try_into().unwrap();
+  chunks.into_remainder().chunks_exact_mut(BYTES_PER_PIXEL).for_each(|chunk| {
+    chunk.iter_mut().zip(a.iter()).for_each(|(x, a)| *x = x.wrapping_add(*a));
+    a.copy_from_slice(chunk);
+  });
+}
+
+/// As [`recon_sub_prefix`], but a block of 16 zero bytes skips the prefix sum.
+///
+/// A filtered "sub" byte of 0 means the pixel is the same as the one before it,
+/// so a whole zero block is just the last pixel repeated, which is already
+/// in `a`. Flat areas of an image filter to long runs of zeros.
+///
+/// On a 4096 byte row (x86_64), all zero after the first pixel, this is 0.21us
+/// at every ByPP vs 0.47us, 0.38us, and 0.26us for [`recon_sub_prefix`] at ByPP
+/// 1, 2, and 4. With random data the check costs 1-3% at ByPP 1 and 2, but 17%
+/// at ByPP 4 (0.30us vs 0.26us), so `unfilter_lines` only uses this at ByPP 1
+/// and 2.
+///
+/// ## Panics
+/// * `assert!(BYTES_PER_PIXEL == 1 || BYTES_PER_PIXEL == 2 || BYTES_PER_PIXEL
+///   == 4);`
+/// * `debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);`
+///
+/// ## Safety
+/// * The `sse2` CPU feature must be available at runtime.
+#[target_feature(enable = "sse2")]
+pub unsafe fn recon_sub_prefix_runs<const BYTES_PER_PIXEL: usize>(filtered_row: &mut [u8]) {
+  assert!(BYTES_PER_PIXEL == 1 || BYTES_PER_PIXEL == 2 || BYTES_PER_PIXEL == 4);
+  debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
+  //
+  let zero = _mm_setzero_si128();
+  let mut chunks = filtered_row.chunks_exact_mut(16);
+  // the last reconstructed pixel, repeated across the whole register.
+  let mut a: __m128i = _mm_setzero_si128();
+  (&mut chunks).for_each(|chunk| {
+    let mut x: __m128i = _mm_loadu_si128(chunk.as_ptr().cast());
+    if _mm_movemask_epi8(_mm_cmpeq_epi8(x, zero)) == 0xFFFF {
+      _mm_storeu_si128(chunk.as_mut_ptr().cast(), a);
+      return;
+    }
+    if BYTES_PER_PIXEL <= 1 {
+      x = _mm_add_epi8(x, _mm_slli_si128::<1>(x));
+    }
+    if BYTES_PER_PIXEL <= 2 {
+      x = _mm_add_epi8(x, _mm_slli_si128::<2>(x));
+    }
+    x = _mm_add_epi8(x, _mm_slli_si128::<4>(x));
+    x = _mm_add_epi8(x, _mm_slli_si128::<8>(x));
+    x = _mm_add_epi8(x, a);
+    _mm_storeu_si128(chunk.as_mut_ptr().cast(), x);
+    a = match BYTES_PER_PIXEL {
+      1 => {
+        let last_u16 = _mm_unpackhi_epi8(x, x);
+        _mm_shuffle_epi32::<0xFF>(_mm_shufflehi_epi16::<0xFF>(last_u16))
+      }
+      2 => _mm_shuffle_epi32::<0xFF>(_mm_shufflehi_epi16::<0xFF>(x)),
+      _ => _mm_shuffle_epi32::<0xFF>(x),
+    };
+  });
+  let mut a: [u8; BYTES_PER_PIXEL] =
+    as_mut_lanes::<u8, _>(&mut a)[..BYTES_PER_PIXEL].try_into().unwrap();
+  chunks.into_remainder().chunks_exact_mut(BYTES_PER_PIXEL).for_each(|chunk| {
+    chunk.iter_mut().zip(a.iter()).for_each(|(x, a)| *x = x.wrapping_add(*a));
+    a.copy_from_slice(chunk);
+  });
+}
+
 /// Like [`recon_up_fallback`](super::recon_up_fallback), but specialized to
 /// `sse2`.
 ///
@@ -73,13 +207,65 @@ pub unsafe fn recon_sub<const BYTES_PER_PIXEL: usize>(filtered_row: &mut [u8]) {
 /// just use that. You only need to call this version with `i585` targets when
 /// you've dynamically detected that `sse2` is available.
 ///
+/// This works on 16 bytes at a time with unaligned loads and stores, rather
+/// than relying on the auto-vectorizer. Any bytes after the last full 16 are
+/// done one at a time.
+///
 /// ## Safety
 /// * The `sse2` CPU feature must be available at runtime.
 #[target_feature(enable = "sse2")]
 pub unsafe fn recon_up(filtered_row: &mut [u8], previous_row: &[u8]) {
   debug_assert_eq!(filtered_row.len(), previous_row.len());
   //
-  filtered_row.iter_mut().zip(previous_row.iter()).for_each(|(x, b)| *x = x.wrapping_add(*b))
+  let mut x_chunks = filtered_row.chunks_exact_mut(16);
+  let mut b_chunks = previous_row.chunks_exact(16);
+  (&mut x_chunks).zip(&mut b_chunks).for_each(|(x_chunk, b_chunk)| {
+    let x: __m128i = _mm_loadu_si128(x_chunk.as_ptr().cast());
+    let b: __m128i = _mm_loadu_si128(b_chunk.as_ptr().cast());
+    _mm_storeu_si128(x_chunk.as_mut_ptr().cast(), _mm_add_epi8(x, b));
+  });
+  x_chunks
+    .into_remainder()
+    .iter_mut()
+    .zip(b_chunks.remainder().iter())
+    .for_each(|(x, b)| *x = x.wrapping_add(*b))
+}
+
+/// Like [`recon_up_checksummed`](crate::fallbacks::recon_up_checksummed), but
+/// specialized to `sse2`.
+///
+/// Each 16 byte block is summed with `psadbw` against zero right after the add,
+/// while it's still in a register, into two 64-bit lanes that are only
+/// combined at the end. Any bytes after the last full 16 are done one at a
+/// time. On a 4096 byte row (x86_64) this is 131ns, vs 445ns for
+/// [`recon_up`] followed by a separate sum.
+///
+/// ## Safety
+/// * The `sse2` CPU feature must be available at runtime.
+#[target_feature(enable = "sse2")]
+pub unsafe fn recon_up_checksummed(filtered_row: &mut [u8], previous_row: &[u8]) -> u32 {
+  debug_assert_eq!(filtered_row.len(), previous_row.len());
+  //
+  let zero = _mm_setzero_si128();
+  let mut sums = zero;
+  let mut x_chunks = filtered_row.chunks_exact_mut(16);
+  let mut b_chunks = previous_row.chunks_exact(16);
+  (&mut x_chunks).zip(&mut b_chunks).for_each(|(x_chunk, b_chunk)| {
+    let x: __m128i = _mm_loadu_si128(x_chunk.as_ptr().cast());
+    let b: __m128i = _mm_loadu_si128(b_chunk.as_ptr().cast());
+    let recon = _mm_add_epi8(x, b);
+    _mm_storeu_si128(x_chunk.as_mut_ptr().cast(), recon);
+    sums = _mm_add_epi64(sums, _mm_sad_epu8(recon, zero));
+  });
+  let low = _mm_cvtsi128_si32(sums) as u32;
+  let high = _mm_cvtsi128_si32(_mm_unpackhi_epi64(sums, sums)) as u32;
+  x_chunks.into_remainder().iter_mut().zip(b_chunks.remainder().iter()).fold(
+    low.wrapping_add(high),
+    |sum, (x, b)| {
+      *x = x.wrapping_add(*b);
+      sum.wrapping_add(u32::from(*x))
+    },
+  )
 }
 
 /// Like [`recon_average_fallback`](super::recon_average_fallback), but
@@ -91,32 +277,9 @@ pub unsafe fn recon_up(filtered_row: &mut [u8], previous_row: &[u8]) {
 pub unsafe fn recon_average<const BYTES_PER_PIXEL: usize>(
   filtered_row: &mut [u8], previous_row: &[u8],
 ) {
-  assert!(BYTES_PER_PIXEL <= 8);
-  debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
   debug_assert_eq!(filtered_row.len(), previous_row.len());
   //
-  // Recon(x) = Filt(x) + floor((Recon(a) + Recon(b)) / 2)
-  //
-  // * (a + b)/2 has to be done with 16-bit precision
-  // * x + ave is done with u8_wrapping
-  //
-  let mut a: __m128i = ZEROED; // i16
-  filtered_row
-    .chunks_exact_mut(BYTES_PER_PIXEL)
-    .zip(previous_row.chunks_exact(BYTES_PER_PIXEL))
-    .for_each(|(x_chunk, b_chunk)| {
-      let mut x: __m128i = ZEROED; // u8
-      m128i_as_mut_u8s(&mut x)[..BYTES_PER_PIXEL].copy_from_slice(x_chunk);
-      let mut b: __m128i = ZEROED; // i16
-      m128i_as_mut_i16s(&mut b).iter_mut().zip(b_chunk.iter()).for_each(|(j, k)| *j = *k as i16);
-      {
-        let average = _mm_srai_epi16(_mm_add_epi16(a, b), 1);
-        let average_u8 = _mm_packus_epi16(average, ZEROED);
-        x = _mm_add_epi8(x, average_u8);
-      }
-      x_chunk.copy_from_slice(&m128i_as_mut_u8s(&mut x)[..BYTES_PER_PIXEL]);
-      a = _mm_unpacklo_epi8(x, ZEROED);
-    })
+  recon_average_rows::<BYTES_PER_PIXEL>(filtered_row, Some(previous_row))
 }
 
 /// Like [`recon_average_top_fallback`](super::recon_average_top_fallback), but
@@ -126,6 +289,18 @@ pub unsafe fn recon_average<const BYTES_PER_PIXEL: usize>(
 /// * The `sse2` CPU feature must be available at runtime.
 #[target_feature(enable = "sse2")]
 pub unsafe fn recon_average_top<const BYTES_PER_PIXEL: usize>(filtered_row: &mut [u8]) {
+  recon_average_rows::<BYTES_PER_PIXEL>(filtered_row, None)
+}
+
+/// The kernel of [`recon_average`] and [`recon_average_top`].
+///
+/// The top line is the same as any other line with `b` as 0, so both use this
+/// one per-pixel step, and only the loop over `previous_row` differs.
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn recon_average_rows<const BYTES_PER_PIXEL: usize>(
+  filtered_row: &mut [u8], previous_row: Option<&[u8]>,
+) {
   assert!(BYTES_PER_PIXEL <= 8);
   debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
   //
@@ -134,18 +309,32 @@ pub unsafe fn recon_average_top<const BYTES_PER_PIXEL: usize>(filtered_row: &mut
   // * (a + b)/2 has to be done with 16-bit precision
   // * x + ave is done with u8_wrapping
   //
-  let mut a: __m128i = ZEROED; // i16
-  filtered_row.chunks_exact_mut(BYTES_PER_PIXEL).for_each(|chunk| {
-    let mut x: __m128i = ZEROED; // u8
-    m128i_as_mut_u8s(&mut x)[..BYTES_PER_PIXEL].copy_from_slice(chunk);
+  let mut a: __m128i = _mm_setzero_si128(); // i16
+  let mut average_pixel = |x_chunk: &mut [u8], b: __m128i| {
+    let mut x: __m128i = _mm_setzero_si128(); // u8
+    as_mut_lanes::<u8, _>(&mut x)[..BYTES_PER_PIXEL].copy_from_slice(x_chunk);
     {
-      let half_a = _mm_srai_epi16(a, 1);
-      let half_a_u8 = _mm_packus_epi16(half_a, ZEROED);
-      x = _mm_add_epi8(x, half_a_u8);
+      let average = _mm_srai_epi16(_mm_add_epi16(a, b), 1);
+      let average_u8 = _mm_packus_epi16(average, _mm_setzero_si128());
+      x = _mm_add_epi8(x, average_u8);
     }
-    chunk.copy_from_slice(&m128i_as_mut_u8s(&mut x)[..BYTES_PER_PIXEL]);
-    a = _mm_unpacklo_epi8(x, ZEROED);
-  })
+    x_chunk.copy_from_slice(&as_mut_lanes::<u8, _>(&mut x)[..BYTES_PER_PIXEL]);
+    a = _mm_unpacklo_epi8(x, _mm_setzero_si128());
+  };
+  let x_chunks = filtered_row.chunks_exact_mut(BYTES_PER_PIXEL);
+  match previous_row {
+    Some(previous_row) => {
+      x_chunks.zip(previous_row.chunks_exact(BYTES_PER_PIXEL)).for_each(|(x_chunk, b_chunk)| {
+        let mut b: __m128i = _mm_setzero_si128(); // i16
+        as_mut_lanes::<i16, _>(&mut b)
+          .iter_mut()
+          .zip(b_chunk.iter())
+          .for_each(|(j, k)| *j = *k as i16);
+        average_pixel(x_chunk, b)
+      })
+    }
+    None => x_chunks.for_each(|x_chunk| average_pixel(x_chunk, _mm_setzero_si128())),
+  }
 }
 
 /// Like [`recon_paeth_fallback`](super::recon_paeth_fallback), but specialized
@@ -161,16 +350,19 @@ pub unsafe fn recon_paeth<const BYTES_PER_PIXEL: usize>(
   debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
   debug_assert_eq!(filtered_row.len(), previous_row.len());
   //
-  let mut a: __m128i = ZEROED; // i16
-  let mut c: __m128i = ZEROED; // i16
+  let mut a: __m128i = _mm_setzero_si128(); // i16
+  let mut c: __m128i = _mm_setzero_si128(); // i16
   filtered_row
     .chunks_exact_mut(BYTES_PER_PIXEL)
     .zip(previous_row.chunks_exact(BYTES_PER_PIXEL))
     .for_each(|(x_chunk, b_chunk)| {
-      let mut x: __m128i = ZEROED; // u8
-      m128i_as_mut_u8s(&mut x)[..BYTES_PER_PIXEL].copy_from_slice(x_chunk);
-      let mut b: __m128i = ZEROED; // i16
-      m128i_as_mut_i16s(&mut b).iter_mut().zip(b_chunk.iter()).for_each(|(j, k)| *j = *k as i16);
+      let mut x: __m128i = _mm_setzero_si128(); // u8
+      as_mut_lanes::<u8, _>(&mut x)[..BYTES_PER_PIXEL].copy_from_slice(x_chunk);
+      let mut b: __m128i = _mm_setzero_si128(); // i16
+      as_mut_lanes::<i16, _>(&mut b)
+        .iter_mut()
+        .zip(b_chunk.iter())
+        .for_each(|(j, k)| *j = *k as i16);
       {
         let p = _mm_sub_epi16(_mm_add_epi16(a, b), c);
         let pa = i16_abs_sse2(_mm_sub_epi16(p, a));
@@ -182,11 +374,148 @@ pub unsafe fn recon_paeth<const BYTES_PER_PIXEL: usize>(
         let pb_le_pc = i16_le_sse2(pb, pc);
         let pick_b_or_c = blend_tf_sse2(pb_le_pc, b, c);
         let paeth16 = blend_tf_sse2(pa_le_pb_and_pa_le_pc, a, pick_b_or_c);
-        let paeth = _mm_packus_epi16(paeth16, ZEROED);
+        let paeth = _mm_packus_epi16(paeth16, _mm_setzero_si128());
         x = _mm_add_epi8(x, paeth);
       }
-      x_chunk.copy_from_slice(&m128i_as_mut_u8s(&mut x)[..BYTES_PER_PIXEL]);
-      a = _mm_unpacklo_epi8(x, ZEROED);
+      x_chunk.copy_from_slice(&as_mut_lanes::<u8, _>(&mut x)[..BYTES_PER_PIXEL]);
+      a = _mm_unpacklo_epi8(x, _mm_setzero_si128());
       c = b;
     })
 }
+
+/// Loads 16 bytes from `row[i..]`, or as many as there are (the rest are 0).
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn load_up_to_16(row: &[u8], i: usize) -> __m128i {
+  if row.len() - i >= 16 {
+    _mm_loadu_si128(row[i..].as_ptr().cast())
+  } else {
+    let mut x: __m128i = _mm_setzero_si128();
+    as_mut_lanes::<u8, _>(&mut x)[..row.len() - i].copy_from_slice(&row[i..]);
+    x
+  }
+}
+/// Stores the first `n` bytes of `x` to `row[i..]`.
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn store_n(row: &mut [u8], i: usize, n: usize, mut x: __m128i) {
+  let out = &mut row[i..i + n];
+  if n == 12 {
+    _mm_storel_epi64(out.as_mut_ptr().cast(), x);
+    let high = _mm_cvtsi128_si32(_mm_srli_si128::<8>(x));
+    out[8..].copy_from_slice(&high.to_ne_bytes());
+  } else {
+    out.copy_from_slice(&as_mut_lanes::<u8, _>(&mut x)[..n]);
+  }
+}
+
+/// Lanes `0..6` of a register.
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn first_pixel_mask_6() -> __m128i {
+  _mm_set_epi8(0, 0, 0, 0, 0, 0, 0, 0, 0, 0, -1, -1, -1, -1, -1, -1)
+}
+
+/// Like [`recon_average`], but specialized to 6 bytes per pixel (eg: RGB with
+/// 16 bits per channel).
+///
+/// The generic version only fills 6 of the lanes in each register. This
+/// version loads two pixels (12 bytes) at once and stays with `u8` lanes the
+/// whole time, since `floor((a + b) / 2)` can be done without widening. The
+/// second pixel still needs the first pixel's result as its `a`, so each pair
+/// is done in two steps: first with just the left pixel as `a`, which gets the
+/// first pixel right, then again with that result shifted up by 6 lanes as the
+/// second pixel's `a`, which gets both pixels right.
+///
+/// This isn't selected by [`unfilter_lines`](crate::unfilter_lines). On a 4096
+/// byte row (x86_64) it takes 2.3us, the same as the fallback, and the generic
+/// `recon_average::<6>` takes 2.7us.
+///
+/// ## Panics
+/// * `debug_assert_eq!(filtered_row.len() % 6, 0);`
+/// * `debug_assert_eq!(filtered_row.len(), previous_row.len());`
+///
+/// ## Safety
+/// * The `sse2` CPU feature must be available at runtime.
+#[target_feature(enable = "sse2")]
+pub unsafe fn recon_average_6(filtered_row: &mut [u8], previous_row: &[u8]) {
+  debug_assert_eq!(filtered_row.len() % 6, 0);
+  debug_assert_eq!(filtered_row.len(), previous_row.len());
+  //
+  let len = filtered_row.len().min(previous_row.len()) / 6 * 6;
+  let first = first_pixel_mask_6();
+  // the pixel to the left, in lanes 0..6 (the other lanes are 0)
+  let mut a: __m128i = _mm_setzero_si128();
+  let mut i = 0;
+  while i < len {
+    let n = (len - i).min(12);
+    let x = load_up_to_16(filtered_row, i);
+    let b = load_up_to_16(previous_row, i);
+    let r1 = _mm_add_epi8(x, u8_floor_avg_sse2(a, b));
+    let a_both = _mm_or_si128(a, _mm_slli_si128::<6>(r1));
+    let r2 = _mm_add_epi8(x, u8_floor_avg_sse2(a_both, b));
+    store_n(filtered_row, i, n, r2);
+    a = _mm_and_si128(_mm_srli_si128::<6>(r2), first);
+    i += n;
+  }
+}
+
+/// Like [`recon_paeth`], but specialized to 6 bytes per pixel (eg: RGB with 16
+/// bits per channel).
+///
+/// This does two pixels at once like [`recon_average_6`] does, and it also
+/// stays with `u8` lanes by working out the predictor without any signed math:
+///
+/// * `pa = |b - c|` and `pb = |a - c|` are unsigned absolute differences.
+/// * `pc = |(a - c) + (b - c)|`, which is `pa + pb` when `a - c` and `b - c`
+///   have the same sign, and `|pa - pb|` otherwise. The sum can be over 255,
+///   but then it's at least as big as both `pa` and `pb` either way, so a
+///   saturating add gives the same result for every comparison we make.
+///
+/// This isn't selected by [`unfilter_lines`](crate::unfilter_lines). Fewer
+/// loads and stores don't make up for the predictor's longer chain of
+/// dependent operations: on a 4096 byte row (x86_64) it takes 5.5us, vs 4.7us
+/// for the generic `recon_paeth::<6>` and 3.9us for `sse4_1::recon_paeth::<6>`.
+///
+/// ## Panics
+/// * `debug_assert_eq!(filtered_row.len() % 6, 0);`
+/// * `debug_assert_eq!(filtered_row.len(), previous_row.len());`
+///
+/// ## Safety
+/// * The `sse2` CPU feature must be available at runtime.
+#[target_feature(enable = "sse2")]
+pub unsafe fn recon_paeth_6(filtered_row: &mut [u8], previous_row: &[u8]) {
+  debug_assert_eq!(filtered_row.len() % 6, 0);
+  debug_assert_eq!(filtered_row.len(), previous_row.len());
+  //
+  let len = filtered_row.len().min(previous_row.len()) / 6 * 6;
+  let first = first_pixel_mask_6();
+  // the pixels to the left and up-left, in lanes 0..6 (the other lanes are 0)
+  let mut a: __m128i = _mm_setzero_si128();
+  let mut c_first: __m128i = _mm_setzero_si128();
+  let mut i = 0;
+  while i < len {
+    let n = (len - i).min(12);
+    let x = load_up_to_16(filtered_row, i);
+    let b = load_up_to_16(previous_row, i);
+    // `c` for both pixels, and everything else that doesn't need `a`.
+    let c = _mm_or_si128(c_first, _mm_slli_si128::<6>(b));
+    let pa = u8_abs_diff_sse2(b, c);
+    let b_ge_c = u8_le_sse2(c, b);
+    let predict = |a: __m128i| {
+      let pb = u8_abs_diff_sse2(a, c);
+      let same_sign = _mm_cmpeq_epi8(u8_le_sse2(c, a), b_ge_c);
+      let pc = blend_tf_sse2(same_sign, _mm_adds_epu8(pa, pb), u8_abs_diff_sse2(pa, pb));
+      let pick_a = _mm_and_si128(u8_le_sse2(pa, pb), u8_le_sse2(pa, pc));
+      let b_or_c = blend_tf_sse2(u8_le_sse2(pb, pc), b, c);
+      blend_tf_sse2(pick_a, a, b_or_c)
+    };
+    let r1 = _mm_add_epi8(x, predict(a));
+    let a_both = _mm_or_si128(a, _mm_slli_si128::<6>(r1));
+    let r2 = _mm_add_epi8(x, predict(a_both));
+    store_n(filtered_row, i, n, r2);
+    a = _mm_and_si128(_mm_srli_si128::<6>(r2), first);
+    c_first = _mm_and_si128(_mm_srli_si128::<6>(b), first);
+    i += n;
+  }
+}
@@ -1,76 +1,149 @@
 #![cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 
-//! PNG filter functions specialized to the `sse2` cpu extension.
+//! PNG filter functions specialized to the `sse4.1` cpu extension.
 
 #[cfg(target_arch = "x86")]
 use core::arch::x86::*;
 #[cfg(target_arch = "x86_64")]
 use core::arch::x86_64::*;
 
-const ZEROED: __m128i = unsafe { core::mem::transmute([0_u64; 2]) };
-
-#[inline]
-fn m128i_as_mut_u8s(m: &mut __m128i) -> &mut [u8] {
-  let data = m as *mut __m128i as *mut u8;
-  let len = core::mem::size_of::<__m128i>() / core::mem::size_of::<u8>();
-  unsafe { core::slice::from_raw_parts_mut(data, len) }
-}
-#[inline]
-fn m128i_as_mut_i16s(m: &mut __m128i) -> &mut [i16] {
-  let data = m as *mut __m128i as *mut i16;
-  let len = core::mem::size_of::<__m128i>() / core::mem::size_of::<i16>();
-  unsafe { core::slice::from_raw_parts_mut(data, len) }
-}
-
-/// We always have to emulate this
-#[inline]
-#[target_feature(enable = "sse2")]
-unsafe fn i16_le_sse2(a: __m128i, b: __m128i) -> __m128i {
-  let lt = _mm_cmplt_epi16(a, b);
-  let eq = _mm_cmpeq_epi16(a, b);
-  _mm_or_si128(lt, eq)
-}
+use crate::simd_bytes::as_mut_lanes;
 
 /// Like [`recon_sub_fallback`](super::recon_sub_fallback), but specialized to
-/// `sse2`.
+/// `sse4.1`.
 ///
 /// ## Safety
-/// * The `sse2` CPU feature must be available at runtime.
+/// * The `sse4.1` CPU feature must be available at runtime.
 #[target_feature(enable = "sse4.1")]
 pub unsafe fn recon_sub<const BYTES_PER_PIXEL: usize>(filtered_row: &mut [u8]) {
   assert!(BYTES_PER_PIXEL <= 8);
   debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
   //
-  let mut a: __m128i = ZEROED;
+  // Note: we always zero vectors with `_mm_setzero_si128` rather than
+  // `mem::zeroed` or a transmute, so there's no unsafe type punning for Miri
+  // (or a reader) to worry about.
+  let mut a: __m128i = _mm_setzero_si128();
   filtered_row.chunks_exact_mut(BYTES_PER_PIXEL).for_each(|chunk| {
-    let mut x: __m128i = ZEROED;
-    m128i_as_mut_u8s(&mut x)[..BYTES_PER_PIXEL].copy_from_slice(chunk);
-    x = unsafe { _mm_add_epi8(x, a) };
-    chunk.copy_from_slice(&m128i_as_mut_u8s(&mut x)[..BYTES_PER_PIXEL]);
+    let mut x: __m128i = _mm_setzero_si128();
+    as_mut_lanes::<u8, _>(&mut x)[..BYTES_PER_PIXEL].copy_from_slice(chunk);
+    x = _mm_add_epi8(x, a);
+    chunk.copy_from_slice(&as_mut_lanes::<u8, _>(&mut x)[..BYTES_PER_PIXEL]);
     a = x;
   })
 }
 
+/// Like [`recon_sub`], but using a prefix sum over as many whole pixels as fit
+/// in 16 bytes, for the pixel sizes that don't evenly divide 16.
+///
+/// [`sse2::recon_sub_prefix`](crate::sse2::recon_sub_prefix) needs each 16
+/// byte block to be whole pixels. Here each block is instead the `16 /
+/// BYTES_PER_PIXEL` whole pixels at the start of a 16 byte load (15 bytes for
+/// 3 and 5, 12 for 6, 14 for 7), and the next load starts right after them.
+/// All the lane movement is done with `_mm_shuffle_epi8` (`ssse3`), using
+/// masks built once per call:
+/// * Shifting the block up by 1, 2, 4, and 8 pixels (while that's still within
+///   the block) and adding gives each pixel the sum of itself and every pixel
+///   before it in the block.
+/// * The last pixel of the previous block is copied into every pixel position
+///   and added on.
+/// * The bytes after the block are put back to what was loaded, so the full 16
+///   byte store doesn't change the data that the next load will read.
+///
+/// Any bytes after the last full 16 byte load are done one pixel at a time.
+///
+/// ## Panics
+/// * `assert!(BYTES_PER_PIXEL <= 8);`
+/// * `debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);`
+///
+/// ## Safety
+/// * The `sse4.1` CPU feature must be available at runtime.
+#[target_feature(enable = "sse4.1")]
+pub unsafe fn recon_sub_odd<const BYTES_PER_PIXEL: usize>(filtered_row: &mut [u8]) {
+  assert!(BYTES_PER_PIXEL <= 8);
+  debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
+  //
+  let block = (16 / BYTES_PER_PIXEL) * BYTES_PER_PIXEL;
+  // Shuffle indexes with the high bit set give 0 in that lane.
+  let shift = |pixels: usize| -> [u8; 16] {
+    let bytes = pixels * BYTES_PER_PIXEL;
+    core::array::from_fn(|lane| if lane >= bytes { (lane - bytes) as u8 } else { 0x80 })
+  };
+  let broadcast_last: [u8; 16] = core::array::from_fn(|lane| {
+    if lane < block {
+      (block - BYTES_PER_PIXEL + lane % BYTES_PER_PIXEL) as u8
+    } else {
+      0x80
+    }
+  });
+  let after_block: [u8; 16] = core::array::from_fn(|lane| if lane >= block { 0xFF } else { 0 });
+  let shift_1 = _mm_loadu_si128(shift(1).as_ptr().cast());
+  let shift_2 = _mm_loadu_si128(shift(2).as_ptr().cast());
+  let shift_4 = _mm_loadu_si128(shift(4).as_ptr().cast());
+  let shift_8 = _mm_loadu_si128(shift(8).as_ptr().cast());
+  let broadcast_last = _mm_loadu_si128(broadcast_last.as_ptr().cast());
+  let after_block = _mm_loadu_si128(after_block.as_ptr().cast());
+  //
+  // Each load overlaps the end of the previous store, and a load that only
+  // partly overlaps a recent store can't be forwarded from it, which stalls
+  // for many cycles. So we load each block before storing the one before it.
+  let mut previous: __m128i = _mm_setzero_si128();
+  let mut i = 0;
+  let len = filtered_row.len();
+  if len >= 16 {
+    let ptr = filtered_row.as_mut_ptr();
+    let mut loaded = _mm_loadu_si128(ptr.cast());
+    loop {
+      let mut x = _mm_add_epi8(loaded, _mm_shuffle_epi8(loaded, shift_1));
+      if 2 * BYTES_PER_PIXEL < block {
+        x = _mm_add_epi8(x, _mm_shuffle_epi8(x, shift_2));
+      }
+      if 4 * BYTES_PER_PIXEL < block {
+        x = _mm_add_epi8(x, _mm_shuffle_epi8(x, shift_4));
+      }
+      if 8 * BYTES_PER_PIXEL < block {
+        x = _mm_add_epi8(x, _mm_shuffle_epi8(x, shift_8));
+      }
+      x = _mm_add_epi8(x, _mm_shuffle_epi8(previous, broadcast_last));
+      let next_i = i + block;
+      let has_next = len - next_i >= 16;
+      let next = if has_next { _mm_loadu_si128(ptr.add(next_i).cast()) } else { loaded };
+      _mm_storeu_si128(ptr.add(i).cast(), _mm_blendv_epi8(x, loaded, after_block));
+      previous = x;
+      i = next_i;
+      if !has_next {
+        break;
+      }
+      loaded = next;
+    }
+  }
+  let mut a: [u8; BYTES_PER_PIXEL] = [0; BYTES_PER_PIXEL];
+  if i > 0 {
+    a.copy_from_slice(&filtered_row[i - BYTES_PER_PIXEL..i]);
+  }
+  filtered_row[i..].chunks_exact_mut(BYTES_PER_PIXEL).for_each(|chunk| {
+    chunk.iter_mut().zip(a.iter()).for_each(|(x, a)| *x = x.wrapping_add(*a));
+    a.copy_from_slice(chunk);
+  });
+}
+
 /// Like [`recon_up_fallback`](super::recon_up_fallback), but specialized to
-/// `sse2`.
+/// `sse4.1`.
 ///
-/// You normally don't need to use this function. On Rust's `x86_64` and `i686`
-/// targets the `sse2` feature is enabled by default, and the auto-vectorizer is
-/// able to SIMD accelerate the normal fallback function quite well, so you can
-/// just use that. You only need to call this version with `i585` targets when
-/// you've dynamically detected that `sse2` is available.
+/// "up" is one byte add per byte, and `sse4.1` has nothing better for that than
+/// `sse2`'s 16-byte adds, so this is [`sse2::recon_up`](crate::sse2::recon_up).
+/// It's here so that the `sse4_1` module has the full set of functions.
 ///
 /// ## Safety
-/// * The `sse2` CPU feature must be available at runtime.
+/// * The `sse4.1` CPU feature must be available at runtime.
 #[target_feature(enable = "sse4.1")]
 pub unsafe fn recon_up(filtered_row: &mut [u8], previous_row: &[u8]) {
-  debug_assert_eq!(filtered_row.len(), previous_row.len());
-  //
-  filtered_row.iter_mut().zip(previous_row.iter()).for_each(|(x, b)| *x = x.wrapping_add(*b))
+  // Safety: `sse4.1` implies `sse2`.
+  crate::sse2::recon_up(filtered_row, previous_row)
 }
 
 /// Like [`recon_average_fallback`](super::recon_average_fallback), but
-/// specialized to `sse2`.
+/// specialized to `sse2`. It doesn't use anything from `sse4.1`, and only
+/// lives here so that the `sse4_1` module has the full set of functions.
 ///
 /// ## Safety
 /// * The `sse2` CPU feature must be available at runtime.
@@ -78,41 +151,30 @@ pub unsafe fn recon_up(filtered_row: &mut [u8], previous_row: &[u8]) {
 pub unsafe fn recon_average<const BYTES_PER_PIXEL: usize>(
   filtered_row: &mut [u8], previous_row: &[u8],
 ) {
-  assert!(BYTES_PER_PIXEL <= 8);
-  debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
   debug_assert_eq!(filtered_row.len(), previous_row.len());
   //
-  // Recon(x) = Filt(x) + floor((Recon(a) + Recon(b)) / 2)
-  //
-  // * (a + b)/2 has to be done with 16-bit precision
-  // * x + ave is done with u8_wrapping
-  //
-  let mut a: __m128i = ZEROED; // i16
-  filtered_row
-    .chunks_exact_mut(BYTES_PER_PIXEL)
-    .zip(previous_row.chunks_exact(BYTES_PER_PIXEL))
-    .for_each(|(x_chunk, b_chunk)| {
-      let mut x: __m128i = ZEROED; // u8
-      m128i_as_mut_u8s(&mut x)[..BYTES_PER_PIXEL].copy_from_slice(x_chunk);
-      let mut b: __m128i = ZEROED; // i16
-      m128i_as_mut_i16s(&mut b).iter_mut().zip(b_chunk.iter()).for_each(|(j, k)| *j = *k as i16);
-      {
-        let average = _mm_srai_epi16(_mm_add_epi16(a, b), 1);
-        let average_u8 = _mm_packus_epi16(average, ZEROED);
-        x = _mm_add_epi8(x, average_u8);
-      }
-      x_chunk.copy_from_slice(&m128i_as_mut_u8s(&mut x)[..BYTES_PER_PIXEL]);
-      a = _mm_unpacklo_epi8(x, ZEROED);
-    })
+  recon_average_rows::<BYTES_PER_PIXEL>(filtered_row, Some(previous_row))
 }
 
 /// Like [`recon_average_top_fallback`](super::recon_average_top_fallback), but
-/// specialized to `sse2`.
+/// specialized to `sse4.1`.
 ///
 /// ## Safety
-/// * The `sse2` CPU feature must be available at runtime.
+/// * The `sse4.1` CPU feature must be available at runtime.
 #[target_feature(enable = "sse4.1")]
 pub unsafe fn recon_average_top<const BYTES_PER_PIXEL: usize>(filtered_row: &mut [u8]) {
+  recon_average_rows::<BYTES_PER_PIXEL>(filtered_row, None)
+}
+
+/// The kernel of [`recon_average`] and [`recon_average_top`].
+///
+/// The top line is the same as any other line with `b` as 0, so both use this
+/// one per-pixel step, and only the loop over `previous_row` differs.
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn recon_average_rows<const BYTES_PER_PIXEL: usize>(
+  filtered_row: &mut [u8], previous_row: Option<&[u8]>,
+) {
   assert!(BYTES_PER_PIXEL <= 8);
   debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
   //
@@ -121,59 +183,220 @@ pub unsafe fn recon_average_top<const BYTES_PER_PIXEL: usize>(filtered_row: &mut
   // * (a + b)/2 has to be done with 16-bit precision
   // * x + ave is done with u8_wrapping
   //
-  let mut a: __m128i = ZEROED; // i16
-  filtered_row.chunks_exact_mut(BYTES_PER_PIXEL).for_each(|chunk| {
-    let mut x: __m128i = ZEROED; // u8
-    m128i_as_mut_u8s(&mut x)[..BYTES_PER_PIXEL].copy_from_slice(chunk);
+  let mut a: __m128i = _mm_setzero_si128(); // i16
+  let mut average_pixel = |x_chunk: &mut [u8], b: __m128i| {
+    let mut x: __m128i = _mm_setzero_si128(); // u8
+    as_mut_lanes::<u8, _>(&mut x)[..BYTES_PER_PIXEL].copy_from_slice(x_chunk);
     {
-      let half_a = _mm_srai_epi16(a, 1);
-      let half_a_u8 = _mm_packus_epi16(half_a, ZEROED);
-      x = _mm_add_epi8(x, half_a_u8);
+      // `a` and `b` are both zero-extended bytes, so `a + b` is at most 510
+      // and never negative. That's what makes the arithmetic shift the same
+      // as a logical one (and the pack never saturate). If either is ever
+      // loaded sign-extended instead, bytes of 128 or more would break.
+      let average = _mm_srai_epi16(_mm_add_epi16(a, b), 1);
+      let average_u8 = _mm_packus_epi16(average, _mm_setzero_si128());
+      x = _mm_add_epi8(x, average_u8);
     }
-    chunk.copy_from_slice(&m128i_as_mut_u8s(&mut x)[..BYTES_PER_PIXEL]);
-    a = _mm_unpacklo_epi8(x, ZEROED);
-  })
+    x_chunk.copy_from_slice(&as_mut_lanes::<u8, _>(&mut x)[..BYTES_PER_PIXEL]);
+    a = _mm_unpacklo_epi8(x, _mm_setzero_si128());
+  };
+  let x_chunks = filtered_row.chunks_exact_mut(BYTES_PER_PIXEL);
+  match previous_row {
+    Some(previous_row) => {
+      x_chunks.zip(previous_row.chunks_exact(BYTES_PER_PIXEL)).for_each(|(x_chunk, b_chunk)| {
+        let mut b: __m128i = _mm_setzero_si128(); // i16
+        as_mut_lanes::<i16, _>(&mut b)
+          .iter_mut()
+          .zip(b_chunk.iter())
+          .for_each(|(j, k)| *j = *k as i16);
+        average_pixel(x_chunk, b)
+      })
+    }
+    None => x_chunks.for_each(|x_chunk| average_pixel(x_chunk, _mm_setzero_si128())),
+  }
 }
 
 /// Like [`recon_paeth_fallback`](super::recon_paeth_fallback), but specialized
-/// to `sse2`.
+/// to `sse4.1`.
+///
+/// The loop is software pipelined: the part of each pixel's work that doesn't
+/// depend on `a` (the pixel to the left, which we've only just reconstructed)
+/// is done one pixel ahead, so that it can overlap with the serial part.
+/// Since `p = a + b - c` we have `pa = |b - c|`, `pb = |a - c|`, and
+/// `pc = |(a - c) + (b - c)|`, so only `pb` and `pc` have to wait on `a`.
+///
+/// At 8 bytes per pixel this is [`recon_paeth_8`] instead (on a 4096 byte row,
+/// 1.7us vs 2.5us for the pipelined loop).
 ///
 /// ## Safety
-/// * The `sse2` CPU feature must be available at runtime.
+/// * The `sse4.1` CPU feature must be available at runtime.
 #[target_feature(enable = "sse4.1")]
 pub unsafe fn recon_paeth<const BYTES_PER_PIXEL: usize>(
   filtered_row: &mut [u8], previous_row: &[u8],
+) {
+  if BYTES_PER_PIXEL == 8 {
+    recon_paeth_8(filtered_row, previous_row)
+  } else {
+    recon_paeth_pipelined::<BYTES_PER_PIXEL, false>(filtered_row, previous_row)
+  }
+}
+
+/// Like [`recon_paeth`], but picks the predictor by finding the smallest of
+/// `pa`, `pb`, and `pc` with `_mm_min_epi16`.
+///
+/// With `m = min(pa, pb, pc)`, the spec's tie-break order (`a`, then `b`,
+/// then `c`) becomes: `a` if `pa == m`, else `b` if `pb == m`, else `c`. That's
+/// two `min` and two `cmpeq`, against one `min` and two `cmpgt` for
+/// [`recon_paeth`], with the same two blends.
+///
+/// This isn't selected by [`unfilter_lines`](crate::unfilter_lines). On a
+/// 4032 byte row (x86_64) it's a little slower than the pipelined loop of
+/// `recon_paeth`: 5.9us vs 5.4us at bpp 4, and 2.9us vs 2.7us at bpp 8.
+///
+/// ## Safety
+/// * The `sse4.1` CPU feature must be available at runtime.
+#[target_feature(enable = "sse4.1")]
+pub unsafe fn recon_paeth_min<const BYTES_PER_PIXEL: usize>(
+  filtered_row: &mut [u8], previous_row: &[u8],
+) {
+  recon_paeth_pipelined::<BYTES_PER_PIXEL, true>(filtered_row, previous_row)
+}
+
+/// The loop of [`recon_paeth`] and [`recon_paeth_min`], which only differ in
+/// how the predictor is picked.
+#[inline]
+#[target_feature(enable = "sse4.1")]
+unsafe fn recon_paeth_pipelined<const BYTES_PER_PIXEL: usize, const MIN_PICK: bool>(
+  filtered_row: &mut [u8], previous_row: &[u8],
 ) {
   assert!(BYTES_PER_PIXEL <= 8);
   debug_assert_eq!(filtered_row.len() % BYTES_PER_PIXEL, 0);
   debug_assert_eq!(filtered_row.len(), previous_row.len());
   //
-  let mut a: __m128i = ZEROED; // i16
-  let mut c: __m128i = ZEROED; // i16
-  filtered_row
-    .chunks_exact_mut(BYTES_PER_PIXEL)
-    .zip(previous_row.chunks_exact(BYTES_PER_PIXEL))
-    .for_each(|(x_chunk, b_chunk)| {
-      let mut x: __m128i = ZEROED; // u8
-      m128i_as_mut_u8s(&mut x)[..BYTES_PER_PIXEL].copy_from_slice(x_chunk);
-      let mut b: __m128i = ZEROED; // i16
-      m128i_as_mut_i16s(&mut b).iter_mut().zip(b_chunk.iter()).for_each(|(j, k)| *j = *k as i16);
-      {
-        let p = _mm_sub_epi16(_mm_add_epi16(a, b), c);
-        let pa = _mm_abs_epi16(_mm_sub_epi16(p, a));
-        let pb = _mm_abs_epi16(_mm_sub_epi16(p, b));
-        let pc = _mm_abs_epi16(_mm_sub_epi16(p, c));
-        let pa_le_pb = i16_le_sse2(pa, pb);
-        let pa_le_pc = i16_le_sse2(pa, pc);
-        let pa_le_pb_and_pa_le_pc = _mm_and_si128(pa_le_pb, pa_le_pc);
-        let pb_le_pc = i16_le_sse2(pb, pc);
-        let pick_b_or_c = _mm_blendv_epi8(c, b, pb_le_pc);
-        let paeth16 = _mm_blendv_epi8(pick_b_or_c, a, pa_le_pb_and_pa_le_pc);
-        let paeth = _mm_packus_epi16(paeth16, ZEROED);
-        x = _mm_add_epi8(x, paeth);
+  let mut x_chunks = filtered_row.chunks_exact_mut(BYTES_PER_PIXEL);
+  let mut b_chunks = previous_row.chunks_exact(BYTES_PER_PIXEL);
+  let Some((mut x_chunk, b_chunk)) = x_chunks.next().zip(b_chunks.next()) else {
+    return;
+  };
+  let mut a: __m128i = _mm_setzero_si128(); // i16
+  let mut c: __m128i = _mm_setzero_si128(); // i16
+  let (mut x, mut b) = load_paeth_pixel::<BYTES_PER_PIXEL>(x_chunk, b_chunk);
+  let mut b_minus_c = b;
+  loop {
+    // Stage 1 of the next pixel: load it and get its `b - c`.
+    let next = x_chunks.next().zip(b_chunks.next());
+    let (next_x, next_b) = match &next {
+      Some((next_x_chunk, next_b_chunk)) => {
+        load_paeth_pixel::<BYTES_PER_PIXEL>(next_x_chunk, next_b_chunk)
       }
-      x_chunk.copy_from_slice(&m128i_as_mut_u8s(&mut x)[..BYTES_PER_PIXEL]);
-      a = _mm_unpacklo_epi8(x, ZEROED);
+      None => (_mm_setzero_si128(), _mm_setzero_si128()),
+    };
+    let next_b_minus_c = _mm_sub_epi16(next_b, b);
+
+    // Stage 2 of this pixel: everything that needs `a`.
+    let pa = _mm_abs_epi16(b_minus_c);
+    let a_minus_c = _mm_sub_epi16(a, c);
+    let pb = _mm_abs_epi16(a_minus_c);
+    let pc = _mm_abs_epi16(_mm_add_epi16(a_minus_c, b_minus_c));
+    let paeth16 =
+      if MIN_PICK { paeth_pick_min(a, b, c, pa, pb, pc) } else { paeth_pick(a, b, c, pa, pb, pc) };
+    let paeth = _mm_packus_epi16(paeth16, _mm_setzero_si128());
+    x = _mm_add_epi8(x, paeth);
+    x_chunk.copy_from_slice(&as_mut_lanes::<u8, _>(&mut x)[..BYTES_PER_PIXEL]);
+    a = _mm_unpacklo_epi8(x, _mm_setzero_si128());
+    c = b;
+
+    let Some((next_x_chunk, _)) = next else {
+      return;
+    };
+    x_chunk = next_x_chunk;
+    x = next_x;
+    b = next_b;
+    b_minus_c = next_b_minus_c;
+  }
+}
+
+/// As [`recon_paeth`], specialized to 8 bytes per pixel (such as RGBA16).
+///
+/// At 8 bytes per pixel one pixel fills all 8 `i16` lanes, so there's nothing
+/// to gain from packing pixels together. Instead this shortens the chain from
+/// one pixel's `a` to the next:
+/// * `x` is widened to `i16` when it's loaded, and the predictor is added in
+///   `i16` lanes and masked back down to a byte. That gives the next `a`
+///   directly, instead of packing to `u8`, adding, and unpacking again.
+/// * The packing for the store is off of that chain.
+/// * Loads and stores are single 8 byte moves, rather than a lane at a time.
+///
+/// ## Safety
+/// * The `sse4.1` CPU feature must be available at runtime.
+#[target_feature(enable = "sse4.1")]
+pub unsafe fn recon_paeth_8(filtered_row: &mut [u8], previous_row: &[u8]) {
+  debug_assert_eq!(filtered_row.len() % 8, 0);
+  debug_assert_eq!(filtered_row.len(), previous_row.len());
+  //
+  let low_byte = _mm_set1_epi16(0xFF);
+  let mut a: __m128i = _mm_setzero_si128(); // i16
+  let mut c: __m128i = _mm_setzero_si128(); // i16
+  filtered_row.chunks_exact_mut(8).zip(previous_row.chunks_exact(8)).for_each(
+    |(x_chunk, b_chunk)| {
+      // Safety: each chunk is exactly 8 bytes, and these are unaligned moves.
+      let x = _mm_cvtepu8_epi16(_mm_loadl_epi64(x_chunk.as_ptr().cast()));
+      let b = _mm_cvtepu8_epi16(_mm_loadl_epi64(b_chunk.as_ptr().cast()));
+      let b_minus_c = _mm_sub_epi16(b, c);
+      let a_minus_c = _mm_sub_epi16(a, c);
+      let pa = _mm_abs_epi16(b_minus_c);
+      let pb = _mm_abs_epi16(a_minus_c);
+      let pc = _mm_abs_epi16(_mm_add_epi16(a_minus_c, b_minus_c));
+      let paeth = paeth_pick(a, b, c, pa, pb, pc);
+      a = _mm_and_si128(_mm_add_epi16(x, paeth), low_byte);
       c = b;
-    })
+      _mm_storel_epi64(x_chunk.as_mut_ptr().cast(), _mm_packus_epi16(a, a));
+    },
+  )
+}
+
+/// Picks `a`, `b`, or `c` (all `i16`) given their distances from `p`, with the
+/// spec's tie-break order.
+///
+/// The spec picks `a` if `pa <= pb && pa <= pc`, else `b` if `pb <= pc`, else
+/// `c`. SSE only has a signed "greater than", so instead of building each
+/// `<=` (compare, compare equal, or) we flip the conditions around and swap
+/// the blend inputs to match:
+/// * `b` unless `pb > pc`
+/// * `a` unless `pa > min(pb, pc)`
+///
+/// That's three operations for the masks instead of ten. On a 4032 byte row
+/// `recon_paeth` runs at the same speed as before (within noise, for every
+/// bytes per pixel), since the loop is bound by the latency of `a` from one
+/// pixel to the next rather than by the instruction count.
+#[inline]
+#[target_feature(enable = "sse4.1")]
+unsafe fn paeth_pick(
+  a: __m128i, b: __m128i, c: __m128i, pa: __m128i, pb: __m128i, pc: __m128i,
+) -> __m128i {
+  let pick_b_or_c = _mm_blendv_epi8(b, c, _mm_cmpgt_epi16(pb, pc));
+  _mm_blendv_epi8(a, pick_b_or_c, _mm_cmpgt_epi16(pa, _mm_min_epi16(pb, pc)))
+}
+
+/// As [`paeth_pick`], but by finding the smallest distance first.
+#[inline]
+#[target_feature(enable = "sse4.1")]
+unsafe fn paeth_pick_min(
+  a: __m128i, b: __m128i, c: __m128i, pa: __m128i, pb: __m128i, pc: __m128i,
+) -> __m128i {
+  let min = _mm_min_epi16(pa, _mm_min_epi16(pb, pc));
+  let pick_b_or_c = _mm_blendv_epi8(c, b, _mm_cmpeq_epi16(pb, min));
+  _mm_blendv_epi8(pick_b_or_c, a, _mm_cmpeq_epi16(pa, min))
+}
+
+/// Loads one pixel of filtered data (as `u8`) and the previous row (as `i16`).
+#[inline]
+#[target_feature(enable = "sse4.1")]
+unsafe fn load_paeth_pixel<const BYTES_PER_PIXEL: usize>(
+  x_chunk: &[u8], b_chunk: &[u8],
+) -> (__m128i, __m128i) {
+  let mut x: __m128i = _mm_setzero_si128(); // u8
+  as_mut_lanes::<u8, _>(&mut x)[..BYTES_PER_PIXEL].copy_from_slice(x_chunk);
+  let mut b: __m128i = _mm_setzero_si128(); // i16
+  as_mut_lanes::<i16, _>(&mut b).iter_mut().zip(b_chunk.iter()).for_each(|(j, k)| *j = *k as i16);
+  (x, b)
 }
@@ -0,0 +1,106 @@
+//! Measures how long each filter type takes, for profiling a decoder.
+//!
+//! This module only exists when the `timing` feature is enabled. With the
+//! feature on, [`unfilter_lines_timed`] times each call of a reconstruction
+//! function with [`Instant`] and adds it up per filter type. With the feature
+//! off the timing code isn't compiled at all.
+//!
+//! The timings are wall-clock time, including the overhead of reading the
+//! clock twice per line, so on narrow images they'll overstate the cost of the
+//! filters themselves. They're best used to compare the filter types against
+//! each other.
+
+use std::{
+  cell::Cell,
+  slice::ChunksExactMut,
+  time::{Duration, Instant},
+};
+
+use crate::Kernel;
+
+/// The total time spent in each reconstruction function.
+///
+/// The fields match [`BackendReport`](crate::BackendReport): "average" on the
+/// top line of the image is `average_top`, and "paeth" on the top line is
+/// counted as `sub` (since that's what it does there). A line below a row
+/// that's all zero is done the same way as the top line, so it's counted the
+/// same way too, and on images with black or transparent areas some of the
+/// "paeth" time shows up as `sub`. Lines with filter type 0 or an invalid
+/// filter type aren't timed, and neither are "sub" lines one pixel wide (which
+/// have nothing to do).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FilterTimings {
+  /// Time in the "sub" function.
+  pub sub: Duration,
+  /// Time in the "up" function.
+  pub up: Duration,
+  /// Time in the "average" function.
+  pub average: Duration,
+  /// Time in the "average" function for the top line (or below a zero row).
+  pub average_top: Duration,
+  /// Time in the "paeth" function.
+  pub paeth: Duration,
+}
+impl FilterTimings {
+  /// The time spent in all the functions together.
+  #[inline]
+  #[must_use]
+  pub fn total(&self) -> Duration {
+    self.sub + self.up + self.average + self.average_top + self.paeth
+  }
+}
+
+thread_local! {
+  /// The timings of the current [`unfilter_lines_timed`] call on this thread,
+  /// or `None` when there isn't one.
+  static TIMINGS: Cell<Option<FilterTimings>> = const { Cell::new(None) };
+}
+
+/// As [`unfilter_lines`](crate::unfilter_lines), but returns the time spent
+/// in each reconstruction function.
+///
+/// ## Panics
+/// * As [`unfilter_lines`](crate::unfilter_lines)
+#[track_caller]
+pub fn unfilter_lines_timed<const BYTES_PER_PIXEL: usize>(
+  lines: ChunksExactMut<'_, u8>,
+) -> FilterTimings {
+  /// Stops the timing even if `unfilter_lines` panics.
+  struct Stop;
+  impl Drop for Stop {
+    fn drop(&mut self) {
+      TIMINGS.with(|timings| timings.set(None));
+    }
+  }
+  //
+  TIMINGS.with(|timings| timings.set(Some(FilterTimings::default())));
+  let stop = Stop;
+  let _ = crate::unfilter_lines::<BYTES_PER_PIXEL>(lines);
+  let timings = TIMINGS.with(|timings| timings.get()).unwrap_or_default();
+  drop(stop);
+  timings
+}
+
+/// Runs `recon`, and adds the time it took to `kernel` if there's an
+/// [`unfilter_lines_timed`] call on this thread.
+#[inline]
+pub(crate) fn time_kernel(kernel: Kernel, recon: impl FnOnce()) {
+  if TIMINGS.with(|timings| timings.get()).is_none() {
+    return recon();
+  }
+  let start = Instant::now();
+  recon();
+  let elapsed = start.elapsed();
+  TIMINGS.with(|timings| {
+    if let Some(mut t) = timings.get() {
+      match kernel {
+        Kernel::Sub => t.sub += elapsed,
+        Kernel::Up => t.up += elapsed,
+        Kernel::Average => t.average += elapsed,
+        Kernel::AverageTop => t.average_top += elapsed,
+        Kernel::Paeth => t.paeth += elapsed,
+      }
+      timings.set(Some(t));
+    }
+  });
+}
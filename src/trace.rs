@@ -0,0 +1,56 @@
+//! Reports which backend gets selected, for diagnosing performance.
+//!
+//! This module only exists when the `trace` feature is enabled. With the
+//! feature on, each time [`unfilter_lines`](crate::unfilter_lines) (or another
+//! unfiltering function) selects its reconstruction functions it passes a
+//! [`TraceEvent`] to the hook set with [`set_trace_hook`]. With the feature off
+//! there's no code for any of this at all.
+//!
+//! The crate doesn't depend on any logging crate, so you forward the events to
+//! whatever logging you use:
+//!
+//! ```
+//! png_filters::trace::set_trace_hook(Some(|event| eprintln!("{event}")));
+//! ```
+
+use std::sync::RwLock;
+
+use crate::BackendReport;
+
+/// The backends selected for one call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceEvent {
+  /// The `BYTES_PER_PIXEL` of the call.
+  pub bytes_per_pixel: usize,
+  /// The backend used for each filter type.
+  pub report: BackendReport,
+}
+impl core::fmt::Display for TraceEvent {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    let Self { bytes_per_pixel, report } = self;
+    write!(
+      f,
+      "png_filters bpp={bytes_per_pixel} sub={:?} up={:?} average={:?} average_top={:?} paeth={:?}",
+      report.sub, report.up, report.average, report.average_top, report.paeth
+    )
+  }
+}
+
+static TRACE_HOOK: RwLock<Option<fn(&TraceEvent)>> = RwLock::new(None);
+
+/// Sets the function that gets each [`TraceEvent`], or `None` to stop tracing.
+///
+/// There's only one hook for the whole program, so this replaces any previous
+/// hook.
+pub fn set_trace_hook(hook: Option<fn(&TraceEvent)>) {
+  *TRACE_HOOK.write().unwrap_or_else(|poison| poison.into_inner()) = hook;
+}
+
+/// Sends an event to the hook, if there is one.
+#[inline]
+pub(crate) fn emit(bytes_per_pixel: usize, report: BackendReport) {
+  let hook = *TRACE_HOOK.read().unwrap_or_else(|poison| poison.into_inner());
+  if let Some(hook) = hook {
+    hook(&TraceEvent { bytes_per_pixel, report });
+  }
+}
@@ -0,0 +1,80 @@
+//! Functions in this module expand packed low bit depth samples.
+//!
+//! PNG images with a bit depth below 8 are filtered on whole bytes (so you
+//! unfilter them with `BYTES_PER_PIXEL = 1`), but each byte holds more than
+//! one sample. After unfiltering, these functions can expand each row out to
+//! one sample per byte.
+
+/// The bit depth of a packed row of samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BitDepth {
+  /// 8 samples per byte.
+  One = 1,
+  /// 4 samples per byte.
+  Two = 2,
+  /// 2 samples per byte.
+  Four = 4,
+}
+impl BitDepth {
+  /// The number of bits used by each sample.
+  #[inline]
+  #[must_use]
+  pub const fn bits(self) -> usize {
+    self as usize
+  }
+
+  /// The number of bytes that a packed row of `width` samples will use.
+  ///
+  /// The last byte of a row is padded if `width` samples don't exactly fill
+  /// it.
+  #[inline]
+  #[must_use]
+  pub const fn packed_len(self, width: usize) -> usize {
+    (width * self.bits()).div_ceil(8)
+  }
+}
+
+/// Expands a row of packed samples so that each sample is one byte.
+///
+/// The `row` should contain (at least) the packed bytes for `width` samples,
+/// without the filter byte. Samples are unpacked from the high bits of each
+/// byte to the low bits, as the PNG spec requires. After this call the `row`
+/// will have exactly `width` elements, each in the range `0 .. 2^bit_depth`.
+///
+/// ## Panics
+/// * `assert!(row.len() >= bit_depth.packed_len(width));`
+#[track_caller]
+pub fn unpack_bits(row: &mut Vec<u8>, bit_depth: BitDepth, width: usize) {
+  let bits = bit_depth.bits();
+  assert!(row.len() >= bit_depth.packed_len(width));
+  //
+  row.truncate(bit_depth.packed_len(width));
+  row.resize(width, 0);
+  let mask: u8 = (1 << bits) - 1;
+  // We go from back to front, so that the packed byte for a sample is never
+  // overwritten before we get to read it.
+  for i in (0..width).rev() {
+    let bit_offset = i * bits;
+    let shift = 8 - bits - (bit_offset % 8);
+    row[i] = (row[bit_offset / 8] >> shift) & mask;
+  }
+}
+
+/// As [`unpack_bits`], but also scales each sample to the full `0 ..= 255`
+/// range.
+///
+/// This is the same as multiplying each sample by `255 / (2^bit_depth - 1)`,
+/// so it's appropriate for grayscale data, but *not* for indexed color data.
+///
+/// ## Panics
+/// * `assert!(row.len() >= bit_depth.packed_len(width));`
+#[track_caller]
+pub fn unpack_bits_scaled(row: &mut Vec<u8>, bit_depth: BitDepth, width: usize) {
+  unpack_bits(row, bit_depth, width);
+  let scale: u8 = match bit_depth {
+    BitDepth::One => 0xFF,
+    BitDepth::Two => 0x55,
+    BitDepth::Four => 0x11,
+  };
+  row.iter_mut().for_each(|sample| *sample *= scale);
+}
@@ -0,0 +1,156 @@
+//! Small helpers shared by the rest of the crate.
+
+#[cfg(all(target_arch = "x86", not(feature = "no-simd")))]
+use core::arch::x86::*;
+#[cfg(all(target_arch = "x86_64", not(feature = "no-simd")))]
+use core::arch::x86_64::*;
+
+#[cfg(all(target_arch = "aarch64", not(feature = "no-simd")))]
+use core::arch::aarch64::*;
+
+/// If every byte of `bytes` is 0 (which includes an empty slice).
+///
+/// This stops at the first block with a non-zero byte, so it's cheap on rows
+/// that have data near the start. It uses `sse4.1` or `neon` when the CPU has
+/// them (and the `no-simd` feature is off), and otherwise checks a `u64` at a
+/// time.
+#[inline]
+pub(crate) fn is_all_zero(bytes: &[u8]) -> bool {
+  #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), not(feature = "no-simd")))]
+  if crate::cpu::features().sse4_1 {
+    // Safety: we just checked for `sse4.1`.
+    return unsafe { is_all_zero_sse4_1(bytes) };
+  }
+  #[cfg(all(target_arch = "aarch64", not(feature = "no-simd")))]
+  if crate::cpu::features().neon {
+    // Safety: we just checked for `neon`.
+    return unsafe { is_all_zero_neon(bytes) };
+  }
+  is_all_zero_swar(bytes)
+}
+
+/// [`is_all_zero`] with `_mm_testz_si128`, 64 bytes per test and then 16.
+///
+/// ## Safety
+/// * The `sse4.1` CPU feature must be available at runtime.
+#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), not(feature = "no-simd")))]
+#[target_feature(enable = "sse4.1")]
+unsafe fn is_all_zero_sse4_1(bytes: &[u8]) -> bool {
+  let mut blocks = bytes.chunks_exact(64);
+  for block in blocks.by_ref() {
+    let p: *const __m128i = block.as_ptr().cast();
+    // Safety: `block` is 64 bytes, so all four loads are in bounds.
+    let v = unsafe {
+      _mm_or_si128(
+        _mm_or_si128(_mm_loadu_si128(p), _mm_loadu_si128(p.add(1))),
+        _mm_or_si128(_mm_loadu_si128(p.add(2)), _mm_loadu_si128(p.add(3))),
+      )
+    };
+    if _mm_testz_si128(v, v) == 0 {
+      return false;
+    }
+  }
+  let mut vectors = blocks.remainder().chunks_exact(16);
+  for vector in vectors.by_ref() {
+    // Safety: `vector` is 16 bytes.
+    let v = unsafe { _mm_loadu_si128(vector.as_ptr().cast()) };
+    if _mm_testz_si128(v, v) == 0 {
+      return false;
+    }
+  }
+  is_all_zero_swar(vectors.remainder())
+}
+
+/// [`is_all_zero`] with `vmaxvq_u8`, 64 bytes per test and then 16.
+///
+/// ## Safety
+/// * The `neon` CPU feature must be available at runtime.
+#[cfg(all(target_arch = "aarch64", not(feature = "no-simd")))]
+#[target_feature(enable = "neon")]
+unsafe fn is_all_zero_neon(bytes: &[u8]) -> bool {
+  let mut blocks = bytes.chunks_exact(64);
+  for block in blocks.by_ref() {
+    let p = block.as_ptr();
+    // Safety: `block` is 64 bytes, so all four loads are in bounds.
+    let v = unsafe {
+      vorrq_u8(
+        vorrq_u8(vld1q_u8(p), vld1q_u8(p.add(16))),
+        vorrq_u8(vld1q_u8(p.add(32)), vld1q_u8(p.add(48))),
+      )
+    };
+    if vmaxvq_u8(v) != 0 {
+      return false;
+    }
+  }
+  let mut vectors = blocks.remainder().chunks_exact(16);
+  for vector in vectors.by_ref() {
+    // Safety: `vector` is 16 bytes.
+    if vmaxvq_u8(unsafe { vld1q_u8(vector.as_ptr()) }) != 0 {
+      return false;
+    }
+  }
+  is_all_zero_swar(vectors.remainder())
+}
+
+/// [`is_all_zero`] without SIMD: the bytes are OR-ed together as `u64`s, 64
+/// bytes per branch, and then the last few bytes one at a time.
+#[inline]
+#[deny(unsafe_code)]
+fn is_all_zero_swar(bytes: &[u8]) -> bool {
+  let mut blocks = bytes.chunks_exact(64);
+  let blocks_zero = blocks.by_ref().all(|block| {
+    block.chunks_exact(8).fold(0, |acc, word| acc | u64::from_ne_bytes(word.try_into().unwrap()))
+      == 0
+  });
+  if !blocks_zero {
+    return false;
+  }
+  let mut words = blocks.remainder().chunks_exact(8);
+  words.by_ref().all(|word| u64::from_ne_bytes(word.try_into().unwrap()) == 0)
+    && words.remainder().iter().all(|byte| *byte == 0)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  type IsAllZero = fn(&[u8]) -> bool;
+
+  /// Every implementation that can run on this CPU.
+  fn impls() -> Vec<(&'static str, IsAllZero)> {
+    #[allow(unused_mut)]
+    let mut out: Vec<(&'static str, IsAllZero)> =
+      vec![("is_all_zero", is_all_zero), ("swar", is_all_zero_swar)];
+    #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), not(feature = "no-simd")))]
+    if crate::cpu::features().sse4_1 {
+      out.push(("sse4_1", |bytes| unsafe { is_all_zero_sse4_1(bytes) }));
+    }
+    #[cfg(all(target_arch = "aarch64", not(feature = "no-simd")))]
+    if crate::cpu::features().neon {
+      out.push(("neon", |bytes| unsafe { is_all_zero_neon(bytes) }));
+    }
+    out
+  }
+
+  #[test]
+  fn test_is_all_zero() {
+    let zeros = [0_u8; 300];
+    for (name, f) in impls() {
+      assert!(f(&[]), "{name}: empty");
+      for len in 0..=zeros.len() {
+        assert!(f(&zeros[..len]), "{name}: all zero, len {len}");
+        // A single non-zero byte anywhere, including right at the end.
+        for at in 0..len {
+          let mut bytes = zeros;
+          bytes[at] = 0x80;
+          assert!(!f(&bytes[..len]), "{name}: len {len}, non-zero at {at}");
+        }
+      }
+      // A non-zero byte just past the end of the slice doesn't count.
+      let mut bytes = zeros;
+      bytes[100] = 1;
+      assert!(f(&bytes[..100]), "{name}: non-zero past the end");
+      assert!(!f(&bytes[..101]), "{name}: single non-zero at the end");
+    }
+  }
+}
@@ -0,0 +1,98 @@
+//! "average" at the edges of the byte range, on every backend.
+//!
+//! `floor((a + b) / 2)` is computed with 16-bit (or 9-bit) lanes and packed
+//! back down to bytes, so the values where `a + b` is close to 0 or to 510 are
+//! where a bad shift or a saturating pack would show up. Each case sweeps `a`
+//! (the reconstructed pixel to the left) and `b` (the pixel above) over
+//! `EDGES`, and checks that every backend gives exactly the fallback's bytes.
+
+use png_filters::{options::UnfilterOptions, Backend};
+
+const EDGES: [u8; 4] = [0, 1, 254, 255];
+
+/// The filtered line and the previous row for one case, `pixels` wide.
+///
+/// The first pixel is filtered so that it reconstructs to `a`, the second
+/// pixel is `x` and has `b` above it, and any pixels after that are 0 (so that
+/// the rows are long enough to go through the SIMD loops).
+fn case(bytes_per_pixel: usize, pixels: usize, a: u8, b: u8, x: u8) -> (Vec<u8>, Vec<u8>) {
+  let mut previous = vec![0_u8; pixels * bytes_per_pixel];
+  let mut line = vec![0_u8; 1 + pixels * bytes_per_pixel];
+  line[0] = 3;
+  // Above the first pixel is `b` too, so the first pixel is `a - b / 2`.
+  previous[..2 * bytes_per_pixel].fill(b);
+  line[1..1 + bytes_per_pixel].fill(a.wrapping_sub(b / 2));
+  line[1 + bytes_per_pixel..1 + 2 * bytes_per_pixel].fill(x);
+  (line, previous)
+}
+
+fn check<const BYTES_PER_PIXEL: usize>() {
+  let backends = [
+    Backend::Fallback,
+    Backend::Sse2,
+    Backend::Sse4_1,
+    Backend::Neon,
+    Backend::Portable,
+    Backend::Avx2,
+    Backend::Avx512,
+  ];
+  for pixels in [2, 3, 17] {
+    for a in EDGES {
+      for b in EDGES {
+        for x in [0, 1, 255] {
+          let (filtered, previous) = case(BYTES_PER_PIXEL, pixels, a, b, x);
+          let msg = format!("bpp: {BYTES_PER_PIXEL}, pixels: {pixels}, a: {a}, b: {b}, x: {x}");
+          let mut expected = filtered[1..].to_vec();
+          png_filters::fallbacks::recon_average::<BYTES_PER_PIXEL>(&mut expected, &previous);
+          // Check the case itself against the formula.
+          assert_eq!(&expected[..BYTES_PER_PIXEL], &[a; BYTES_PER_PIXEL][..], "{msg}");
+          let average = ((u16::from(a) + u16::from(b)) / 2) as u8;
+          assert_eq!(expected[BYTES_PER_PIXEL], x.wrapping_add(average), "{msg}");
+          //
+          for backend in backends {
+            let mut actual = filtered.clone();
+            let outcome = UnfilterOptions::new()
+              .backend(backend)
+              .previous_row(&previous)
+              .run::<BYTES_PER_PIXEL>(actual.chunks_exact_mut(filtered.len()));
+            if outcome.result.is_err() {
+              // not available on this CPU
+              continue;
+            }
+            assert_eq!(&expected[..], &actual[1..], "{msg}, {backend:?}");
+          }
+          for (name, kernel) in extra_kernels::<BYTES_PER_PIXEL>() {
+            let mut actual = filtered[1..].to_vec();
+            kernel(&mut actual, &previous);
+            assert_eq!(expected, actual, "{msg}, {name}");
+          }
+        }
+      }
+    }
+  }
+}
+
+/// A safe wrapper for a `recon_average` function.
+type Kernel = fn(&mut [u8], &[u8]);
+
+/// The "average" functions that no backend uses as a whole, and that are
+/// available on this CPU.
+#[allow(unused_mut)]
+fn extra_kernels<const BYTES_PER_PIXEL: usize>() -> Vec<(&'static str, Kernel)> {
+  let mut out: Vec<(&'static str, Kernel)> = Vec::new();
+  if BYTES_PER_PIXEL == 4 {
+    out.push(("fallbacks::recon_average_swar_rgba8", |x, b| {
+      png_filters::fallbacks::recon_average_swar_rgba8(x, b)
+    }));
+  }
+  #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+  if BYTES_PER_PIXEL == 6 && std::arch::is_x86_feature_detected!("sse2") {
+    out.push(("sse2::recon_average_6", |x, b| unsafe { png_filters::sse2::recon_average_6(x, b) }));
+  }
+  out
+}
+
+#[test]
+fn test_average_boundary_every_backend() {
+  for_each_bpp!(check);
+}
@@ -0,0 +1,216 @@
+use png_filters::{
+  checked,
+  options::{UnfilterError, UnfilterOptions},
+};
+
+use crate::{random_filtered_image, Rng};
+
+#[test]
+fn test_checked_errors() {
+  let mut buf = [1, 2, 3, 4, 5, 6];
+  assert_eq!(
+    checked::unfilter_lines(0, &mut buf, 3),
+    Err(UnfilterError::UnsupportedBytesPerPixel(0))
+  );
+  assert_eq!(
+    checked::unfilter_lines(9, &mut buf, 3),
+    Err(UnfilterError::UnsupportedBytesPerPixel(9))
+  );
+  assert_eq!(
+    checked::unfilter_lines(1, &mut buf, 4),
+    Err(UnfilterError::BufferLength { len: 6, line_len: 4 })
+  );
+  assert_eq!(
+    checked::unfilter_lines(1, &mut buf, 0),
+    Err(UnfilterError::BufferLength { len: 6, line_len: 0 })
+  );
+  assert_eq!(
+    checked::unfilter_lines(4, &mut buf, 3),
+    Err(UnfilterError::RowShorterThanPixel { row_len: 2, bytes_per_pixel: 4 })
+  );
+  assert_eq!(
+    checked::unfilter_lines(2, &mut buf, 6),
+    Err(UnfilterError::PartialPixel { row_len: 5, bytes_per_pixel: 2 })
+  );
+  assert_eq!(buf, [1, 2, 3, 4, 5, 6]);
+  // The lines before an invalid filter type are still unfiltered.
+  let mut buf = [1, 2, 3, 7, 5, 6];
+  assert_eq!(
+    checked::unfilter_lines(1, &mut buf, 3),
+    Err(UnfilterError::InvalidFilterType { y: 1, filter: 7 })
+  );
+  assert_eq!(buf, [0, 2, 5, 7, 5, 6]);
+  assert_eq!(checked::unfilter_lines(1, &mut [], 0), Ok(0));
+  assert_eq!(checked::unfilter_lines(1, &mut [], 5), Ok(0));
+}
+
+#[test]
+fn test_checked_after_previous_row_length() {
+  // Two lines of 4 bytes, "up" then "paeth".
+  let filtered = [2, 1, 2, 3, 4, 4, 5, 6, 7, 8];
+  let previous = [10, 20, 30, 40];
+  for short_or_long in [&previous[..3], &[10, 20, 30, 40, 50][..], &[]] {
+    let mut buf = filtered;
+    assert_eq!(
+      checked::unfilter_lines_after(2, short_or_long, &mut buf, 5),
+      Err(UnfilterError::PreviousRowLength { expected: 4, actual: short_or_long.len() })
+    );
+    assert_eq!(buf, filtered);
+  }
+  // Checked even when there are no lines to use it on.
+  assert_eq!(
+    checked::unfilter_lines_after(2, &previous[..2], &mut [], 5),
+    Err(UnfilterError::PreviousRowLength { expected: 4, actual: 2 })
+  );
+  // The layout errors still come first.
+  assert_eq!(
+    checked::unfilter_lines_after(2, &previous, &mut [0; 6], 5),
+    Err(UnfilterError::BufferLength { len: 6, line_len: 5 })
+  );
+  // With the right length it's the same as unfiltering below the previous row.
+  let mut expected = vec![0];
+  expected.extend_from_slice(&previous);
+  expected.extend_from_slice(&filtered);
+  assert_eq!(checked::unfilter_lines(2, &mut expected, 5), Ok(12));
+  let mut buf = filtered;
+  assert_eq!(checked::unfilter_lines_after(2, &previous, &mut buf, 5), Ok(8));
+  assert_eq!(buf[..], expected[5..]);
+}
+
+#[test]
+fn test_checked_never_panics() {
+  let mut seed = [0_u8; 8];
+  getrandom::getrandom(&mut seed).unwrap();
+  let seed = u64::from_le_bytes(seed) | 1;
+  let mut rng = Rng::new(seed);
+  for _ in 0..5000 {
+    let bytes_per_pixel = rng.below(12);
+    let line_len = rng.below(40);
+    let len = match rng.below(3) {
+      0 => rng.below(200),
+      _ => line_len * rng.below(8),
+    };
+    // mostly valid filter types, with some invalid ones.
+    let mut buf: Vec<u8> = (0..len)
+      .map(|_| if rng.below(4) == 0 { rng.next_u64() as u8 } else { rng.below(5) as u8 })
+      .collect();
+    let original = buf.clone();
+    let result = std::panic::catch_unwind(move || {
+      let result = checked::unfilter_lines(bytes_per_pixel, &mut buf, line_len);
+      (result, buf)
+    });
+    let msg = format!("seed: {seed}, bpp: {bytes_per_pixel}, line_len: {line_len}, len: {len}");
+    let (result, buf) = result.unwrap_or_else(|_| panic!("{msg}"));
+    // Anything that worked matches the normal function.
+    if let Ok(reconstructed) = result {
+      let mut expected = original.clone();
+      if line_len > 0 {
+        let _ =
+          png_filters::unfilter_lines_dynamic(bytes_per_pixel, expected.chunks_exact_mut(line_len));
+      }
+      assert_eq!(expected, buf, "{msg}");
+      assert_eq!(reconstructed, len - len.checked_div(line_len).unwrap_or(0), "{msg}");
+    }
+    // Any size is either the right one for `buf` or an error.
+    let (width, height) = (rng.below(12), rng.below(8));
+    let buf = original.clone();
+    let result = std::panic::catch_unwind(move || {
+      checked::unfilter_to_vec(&buf, width, height, bytes_per_pixel)
+    });
+    let result = result.unwrap_or_else(|_| panic!("to_vec, {width}x{height}, {msg}"));
+    if let Ok(pixels) = result {
+      assert_eq!(pixels.len(), width * height * bytes_per_pixel, "to_vec, {msg}");
+    }
+    // A previous row of any length is either used or an error.
+    let previous: Vec<u8> = (0..rng.below(line_len + 2)).map(|_| rng.next_u64() as u8).collect();
+    let mut buf = original.clone();
+    let result = std::panic::catch_unwind(move || {
+      let result = checked::unfilter_lines_after(bytes_per_pixel, &previous, &mut buf, line_len);
+      (result, previous.len())
+    });
+    let (result, previous_len) = result.unwrap_or_else(|_| panic!("after, {msg}"));
+    if result.is_ok() && line_len > 0 {
+      assert_eq!(previous_len, line_len - 1, "after, {msg}");
+    }
+  }
+}
+
+#[test]
+fn test_checked_unfilter_to_vec() {
+  fn check<const BYTES_PER_PIXEL: usize>(width: usize, height: usize) {
+    let line_len = 1 + width * BYTES_PER_PIXEL;
+    let filtered = random_filtered_image::<BYTES_PER_PIXEL>(width, height, 1);
+    let original = filtered.clone();
+    let mut expected = filtered.clone();
+    png_filters::unfilter_and_compact::<BYTES_PER_PIXEL>(&mut expected, line_len);
+    let actual = checked::unfilter_to_vec(&filtered, width, height, BYTES_PER_PIXEL);
+    assert_eq!(actual, Ok(expected), "bpp: {BYTES_PER_PIXEL}, {width}x{height}");
+    assert_eq!(filtered, original);
+  }
+  for (width, height) in [(0, 0), (0, 3), (5, 0), (1, 1), (7, 10), (33, 12)] {
+    for_each_bpp!(check, width, height);
+  }
+  //
+  let filtered = [1, 1, 2, 3, 4, 2, 5, 6, 7, 8];
+  assert_eq!(checked::unfilter_to_vec(&filtered, 4, 2, 1), Ok(vec![1, 3, 6, 10, 6, 9, 13, 18]));
+  assert_eq!(
+    checked::unfilter_to_vec(&filtered, 4, 2, 9),
+    Err(UnfilterError::UnsupportedBytesPerPixel(9))
+  );
+  assert_eq!(
+    checked::unfilter_to_vec(&filtered, 2, 2, 1),
+    Err(UnfilterError::ImageSize { len: 10, width: 2, height: 2 })
+  );
+  assert_eq!(
+    checked::unfilter_to_vec(&filtered, usize::MAX, 2, 2),
+    Err(UnfilterError::ImageSize { len: 10, width: usize::MAX, height: 2 })
+  );
+  assert_eq!(
+    checked::unfilter_to_vec(&filtered, usize::MAX, 2, 1),
+    Err(UnfilterError::ImageSize { len: 10, width: usize::MAX, height: 2 })
+  );
+  assert_eq!(
+    checked::unfilter_to_vec(&filtered, usize::MAX / 2, 2, 2),
+    Err(UnfilterError::ImageSize { len: 10, width: usize::MAX / 2, height: 2 })
+  );
+  assert_eq!(
+    checked::unfilter_to_vec(&[1, 1, 2, 3, 4, 7, 5, 6, 7, 8], 4, 2, 1),
+    Err(UnfilterError::InvalidFilterType { y: 1, filter: 7 })
+  );
+}
+
+#[test]
+fn test_checked_unfilter_to_vec_with_stats() {
+  // 3x6 at ByPP 2: two "sub" lines, one each of "none", "up" and "paeth", and
+  // one "average" on the top line.
+  let filtered = [
+    3, 2, 4, 6, 8, 10, 12, //
+    1, 1, 1, 1, 1, 1, 1, //
+    0, 9, 9, 9, 9, 9, 9, //
+    2, 1, 2, 3, 4, 5, 6, //
+    4, 0, 0, 0, 0, 0, 0, //
+    1, 0, 1, 2, 2, 2, 2, //
+  ];
+  let expected_pixels = vec![
+    2, 4, 7, 10, 13, 17, //
+    1, 1, 2, 2, 3, 3, //
+    9, 9, 9, 9, 9, 9, //
+    10, 11, 12, 13, 14, 15, //
+    10, 11, 12, 13, 14, 15, //
+    0, 1, 2, 3, 4, 5, //
+  ];
+  let (pixels, counts) = checked::unfilter_to_vec_with_stats(&filtered, 3, 6, 2).unwrap();
+  assert_eq!(pixels, expected_pixels);
+  assert_eq!(counts, [1, 2, 1, 1, 1]);
+  assert_eq!(checked::unfilter_to_vec(&filtered, 3, 6, 2), Ok(pixels));
+  // The same counts as `UnfilterOptions::count_filters`.
+  let mut buf = filtered;
+  let outcome = UnfilterOptions::new().count_filters(true).run::<2>(buf.chunks_exact_mut(7));
+  assert_eq!(outcome.filter_counts, Some(counts.map(|count| count as usize)));
+  //
+  assert_eq!(checked::unfilter_to_vec_with_stats(&[], 0, 0, 3), Ok((vec![], [0; 5])));
+  assert_eq!(
+    checked::unfilter_to_vec_with_stats(&[1, 1, 2, 3, 4, 7, 5, 6, 7, 8], 4, 2, 1),
+    Err(UnfilterError::InvalidFilterType { y: 1, filter: 7 })
+  );
+}
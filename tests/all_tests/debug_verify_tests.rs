@@ -0,0 +1,36 @@
+#![cfg(feature = "debug-verify")]
+
+use png_filters::debug_verify::verify_line;
+
+/// A deliberately wrong `recon_up` that forgets about the last byte.
+fn broken_recon_up(filtered_row: &mut [u8], previous_row: &[u8]) {
+  let n = filtered_row.len() - 1;
+  png_filters::fallbacks::recon_up(&mut filtered_row[..n], &previous_row[..n]);
+}
+
+#[test]
+fn test_verify_line_accepts_correct_kernel() {
+  let last_row = [12, 17, 127, 128, 255, 250, 7, 54];
+  let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8];
+  verify_line::<4>(1, 2, &mut actual, Some(&last_row), |line| {
+    png_filters::fallbacks::recon_up(line, &last_row)
+  });
+  let expected = [13, 19, 130, 127, 4, 0, 14, 62];
+  assert_eq!(expected, actual);
+}
+
+#[test]
+#[should_panic = "row 3 with filter type 2"]
+fn test_verify_line_detects_broken_kernel() {
+  let last_row = [12, 17, 127, 128, 255, 250, 7, 54];
+  let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8];
+  verify_line::<4>(3, 2, &mut actual, Some(&last_row), |line| broken_recon_up(line, &last_row));
+}
+
+#[test]
+fn test_unfilter_lines_verified() {
+  let mut actual = [3, 2, 4, 6, 8, 3, 2, 4, 6, 8, 4, 1, 2, 3, 4];
+  let _ = png_filters::unfilter_lines::<4>(actual.chunks_exact_mut(5));
+  let expected = [0, 2, 4, 6, 8, 0, 3, 6, 9, 12, 0, 4, 8, 12, 16];
+  assert_eq!(expected, actual);
+}
@@ -0,0 +1,84 @@
+#![cfg(feature = "diagnostics")]
+
+use png_filters::{
+  diagnostics::{decode, encode, unfilter_lines_diagnosed, INVALID_FILTER},
+  Backend,
+};
+
+#[test]
+fn test_unfilter_lines_diagnosed() {
+  const LINE_LEN: usize = 1 + 4 * 40;
+  // None of the rows are all zero, so "average" and "paeth" really use the
+  // previous row.
+  let filters = [0, 3, 1, 2, 3, 4, 7, 0];
+  let mut image: Vec<u8> = filters
+    .iter()
+    .flat_map(|&filter| {
+      core::iter::once(filter)
+        .chain((1..LINE_LEN).map(move |i| (i * 5 + usize::from(filter)) as u8))
+    })
+    .collect();
+  let mut expected = image.clone();
+  let _ = png_filters::unfilter_lines::<4>(expected.chunks_exact_mut(LINE_LEN));
+  //
+  let mut rows = [0_u8; 8];
+  let len = unfilter_lines_diagnosed::<4>(image.chunks_exact_mut(LINE_LEN), &mut rows);
+  assert_eq!(len, 8 * (LINE_LEN - 1));
+  assert_eq!(expected, image);
+  let report = png_filters::selected_backends(4);
+  assert_eq!(
+    rows,
+    [
+      encode(0, None),
+      // "average" on the top line
+      encode(3, Some(report.average_top)),
+      encode(1, Some(report.sub)),
+      encode(2, Some(report.up)),
+      encode(3, Some(report.average)),
+      encode(4, Some(report.paeth)),
+      encode(7, None),
+      encode(0, None),
+    ]
+  );
+  assert_eq!(decode(rows[6]), Some((INVALID_FILTER, None)));
+  // One pixel wide, "sub" (and "paeth" on the top line) has nothing to do, so
+  // no function runs. The other filter types still do.
+  let mut narrow = [4, 10, 20, 30, 40, 1, 1, 2, 3, 4, 4, 5, 6, 7, 8, 3, 9, 9, 9, 9];
+  let mut expected = narrow;
+  let _ = png_filters::unfilter_lines::<4>(expected.chunks_exact_mut(5));
+  let mut rows = [0_u8; 4];
+  let len = unfilter_lines_diagnosed::<4>(narrow.chunks_exact_mut(5), &mut rows);
+  assert_eq!(len, 4 * 4);
+  assert_eq!(expected, narrow);
+  assert_eq!(
+    rows,
+    [
+      encode(4, None),
+      encode(1, None),
+      encode(4, Some(report.paeth)),
+      encode(3, Some(report.average))
+    ]
+  );
+}
+
+#[test]
+fn test_diagnostics_encode_decode() {
+  let backends = [
+    None,
+    Some(Backend::Fallback),
+    Some(Backend::Sse2),
+    Some(Backend::Sse4_1),
+    Some(Backend::Neon),
+    Some(Backend::Portable),
+    Some(Backend::Avx2),
+    Some(Backend::Avx512),
+  ];
+  for filter in 0..=4 {
+    for backend in backends {
+      assert_eq!(decode(encode(filter, backend)), Some((filter, backend)));
+    }
+  }
+  assert_eq!(decode(encode(200, None)), Some((INVALID_FILTER, None)));
+  assert_eq!(decode(0x05), None);
+  assert_eq!(decode(0x80), None);
+}
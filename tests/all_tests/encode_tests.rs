@@ -0,0 +1,184 @@
+use png_filters::{
+  encode::{
+    filter_average, filter_image_best, filter_lines_auto, filter_lines_in_place, filter_paeth,
+    filter_sub, filter_up, FilterHeuristic,
+  },
+  heuristic::estimate_deflate_cost,
+  unfilter_flat, unfilter_lines, FilterType,
+};
+
+use crate::Rng;
+
+fn roundtrip<const BYTES_PER_PIXEL: usize>(strategy: FilterHeuristic, width: usize) {
+  let row_len = width * BYTES_PER_PIXEL;
+  // A smooth gradient makes the heuristic pick a mix of filters, and noise
+  // checks that nothing depends on the data being nice.
+  let mut image: Vec<u8> = (0..row_len * 7).map(|i| (i / 3 + i % row_len) as u8).collect();
+  image.extend(Rng::new(1).bytes(row_len * 5));
+  //
+  let mut filtered = filter_lines_auto::<BYTES_PER_PIXEL>(strategy, &image, row_len);
+  assert_eq!(filtered.len(), image.len() + 12);
+  let reconstructed = unfilter_lines::<BYTES_PER_PIXEL>(filtered.chunks_exact_mut(1 + row_len));
+  assert_eq!(reconstructed, image.len());
+  let actual: Vec<u8> =
+    filtered.chunks_exact(1 + row_len).flat_map(|line| line[1..].iter().copied()).collect();
+  assert_eq!(image, actual);
+}
+
+#[test]
+fn test_filter_lines_auto_roundtrip() {
+  let strategies = [
+    FilterHeuristic::MinSumAbs,
+    FilterHeuristic::EstimatedDeflate,
+    FilterHeuristic::Fixed(FilterType::None),
+    FilterHeuristic::Fixed(FilterType::Sub),
+    FilterHeuristic::Fixed(FilterType::Up),
+    FilterHeuristic::Fixed(FilterType::Average),
+    FilterHeuristic::Fixed(FilterType::Paeth),
+  ];
+  for strategy in strategies {
+    roundtrip::<1>(strategy, 37);
+    roundtrip::<2>(strategy, 19);
+    roundtrip::<3>(strategy, 21);
+    roundtrip::<4>(strategy, 16);
+    roundtrip::<6>(strategy, 9);
+    roundtrip::<8>(strategy, 10);
+  }
+}
+
+#[test]
+fn test_filter_image_best_roundtrip() {
+  fn check<const BYTES_PER_PIXEL: usize>(strategy: FilterHeuristic, width: usize) {
+    let row_len = width * BYTES_PER_PIXEL;
+    let mut image: Vec<u8> = (0..row_len * 6).map(|i| (i / 5 + i % row_len * 3) as u8).collect();
+    image.extend(Rng::new(2).bytes(row_len * 6));
+    // `out` already has data, which should be kept.
+    let mut out = vec![0xAB; 3];
+    filter_image_best::<BYTES_PER_PIXEL>(&image, row_len, strategy, &mut out);
+    assert_eq!(&out[..3], &[0xAB; 3]);
+    let mut filtered = out.split_off(3);
+    assert_eq!(filtered, filter_lines_auto::<BYTES_PER_PIXEL>(strategy, &image, row_len));
+    //
+    assert_eq!(unfilter_flat::<BYTES_PER_PIXEL>(&mut filtered, 1 + row_len), image.len());
+    let actual: Vec<u8> =
+      filtered.chunks_exact(1 + row_len).flat_map(|line| line[1..].iter().copied()).collect();
+    assert_eq!(image, actual, "bpp: {BYTES_PER_PIXEL}, {strategy:?}");
+  }
+  let strategies = [
+    FilterHeuristic::MinSumAbs,
+    FilterHeuristic::EstimatedDeflate,
+    FilterHeuristic::Fixed(FilterType::None),
+    FilterHeuristic::Fixed(FilterType::Sub),
+    FilterHeuristic::Fixed(FilterType::Up),
+    FilterHeuristic::Fixed(FilterType::Average),
+    FilterHeuristic::Fixed(FilterType::Paeth),
+  ];
+  for strategy in strategies {
+    check::<1>(strategy, 33);
+    check::<2>(strategy, 17);
+    check::<3>(strategy, 20);
+    check::<4>(strategy, 16);
+    check::<6>(strategy, 7);
+    check::<8>(strategy, 12);
+  }
+}
+
+#[test]
+fn test_filter_image_best_uses_original_rows() {
+  // "up" on every row of a vertical ramp: each row is 10 more than the
+  // original row above it. Predicting from the filtered row above would give
+  // 11, 21, 31 instead.
+  let image: Vec<u8> = (0..5_u8).flat_map(|y| [y * 10 + 1; 4]).collect();
+  let mut out = Vec::new();
+  filter_image_best::<1>(&image, 4, FilterHeuristic::Fixed(FilterType::Up), &mut out);
+  let expected: Vec<u8> = [
+    [2, 1, 1, 1, 1],
+    [2, 10, 10, 10, 10],
+    [2, 10, 10, 10, 10],
+    [2, 10, 10, 10, 10],
+    [2, 10, 10, 10, 10],
+  ]
+  .concat();
+  assert_eq!(out, expected);
+}
+
+#[test]
+fn test_filter_lines_auto_top_row() {
+  // up and paeth are never picked for the top row.
+  let image = [10, 20, 30, 40, 50, 60, 70, 80];
+  let filtered = filter_lines_auto::<1>(FilterHeuristic::MinSumAbs, &image, 8);
+  assert_eq!(filtered, [1, 10, 10, 10, 10, 10, 10, 10, 10]);
+}
+
+#[test]
+fn test_estimated_deflate_not_worse_than_min_sum_abs() {
+  // Flat bands with a little noise in them, then diagonal stripes. Each row's
+  // estimated cost is what `EstimatedDeflate` minimizes, so the total can't be
+  // more than with `MinSumAbs` (on this image it's 192 vs 200).
+  let row_len = 48;
+  let mut rng = Rng::new(4);
+  let mut image: Vec<u8> = Vec::new();
+  for y in 0..24 {
+    for x in 0..row_len {
+      image.push(match y {
+        0..=11 if rng.below(16) == 0 => rng.next_u64() as u8,
+        0..=11 => 0x80 + (x / 12) as u8,
+        _ => (((x + y) / 4) % 2 * 0xF0) as u8,
+      });
+    }
+  }
+  let cost = |strategy: FilterHeuristic| -> usize {
+    filter_lines_auto::<1>(strategy, &image, row_len)
+      .chunks_exact(1 + row_len)
+      .map(|line| estimate_deflate_cost(&line[1..]))
+      .sum()
+  };
+  let estimated = cost(FilterHeuristic::EstimatedDeflate);
+  let min_sum_abs = cost(FilterHeuristic::MinSumAbs);
+  assert!(estimated <= min_sum_abs, "{estimated} > {min_sum_abs}");
+}
+
+#[test]
+fn test_filter_lines_in_place_roundtrip() {
+  fn check<const BYTES_PER_PIXEL: usize>(width: usize) {
+    const FILTERS: [u8; 12] = [4, 3, 2, 1, 0, 1, 4, 2, 3, 0, 6, 3];
+    let row_len = width * BYTES_PER_PIXEL;
+    let line_len = 1 + row_len;
+    let image = Rng::new(3).bytes(row_len * FILTERS.len());
+    let msg = format!("bpp: {BYTES_PER_PIXEL}, width: {width}");
+    // Filter each row out of place, with a zero row above the top row.
+    let mut expected = Vec::new();
+    let zero_row = vec![0_u8; row_len];
+    for (y, (row, filter)) in image.chunks_exact(row_len.max(1)).zip(FILTERS).enumerate() {
+      let previous = if y == 0 { &zero_row[..] } else { &image[(y - 1) * row_len..y * row_len] };
+      let mut dst = vec![0_u8; row_len];
+      match filter {
+        1 => filter_sub::<BYTES_PER_PIXEL>(&mut dst, row),
+        2 => filter_up(&mut dst, row, previous),
+        3 => filter_average::<BYTES_PER_PIXEL>(&mut dst, row, previous),
+        4 => filter_paeth::<BYTES_PER_PIXEL>(&mut dst, row, previous),
+        _ => dst.copy_from_slice(row),
+      }
+      expected.push(if filter > 4 { 0 } else { filter });
+      expected.extend(dst);
+    }
+    //
+    let mut buf = Vec::new();
+    for (row, filter) in image.chunks_exact(row_len.max(1)).zip(FILTERS) {
+      buf.push(filter);
+      buf.extend_from_slice(row);
+    }
+    filter_lines_in_place::<BYTES_PER_PIXEL>(&mut buf, line_len);
+    assert_eq!(buf, expected, "{msg}");
+    let reconstructed = unfilter_lines::<BYTES_PER_PIXEL>(buf.chunks_exact_mut(line_len));
+    assert_eq!(reconstructed, image.len(), "{msg}");
+    let actual: Vec<u8> =
+      buf.chunks_exact(line_len).flat_map(|line| line[1..].iter().copied()).collect();
+    assert_eq!(image, actual, "{msg}");
+  }
+  for width in [1, 2, 9, 64] {
+    for_each_bpp!(check, width);
+  }
+  filter_lines_in_place::<4>(&mut [], 0);
+  filter_lines_in_place::<4>(&mut [], 5);
+}
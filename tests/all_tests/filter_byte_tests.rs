@@ -0,0 +1,184 @@
+//! The filter byte of each line is set to 0 once the line is unfiltered.
+//!
+//! Re-encoders can rely on this: after an in-place unfilter the buffer is an
+//! image where every line has filter type 0. The functions that don't do this
+//! (compacting, out-of-place, stopping at an invalid filter type, and keeping
+//! the filter types) are checked here too, so that each difference stays the
+//! documented one.
+
+use png_filters::{
+  options::{UnfilterError, UnfilterOptions},
+  Backend,
+};
+
+/// Every filter type, each one after each of the others, and two invalid
+/// filter types.
+const FILTERS: [u8; 14] = [1, 2, 3, 4, 0, 4, 2, 0, 3, 1, 7, 0, 255, 2];
+
+/// Random image data with the filter types of `FILTERS`, `width` pixels wide.
+fn image(bytes_per_pixel: usize, width: usize) -> (Vec<u8>, usize) {
+  let line_len = 1 + width * bytes_per_pixel;
+  let mut buf = crate::Rng::new(line_len as u64).bytes(line_len * FILTERS.len());
+  buf.chunks_exact_mut(line_len).zip(FILTERS).for_each(|(line, filter)| line[0] = filter);
+  (buf, line_len)
+}
+
+fn assert_filter_bytes_zero(buf: &[u8], line_len: usize, msg: &str) {
+  buf.chunks_exact(line_len).enumerate().for_each(|(y, line)| {
+    assert_eq!(line[0], 0, "{msg}, y: {y}");
+  });
+}
+
+#[test]
+fn test_unfilter_lines_zeroes_filter_bytes() {
+  fn check<const BYTES_PER_PIXEL: usize>() {
+    for width in [0, 1, 5, 33] {
+      let (original, line_len) = image(BYTES_PER_PIXEL, width);
+      let msg = format!("bpp: {BYTES_PER_PIXEL}, width: {width}");
+      //
+      let mut buf = original.clone();
+      let _ = png_filters::unfilter_lines::<BYTES_PER_PIXEL>(buf.chunks_exact_mut(line_len));
+      assert_filter_bytes_zero(&buf, line_len, &msg);
+      // Only the filter byte of an invalid line changes.
+      for (y, filter) in FILTERS.into_iter().enumerate() {
+        if filter > 4 {
+          let line = y * line_len..(y + 1) * line_len;
+          assert_eq!(buf[line.start + 1..line.end], original[line.start + 1..line.end], "{msg}");
+        }
+      }
+      //
+      let mut hooked = original.clone();
+      let _ = png_filters::unfilter_lines_with_hook::<BYTES_PER_PIXEL>(
+        hooked.chunks_exact_mut(line_len),
+        &mut |_, _| (),
+      );
+      assert_eq!(buf, hooked, "{msg}, hook");
+      //
+      let mut flat = original.clone();
+      let _ = png_filters::unfilter_flat::<BYTES_PER_PIXEL>(&mut flat, line_len);
+      assert_eq!(buf, flat, "{msg}, flat");
+      //
+      let mut first = original.clone();
+      let mut second = original.clone();
+      png_filters::unfilter_many::<BYTES_PER_PIXEL>(&mut [&mut first, &mut second], line_len);
+      assert_eq!(buf, first, "{msg}, many");
+      assert_eq!(buf, second, "{msg}, many");
+      // The padding isn't a filter byte, so it's left alone.
+      let stride = line_len + 3;
+      let mut strided = vec![0xAA_u8; stride * FILTERS.len()];
+      strided
+        .chunks_exact_mut(stride)
+        .zip(original.chunks_exact(line_len))
+        .for_each(|(padded, line)| padded[..line_len].copy_from_slice(line));
+      let _ =
+        png_filters::unfilter_lines_strided::<BYTES_PER_PIXEL>(&mut strided, stride, line_len - 1);
+      strided.chunks_exact(stride).zip(buf.chunks_exact(line_len)).for_each(|(padded, line)| {
+        assert_eq!(&padded[..line_len], line, "{msg}, strided");
+        assert_eq!(&padded[line_len..], &[0xAA; 3], "{msg}, strided");
+      });
+      //
+      let backends = [
+        Backend::Fallback,
+        Backend::Sse2,
+        Backend::Sse4_1,
+        Backend::Neon,
+        Backend::Portable,
+        Backend::Avx2,
+        Backend::Avx512,
+      ];
+      for backend in backends {
+        let mut actual = original.clone();
+        let outcome = UnfilterOptions::new()
+          .backend(backend)
+          .run::<BYTES_PER_PIXEL>(actual.chunks_exact_mut(line_len));
+        if let Err(UnfilterError::BackendUnavailable(_)) = outcome.result {
+          continue;
+        }
+        // An invalid filter type is an error, but the lines are still done.
+        let error = UnfilterError::InvalidFilterType { y: 10, filter: 7 };
+        assert_eq!(outcome.result, Err(error), "{msg}, {backend:?}");
+        assert_eq!(buf, actual, "{msg}, {backend:?}");
+      }
+      // With split filters it's the `filters` slice that's zeroed.
+      if width > 0 {
+        let mut filters = FILTERS;
+        let mut rows: Vec<u8> =
+          original.chunks_exact(line_len).flat_map(|line| line[1..].iter().copied()).collect();
+        let _ = png_filters::unfilter_split::<BYTES_PER_PIXEL>(
+          &mut filters,
+          rows.chunks_exact_mut(line_len - 1),
+        );
+        assert_eq!(filters, [0; FILTERS.len()], "{msg}, split");
+      }
+    }
+  }
+  for_each_bpp!(check);
+}
+
+#[test]
+fn test_unfilter_lines_fixed_zeroes_filter_bytes() {
+  let mut filters = FILTERS;
+  let mut rows = [[0_u8; 12]; FILTERS.len()];
+  let mut rng = crate::Rng::new(1);
+  rows.iter_mut().for_each(|row| row.copy_from_slice(&rng.bytes(12)));
+  png_filters::unfilter_lines_fixed::<3, 12, { FILTERS.len() }>(&mut filters, &mut rows);
+  assert_eq!(filters, [0; FILTERS.len()]);
+}
+
+#[test]
+fn test_filter_bytes_in_other_variants() {
+  let (original, line_len) = image(4, 9);
+  let mut expected = original.clone();
+  let _ = png_filters::unfilter_lines::<4>(expected.chunks_exact_mut(line_len));
+  let pixels: Vec<u8> =
+    expected.chunks_exact(line_len).flat_map(|line| line[1..].iter().copied()).collect();
+  // Compacting removes the filter bytes entirely.
+  let mut compacted = original.clone();
+  let len = png_filters::unfilter_and_compact::<4>(&mut compacted, line_len);
+  assert_eq!(len, pixels.len());
+  assert_eq!(compacted, pixels);
+  // Out-of-place never writes to the input, so its filter bytes are unchanged.
+  let mut dst = vec![0_u8; pixels.len()];
+  png_filters::unfilter_lines_to::<4>(
+    original.chunks_exact(line_len),
+    dst.chunks_exact_mut(line_len - 1),
+  );
+  assert_eq!(dst, pixels);
+  let mut uninit = vec![core::mem::MaybeUninit::uninit(); pixels.len()];
+  let dst =
+    png_filters::unfilter_lines_to_uninit::<4>(original.chunks_exact(line_len), &mut uninit);
+  assert_eq!(dst, &pixels[..]);
+  // Stopping at an invalid filter type leaves that line's filter byte, and
+  // every line after it, as they were.
+  let mut inline = original.clone();
+  assert!(png_filters::try_unfilter_lines_inline::<4>(inline.chunks_exact_mut(line_len)).is_err());
+  let bad = FILTERS.iter().position(|&filter| filter > 4).unwrap();
+  assert_filter_bytes_zero(&inline[..bad * line_len], line_len, "inline");
+  assert_eq!(inline[bad * line_len..], original[bad * line_len..]);
+}
+
+#[test]
+fn test_unfilter_lines_keep_filters() {
+  fn check<const BYTES_PER_PIXEL: usize>() {
+    for width in [0, 1, 5, 33] {
+      let (original, line_len) = image(BYTES_PER_PIXEL, width);
+      let msg = format!("bpp: {BYTES_PER_PIXEL}, width: {width}");
+      let mut expected = original.clone();
+      let expected_len =
+        png_filters::unfilter_lines::<BYTES_PER_PIXEL>(expected.chunks_exact_mut(line_len));
+      let mut actual = original.clone();
+      let actual_len = png_filters::unfilter_lines_keep_filters::<BYTES_PER_PIXEL>(
+        actual.chunks_exact_mut(line_len),
+      );
+      assert_eq!(expected_len, actual_len, "{msg}");
+      actual.chunks_exact(line_len).zip(expected.chunks_exact(line_len)).zip(FILTERS).for_each(
+        |((actual, expected), filter)| {
+          assert_eq!(actual[0], filter, "{msg}");
+          assert_eq!(actual[1..], expected[1..], "{msg}");
+        },
+      );
+    }
+  }
+  for_each_bpp!(check);
+  assert_eq!(png_filters::unfilter_lines_keep_filters::<4>([].chunks_exact_mut(5)), 0);
+}
@@ -0,0 +1,32 @@
+use png_filters::heuristic::estimate_deflate_cost;
+
+#[test]
+fn test_estimate_deflate_cost_low_entropy_is_cheaper() {
+  let mut rng = crate::Rng::new(1);
+  let random: Vec<u8> = (0..1024).map(|_| rng.next_u64() as u8).collect();
+  // only a few distinct values, like a filtered smooth gradient.
+  let smooth: Vec<u8> = (0..1024).map(|i| [0, 1, 1, 0, 255, 0][i % 6]).collect();
+  let zeroes = [0_u8; 1024];
+  //
+  let random_cost = estimate_deflate_cost(&random);
+  let smooth_cost = estimate_deflate_cost(&smooth);
+  let zeroes_cost = estimate_deflate_cost(&zeroes);
+  assert!(smooth_cost < random_cost, "{smooth_cost} should be < {random_cost}");
+  assert!(zeroes_cost < smooth_cost, "{zeroes_cost} should be < {smooth_cost}");
+  // random bytes don't compress, so the estimate should be close to the length.
+  assert!(random_cost > 950, "{random_cost}");
+  // one literal, and 1023 repeats in 4 matches.
+  assert_eq!(zeroes_cost, 7);
+}
+
+#[test]
+fn test_estimate_deflate_cost_small_inputs() {
+  assert_eq!(estimate_deflate_cost(&[]), 0);
+  assert_eq!(estimate_deflate_cost(&[7]), 0);
+  // two literals, each one bit.
+  assert_eq!(estimate_deflate_cost(&[1, 2]), 1);
+  // a run of 3 is all literals (of only one value, so zero bits each), a run
+  // of 4 is a literal and a 13 bit match.
+  assert_eq!(estimate_deflate_cost(&[5, 5, 5]), 0);
+  assert_eq!(estimate_deflate_cost(&[5, 5, 5, 5]), 2);
+}
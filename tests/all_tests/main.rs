@@ -1,5 +1,80 @@
+/// Runs `$check::<N>(args..)` for every supported bytes per pixel.
+///
+/// Defined before the `mod`s so that every test module can use it.
+macro_rules! for_each_bpp {
+  ($check:ident $(, $arg:expr)* $(,)?) => {
+    $check::<1>($($arg),*);
+    $check::<2>($($arg),*);
+    $check::<3>($($arg),*);
+    $check::<4>($($arg),*);
+    $check::<5>($($arg),*);
+    $check::<6>($($arg),*);
+    $check::<7>($($arg),*);
+    $check::<8>($($arg),*);
+  };
+}
+
+mod average_boundary;
+mod checked_tests;
+mod debug_verify_tests;
+mod diagnostics_tests;
+mod encode_tests;
+mod filter_byte_tests;
+mod heuristic_tests;
 mod neon_tests;
+mod options_tests;
+mod paeth_boundary;
+mod property_tests;
+mod regressions_tests;
+mod self_test_tests;
 mod sse2_tests;
+mod timing_tests;
+mod trace_tests;
+mod u16_native_tests;
+mod unpack_tests;
+
+/// A `xorshift64*` generator, so random test data only depends on the seed.
+struct Rng(u64);
+impl Rng {
+  fn new(seed: u64) -> Self {
+    // xorshift gets stuck on 0
+    Self(seed | 1)
+  }
+  fn next_u64(&mut self) -> u64 {
+    self.0 ^= self.0 >> 12;
+    self.0 ^= self.0 << 25;
+    self.0 ^= self.0 >> 27;
+    self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+  }
+  fn below(&mut self, n: usize) -> usize {
+    (self.next_u64() % n as u64) as usize
+  }
+  /// Random bytes, biased towards `0` and `255` since that's where the
+  /// wrapping and tie-breaking bugs tend to be.
+  fn bytes(&mut self, len: usize) -> Vec<u8> {
+    (0..len)
+      .map(|_| match self.below(8) {
+        0 => 0,
+        1 => u8::MAX,
+        _ => self.next_u64() as u8,
+      })
+      .collect()
+  }
+}
+
+/// A filtered image of random bytes, `height` lines of a filter byte and then
+/// `width` pixels.
+///
+/// The filter types go 0, 1, 2, 3, 4 and then repeat, so every filter type is
+/// used once the image is 5 lines tall.
+fn random_filtered_image<const BYTES_PER_PIXEL: usize>(
+  width: usize, height: usize, seed: u64,
+) -> Vec<u8> {
+  let line_len = 1 + width * BYTES_PER_PIXEL;
+  let mut image = Rng::new(seed).bytes(line_len * height);
+  image.chunks_exact_mut(line_len).enumerate().for_each(|(y, line)| line[0] = (y % 5) as u8);
+  image
+}
 
 #[test]
 fn test_recon_sub_fallback() {
@@ -29,6 +104,17 @@ fn test_recon_up_fallback() {
   assert_eq!(expected, actual);
 }
 
+#[test]
+fn test_recon_up_checksummed_fallback() {
+  let last_row = [12, 17, 127, 128, 255, 250, 7, 54];
+  //
+  let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8];
+  let sum = png_filters::fallbacks::recon_up_checksummed(&mut actual, &last_row);
+  let expected = [13, 19, 130, 127, 4, 0, 14, 62];
+  assert_eq!(expected, actual);
+  assert_eq!(sum, expected.iter().map(|&x| u32::from(x)).sum::<u32>());
+}
+
 #[test]
 fn test_recon_average_fallback() {
   let last_row = [12, 17, 127, 128, 255, 250, 7, 54];
@@ -49,6 +135,34 @@ fn test_recon_average_fallback() {
   assert_eq!(expected, actual);
 }
 
+#[test]
+fn test_recon_average_swar_rgba8_fallback() {
+  let last_row = [12, 17, 127, 128, 255, 250, 7, 54];
+  //
+  let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8];
+  png_filters::fallbacks::recon_average_swar_rgba8(&mut actual, &last_row);
+  let expected = [7, 10, 66, 63, 136, 136, 43, 66];
+  assert_eq!(expected, actual);
+  //
+  // `(255 + 255) / 2` is the largest average, and doesn't carry into the next
+  // channel. Adding it to a filtered 255 wraps within the channel.
+  let last_row = [255; 8];
+  let mut actual = [255, 0, 255, 1, 255, 255, 0, 2];
+  png_filters::fallbacks::recon_average_swar_rgba8(&mut actual, &last_row);
+  let expected = [126, 127, 126, 128, 189, 190, 190, 193];
+  assert_eq!(expected, actual);
+  //
+  for pixels in [0, 1, 2, 7, 64, 1000] {
+    let mut rng = Rng::new(pixels as u64);
+    let mut filtered = rng.bytes(pixels * 4);
+    let previous = rng.bytes(pixels * 4);
+    let mut expected = filtered.clone();
+    png_filters::fallbacks::recon_average::<4>(&mut expected, &previous);
+    png_filters::fallbacks::recon_average_swar_rgba8(&mut filtered, &previous);
+    assert_eq!(expected, filtered, "pixels: {pixels}");
+  }
+}
+
 #[test]
 fn test_recon_average_top_fallback() {
   let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8];
@@ -61,6 +175,11 @@ fn test_recon_average_top_fallback() {
   let expected = [1, 2, 3, 0, 6, 6, 10, 11];
   assert_eq!(expected, actual);
   //
+  let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8, 9];
+  png_filters::fallbacks::recon_average_top::<3>(&mut actual);
+  let expected = [1, 2, 3, 255, 6, 7, 134, 11, 12];
+  assert_eq!(expected, actual);
+  //
   let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8];
   png_filters::fallbacks::recon_average_top::<4>(&mut actual);
   let expected = [1, 2, 3, 255, 5, 7, 8, 135];
@@ -85,4 +204,1052 @@ fn test_recon_paeth_fallback() {
   png_filters::fallbacks::recon_paeth::<4>(&mut actual, &last_row);
   let expected = [13, 19, 130, 127, 4, 0, 14, 62];
   assert_eq!(expected, actual);
+  //
+  let last_row = [12, 17, 127, 128, 255, 250, 7, 54, 0, 255, 200, 1, 128, 3, 9, 254];
+  let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8, 9, 10, 255, 0, 128, 127, 1, 2];
+  png_filters::fallbacks::recon_paeth::<8>(&mut actual, &last_row);
+  let expected = [13, 19, 130, 127, 4, 0, 14, 62, 9, 9, 199, 1, 132, 127, 15, 0];
+  assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_recon_paeth_rgba8_fallback() {
+  let last_row = [12, 17, 127, 128, 255, 250, 7, 54];
+  //
+  let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8];
+  png_filters::fallbacks::recon_paeth_rgba8(&mut actual, &last_row);
+  let expected = [13, 19, 130, 127, 4, 0, 14, 62];
+  assert_eq!(expected, actual);
+  //
+  // Opaque and fully transparent pixels, where `a`, `b`, and `c` often tie.
+  let last_row = [0, 0, 0, 255, 0, 0, 0, 255, 9, 9, 9, 0, 9, 9, 9, 0];
+  let mut actual = [0, 0, 0, 255, 0, 0, 0, 0, 9, 9, 9, 0, 0, 0, 0, 0];
+  png_filters::fallbacks::recon_paeth_rgba8(&mut actual, &last_row);
+  let expected = [0, 0, 0, 254, 0, 0, 0, 254, 18, 18, 18, 0, 18, 18, 18, 0];
+  assert_eq!(expected, actual);
+  //
+  for pixels in [0, 1, 2, 7, 64, 1000] {
+    let mut rng = Rng::new(pixels as u64);
+    let mut filtered = rng.bytes(pixels * 4);
+    let previous = rng.bytes(pixels * 4);
+    let mut expected = filtered.clone();
+    png_filters::fallbacks::recon_paeth::<4>(&mut expected, &previous);
+    png_filters::fallbacks::recon_paeth_rgba8(&mut filtered, &previous);
+    assert_eq!(expected, filtered, "pixels: {pixels}");
+  }
+}
+
+#[test]
+fn test_recon_paeth_branchless_fallback() {
+  let last_row = [12, 17, 127, 128, 255, 250, 7, 54];
+  //
+  let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8];
+  png_filters::fallbacks::recon_paeth_branchless::<1>(&mut actual, &last_row);
+  let expected = [13, 19, 130, 129, 4, 10, 14, 62];
+  assert_eq!(expected, actual);
+  //
+  let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8];
+  png_filters::fallbacks::recon_paeth_branchless::<2>(&mut actual, &last_row);
+  let expected = [13, 19, 130, 127, 4, 0, 11, 8];
+  assert_eq!(expected, actual);
+  //
+  let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8];
+  png_filters::fallbacks::recon_paeth_branchless::<4>(&mut actual, &last_row);
+  let expected = [13, 19, 130, 127, 4, 0, 14, 62];
+  assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_recon_paeth_branchless_edge_values() {
+  // Every combination of values around the 0, 128, and 255 boundaries. With
+  // one byte per pixel and a two pixel row, the first filtered byte is set so
+  // that the first pixel reconstructs to `a`, and then the second output byte
+  // is exactly the predictor for `a`, `b`, and `c`.
+  let edges: [u8; 14] = [0, 1, 2, 63, 64, 126, 127, 128, 129, 191, 192, 253, 254, 255];
+  for a in edges {
+    for b in edges {
+      for c in edges {
+        let filtered = [a.wrapping_sub(c), 0];
+        let previous = [c, b];
+        let mut expected = filtered;
+        png_filters::fallbacks::recon_paeth::<1>(&mut expected, &previous);
+        let mut actual = filtered;
+        png_filters::fallbacks::recon_paeth_branchless::<1>(&mut actual, &previous);
+        assert_eq!(expected, actual, "a: {a}, b: {b}, c: {c}");
+      }
+    }
+  }
+}
+
+#[test]
+fn test_recon_paeth_table_every_input() {
+  // For each `c`, the previous row alternates `c` with each `b`, and the
+  // expected row puts each `a` just before that. Filtering that row and then
+  // unfiltering it must give it back, so every odd byte checks one `(a, b, c)`
+  // against the encoder's predictor.
+  let mut previous = vec![0_u8; 2 * 256 * 256];
+  let mut expected = vec![0_u8; 2 * 256 * 256];
+  for (i, (p, e)) in previous.chunks_exact_mut(2).zip(expected.chunks_exact_mut(2)).enumerate() {
+    e[0] = i as u8;
+    p[1] = (i >> 8) as u8;
+  }
+  let mut filtered = vec![0_u8; expected.len()];
+  for c in 0..=255 {
+    previous.chunks_exact_mut(2).for_each(|p| p[0] = c);
+    png_filters::encode::filter_paeth::<1>(&mut filtered, &expected, &previous);
+    png_filters::fallbacks::recon_paeth_table::<1>(&mut filtered, &previous);
+    assert!(filtered == expected, "c: {c}");
+  }
+}
+
+#[test]
+fn test_recon_paeth_table_fallback() {
+  let bytes = Rng::new(1).bytes(48 * 2);
+  let (filtered, previous) = bytes.split_at(48);
+  let mut expected = [0_u8; 48];
+  expected.copy_from_slice(filtered);
+  png_filters::fallbacks::recon_paeth::<3>(&mut expected, previous);
+  let mut actual = [0_u8; 48];
+  actual.copy_from_slice(filtered);
+  png_filters::fallbacks::recon_paeth_table::<3>(&mut actual, previous);
+  assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_unfilter_lines_all_none() {
+  let mut actual = [0, 1, 2, 3, 4, 0, 5, 6, 7, 8, 0, 9, 10, 11, 12];
+  assert_eq!(png_filters::unfilter_lines::<1>(actual.chunks_exact_mut(5)), 12);
+  let expected = [0, 1, 2, 3, 4, 0, 5, 6, 7, 8, 0, 9, 10, 11, 12];
+  assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_unfilter_lines_leading_none() {
+  // the first non-zero filter line still sees the previous (filter 0) line.
+  let mut actual = [0, 1, 2, 3, 4, 0, 5, 6, 7, 8, 2, 9, 10, 11, 12, 1, 1, 1, 1, 1];
+  let _ = png_filters::unfilter_lines::<1>(actual.chunks_exact_mut(5));
+  let expected = [0, 1, 2, 3, 4, 0, 5, 6, 7, 8, 0, 14, 16, 18, 20, 0, 1, 2, 3, 4];
+  assert_eq!(expected, actual);
+  //
+  // a non-zero filter on the very first line still gets top line handling.
+  let mut actual = [3, 2, 4, 6, 8, 3, 2, 4, 6, 8];
+  let _ = png_filters::unfilter_lines::<1>(actual.chunks_exact_mut(5));
+  let expected = [0, 2, 5, 8, 12, 0, 3, 8, 14, 21];
+  assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_unfilter_many() {
+  let originals: [[u8; 15]; 3] = [
+    [1, 1, 2, 3, 4, 2, 5, 6, 7, 8, 3, 9, 10, 11, 12],
+    [4, 200, 1, 3, 7, 4, 5, 6, 7, 8, 1, 9, 10, 11, 12],
+    [0, 1, 2, 3, 4, 0, 5, 6, 7, 8, 0, 9, 10, 11, 12],
+  ];
+  let mut expected = originals;
+  expected.iter_mut().for_each(|image| {
+    assert_eq!(png_filters::unfilter_lines::<2>(image.chunks_exact_mut(5)), 12);
+  });
+  //
+  let mut actual = originals;
+  let [a, b, c] = &mut actual;
+  png_filters::unfilter_many::<2>(&mut [&mut a[..], &mut b[..], &mut c[..]], 5);
+  assert_eq!(expected, actual);
+}
+
+/// `unfilter_lines`, using only the fallbacks.
+fn unfilter_lines_reference<const BYTES_PER_PIXEL: usize>(bytes: &mut [u8], line_len: usize) {
+  let mut previous: Vec<u8> = vec![0; line_len - 1];
+  bytes.chunks_exact_mut(line_len).enumerate().for_each(|(y, line)| {
+    let (filter, line) = line.split_first_mut().unwrap();
+    match (*filter, y) {
+      (1, _) | (4, 0) => png_filters::fallbacks::recon_sub::<BYTES_PER_PIXEL>(line),
+      (2, _) => png_filters::fallbacks::recon_up(line, &previous),
+      (3, 0) => png_filters::fallbacks::recon_average_top::<BYTES_PER_PIXEL>(line),
+      (3, _) => png_filters::fallbacks::recon_average::<BYTES_PER_PIXEL>(line, &previous),
+      (4, _) => png_filters::fallbacks::recon_paeth::<BYTES_PER_PIXEL>(line, &previous),
+      _ => (),
+    }
+    *filter = 0;
+    previous.copy_from_slice(line);
+  });
+}
+
+#[test]
+fn test_unfilter_lines_matches_fallbacks() {
+  fn check<const BYTES_PER_PIXEL: usize>() {
+    let line_len = 1 + 33 * BYTES_PER_PIXEL;
+    let original = random_filtered_image::<BYTES_PER_PIXEL>(33, 10, 101);
+    //
+    let mut expected = original.clone();
+    unfilter_lines_reference::<BYTES_PER_PIXEL>(&mut expected, line_len);
+    let mut actual = original.clone();
+    let reconstructed =
+      png_filters::unfilter_lines::<BYTES_PER_PIXEL>(actual.chunks_exact_mut(line_len));
+    assert_eq!(expected, actual, "bpp: {BYTES_PER_PIXEL}");
+    assert_eq!(reconstructed, 10 * 33 * BYTES_PER_PIXEL);
+  }
+  for_each_bpp!(check);
+}
+
+#[test]
+#[cfg(feature = "no-simd")]
+fn test_no_simd_selects_fallbacks() {
+  for bytes_per_pixel in 1..=8 {
+    assert!(png_filters::selected_backends(bytes_per_pixel).all_fallbacks());
+  }
+}
+
+/// Unfilters adjacent rows of one contiguous buffer, where each previous row
+/// is a borrow of the same allocation as the row being written. Run it with
+/// `cargo +nightly miri test adjacent_rows` to check the borrows, which is why
+/// it's kept small.
+#[test]
+fn test_unfilter_lines_adjacent_rows() {
+  fn check<const BYTES_PER_PIXEL: usize>() {
+    const WIDTH: usize = 5;
+    let line_len = 1 + WIDTH * BYTES_PER_PIXEL;
+    // Every filter type that reads the row above, plus a guard line after
+    // the image that should never be touched.
+    let filters = [1, 2, 3, 4, 2, 4, 3];
+    let mut buf = vec![0_u8; line_len * (filters.len() + 1)];
+    buf.iter_mut().enumerate().for_each(|(i, x)| *x = (i * 37 + 11) as u8);
+    buf.chunks_exact_mut(line_len).zip(filters).for_each(|(line, filter)| line[0] = filter);
+    let guard = buf[line_len * filters.len()..].to_vec();
+    // The same rows, each in its own allocation, with the fallbacks.
+    let mut expected: Vec<Vec<u8>> = Vec::new();
+    for (line, filter) in buf.chunks_exact(line_len).zip(filters) {
+      let mut row = line[1..].to_vec();
+      match (filter, expected.last()) {
+        (1, _) => png_filters::fallbacks::recon_sub::<BYTES_PER_PIXEL>(&mut row),
+        (2, Some(b)) => png_filters::fallbacks::recon_up(&mut row, b),
+        (3, Some(b)) => png_filters::fallbacks::recon_average::<BYTES_PER_PIXEL>(&mut row, b),
+        (4, Some(b)) => png_filters::fallbacks::recon_paeth::<BYTES_PER_PIXEL>(&mut row, b),
+        _ => unreachable!(),
+      }
+      expected.push(row);
+    }
+    //
+    let image_len = line_len * filters.len();
+    let _ =
+      png_filters::unfilter_lines::<BYTES_PER_PIXEL>(buf[..image_len].chunks_exact_mut(line_len));
+    let actual: Vec<Vec<u8>> =
+      buf[..image_len].chunks_exact(line_len).map(|line| line[1..].to_vec()).collect();
+    assert_eq!(expected, actual, "bpp: {BYTES_PER_PIXEL}");
+    assert_eq!(guard, &buf[image_len..], "bpp: {BYTES_PER_PIXEL}");
+  }
+  for_each_bpp!(check);
+}
+
+#[test]
+fn test_unfilter_lines_to() {
+  fn check<const BYTES_PER_PIXEL: usize>() {
+    let line_len = 1 + 21 * BYTES_PER_PIXEL;
+    let mut original = random_filtered_image::<BYTES_PER_PIXEL>(21, 10, 2);
+    // an invalid filter type, which leaves the line as it is.
+    original[5 * line_len] = 5;
+    //
+    let mut expected = original.clone();
+    let _ = png_filters::unfilter_lines::<BYTES_PER_PIXEL>(expected.chunks_exact_mut(line_len));
+    let expected: Vec<u8> =
+      expected.chunks_exact(line_len).flat_map(|line| line[1..].iter().copied()).collect();
+    let mut actual = vec![0_u8; (line_len - 1) * 10];
+    png_filters::unfilter_lines_to::<BYTES_PER_PIXEL>(
+      original.chunks_exact(line_len),
+      actual.chunks_exact_mut(line_len - 1),
+    );
+    assert_eq!(expected, actual, "bpp: {BYTES_PER_PIXEL}");
+  }
+  for_each_bpp!(check);
+}
+
+#[test]
+fn test_unfilter_lines_to_uninit() {
+  fn check<const BYTES_PER_PIXEL: usize>(width: usize, height: usize) {
+    let line_len = 1 + width * BYTES_PER_PIXEL;
+    let mut original = random_filtered_image::<BYTES_PER_PIXEL>(width, height, 3);
+    // an invalid filter type, which leaves the line as it is.
+    if let Some(line) = original.chunks_exact_mut(line_len).nth(5) {
+      line[0] = 5;
+    }
+    //
+    let mut expected = vec![0_u8; (line_len - 1) * height];
+    if width > 0 {
+      png_filters::unfilter_lines_to::<BYTES_PER_PIXEL>(
+        original.chunks_exact(line_len),
+        expected.chunks_exact_mut(line_len - 1),
+      );
+    }
+    let mut dst: Box<[core::mem::MaybeUninit<u8>]> = Box::new_uninit_slice((line_len - 1) * height);
+    let actual = png_filters::unfilter_lines_to_uninit::<BYTES_PER_PIXEL>(
+      original.chunks_exact(line_len),
+      &mut dst,
+    );
+    assert_eq!(expected, actual, "bpp: {BYTES_PER_PIXEL}, {width}x{height}");
+  }
+  for (width, height) in [(21, 10), (1, 1), (0, 3), (4, 0)] {
+    for_each_bpp!(check, width, height);
+  }
+}
+
+#[test]
+fn test_recon_to_uninit_fallbacks() {
+  use png_filters::fallbacks::*;
+  fn check<const BYTES_PER_PIXEL: usize>() {
+    let len = 13 * BYTES_PER_PIXEL;
+    let mut rng = Rng::new(4);
+    let src = rng.bytes(len);
+    let previous = rng.bytes(len);
+    let b = &previous;
+    let mut expected = vec![0_u8; len];
+    let mut dst: Box<[core::mem::MaybeUninit<u8>]> = Box::new_uninit_slice(len);
+    let msg = format!("bpp: {BYTES_PER_PIXEL}");
+    //
+    recon_sub_to::<BYTES_PER_PIXEL>(&mut expected, &src);
+    assert_eq!(expected, recon_sub_to_uninit::<BYTES_PER_PIXEL>(&mut dst, &src), "sub, {msg}");
+    recon_up_to(&mut expected, &src, b);
+    assert_eq!(expected, recon_up_to_uninit(&mut dst, &src, b), "up, {msg}");
+    recon_average_to::<BYTES_PER_PIXEL>(&mut expected, &src, b);
+    assert_eq!(
+      expected,
+      recon_average_to_uninit::<BYTES_PER_PIXEL>(&mut dst, &src, b),
+      "average, {msg}"
+    );
+    recon_average_top_to::<BYTES_PER_PIXEL>(&mut expected, &src);
+    assert_eq!(
+      expected,
+      recon_average_top_to_uninit::<BYTES_PER_PIXEL>(&mut dst, &src),
+      "average_top, {msg}"
+    );
+    recon_paeth_to::<BYTES_PER_PIXEL>(&mut expected, &src, b);
+    assert_eq!(
+      expected,
+      recon_paeth_to_uninit::<BYTES_PER_PIXEL>(&mut dst, &src, b),
+      "paeth, {msg}"
+    );
+  }
+  for_each_bpp!(check);
+}
+
+#[test]
+#[should_panic]
+fn test_recon_to_uninit_short_src() {
+  // A short `src` would leave part of `dst` uninitialized.
+  let mut dst: Box<[core::mem::MaybeUninit<u8>]> = Box::new_uninit_slice(8);
+  let _ = png_filters::fallbacks::recon_up_to_uninit(&mut dst, &[0; 4], &[0; 4]);
+}
+
+#[test]
+#[should_panic]
+fn test_recon_to_short_previous_row() {
+  // Even in release, a short previous row is a panic and not a partial row.
+  let mut dst = [0; 8];
+  png_filters::fallbacks::recon_paeth_to::<4>(&mut dst, &[0; 8], &[0; 4]);
+}
+
+#[test]
+fn test_unfilter_lines_zero_height() {
+  // 5 pixels wide, 0 lines tall
+  fn check<const BYTES_PER_PIXEL: usize>() {
+    let line_len = 1 + 5 * BYTES_PER_PIXEL;
+    let mut actual: [u8; 0] = [];
+    assert_eq!(
+      png_filters::unfilter_lines::<BYTES_PER_PIXEL>(actual.chunks_exact_mut(line_len)),
+      0
+    );
+    png_filters::unfilter_lines_to::<BYTES_PER_PIXEL>(
+      actual.chunks_exact(line_len),
+      [].chunks_exact_mut(line_len - 1),
+    );
+    png_filters::unfilter_many::<BYTES_PER_PIXEL>(&mut [&mut [], &mut []], line_len);
+  }
+  for_each_bpp!(check);
+}
+
+#[test]
+fn test_unfilter_lines_zero_width() {
+  // 0 pixels wide, 5 lines tall
+  fn check<const BYTES_PER_PIXEL: usize>() {
+    let mut actual = [1, 2, 3, 4, 0];
+    assert_eq!(png_filters::unfilter_lines::<BYTES_PER_PIXEL>(actual.chunks_exact_mut(1)), 0);
+    assert_eq!([0; 5], actual, "bpp: {BYTES_PER_PIXEL}");
+    //
+    let mut a = [4, 3, 2];
+    let mut b = [0, 1];
+    png_filters::unfilter_many::<BYTES_PER_PIXEL>(&mut [&mut a, &mut b], 1);
+    assert_eq!(([0; 3], [0; 2]), (a, b), "bpp: {BYTES_PER_PIXEL}");
+    //
+    png_filters::unfilter_many::<BYTES_PER_PIXEL>(&mut [&mut [], &mut []], 0);
+  }
+  for_each_bpp!(check);
+}
+
+#[test]
+fn test_unfilter_lines_dynamic() {
+  let original = [4, 200, 1, 3, 7, 4, 5, 6, 7, 8, 1, 9, 10, 11, 12];
+  let mut expected = original;
+  let _ = png_filters::unfilter_lines::<2>(expected.chunks_exact_mut(5));
+  let mut actual = original;
+  assert_eq!(png_filters::unfilter_lines_dynamic(2, actual.chunks_exact_mut(5)), 12);
+  assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_unfilter_lines_reconstructed_len() {
+  fn check<const BYTES_PER_PIXEL: usize>(width: usize, height: usize) {
+    let line_len = 1 + width * BYTES_PER_PIXEL;
+    let mut image = random_filtered_image::<BYTES_PER_PIXEL>(width, height, 102);
+    let len = image.len();
+    let reconstructed =
+      png_filters::unfilter_lines::<BYTES_PER_PIXEL>(image.chunks_exact_mut(line_len));
+    assert_eq!(reconstructed, len - height, "bpp: {BYTES_PER_PIXEL}");
+    assert_eq!(reconstructed, height * width * BYTES_PER_PIXEL, "bpp: {BYTES_PER_PIXEL}");
+  }
+  check::<1>(13, 7);
+  check::<3>(13, 7);
+  check::<4>(1, 1);
+  check::<8>(20, 3);
+}
+
+#[test]
+fn test_unfilter_then_transpose() {
+  fn check<const BYTES_PER_PIXEL: usize>(width: usize, height: usize) {
+    let line_len = 1 + width * BYTES_PER_PIXEL;
+    let original = random_filtered_image::<BYTES_PER_PIXEL>(width, height, 103);
+    //
+    let mut expected_lines = original.clone();
+    let _ =
+      png_filters::unfilter_lines::<BYTES_PER_PIXEL>(expected_lines.chunks_exact_mut(line_len));
+    let mut expected = vec![0_u8; width * height * BYTES_PER_PIXEL];
+    for y in 0..height {
+      for x in 0..width {
+        let src = y * line_len + 1 + x * BYTES_PER_PIXEL;
+        let dst = (x * height + y) * BYTES_PER_PIXEL;
+        expected[dst..dst + BYTES_PER_PIXEL]
+          .copy_from_slice(&expected_lines[src..src + BYTES_PER_PIXEL]);
+      }
+    }
+    //
+    let mut actual_lines = original.clone();
+    let mut actual = vec![0_u8; width * height * BYTES_PER_PIXEL];
+    png_filters::unfilter_then_transpose::<BYTES_PER_PIXEL>(
+      actual_lines.chunks_exact_mut(line_len),
+      &mut actual,
+      width,
+      height,
+    );
+    let msg = format!("bpp: {BYTES_PER_PIXEL}, {width}x{height}");
+    assert_eq!(expected_lines, actual_lines, "{msg}");
+    assert_eq!(expected, actual, "{msg}");
+  }
+  // sizes that are, and aren't, a multiple of the tile size.
+  for (width, height) in [(1, 1), (16, 16), (17, 5), (5, 40), (33, 33), (0, 3), (3, 0)] {
+    for_each_bpp!(check, width, height);
+  }
+}
+
+#[test]
+fn test_unfilter_split() {
+  fn check<const BYTES_PER_PIXEL: usize>(width: usize, height: usize) {
+    let row_len = width * BYTES_PER_PIXEL;
+    let original = random_filtered_image::<BYTES_PER_PIXEL>(width, height, 5);
+    //
+    let mut expected = original.clone();
+    let expected_len =
+      png_filters::unfilter_lines::<BYTES_PER_PIXEL>(expected.chunks_exact_mut(1 + row_len));
+    //
+    let mut filters: Vec<u8> = original.chunks_exact(1 + row_len).map(|line| line[0]).collect();
+    let mut rows: Vec<u8> =
+      original.chunks_exact(1 + row_len).flat_map(|line| line[1..].iter().copied()).collect();
+    let actual_len =
+      png_filters::unfilter_split::<BYTES_PER_PIXEL>(&mut filters, rows.chunks_exact_mut(row_len));
+    let msg = format!("bpp: {BYTES_PER_PIXEL}, {width}x{height}");
+    assert_eq!(expected_len, actual_len, "{msg}");
+    assert!(filters.iter().all(|filter| *filter == 0), "{msg}");
+    let expected_rows: Vec<u8> =
+      expected.chunks_exact(1 + row_len).flat_map(|line| line[1..].iter().copied()).collect();
+    assert_eq!(expected_rows, rows, "{msg}");
+  }
+  for (width, height) in [(1, 1), (3, 7), (16, 4), (33, 10), (5, 0)] {
+    for_each_bpp!(check, width, height);
+  }
+}
+
+#[test]
+fn test_unfilter_lines_zero_previous_rows() {
+  fn check<const BYTES_PER_PIXEL: usize>(width: usize) {
+    let line_len = 1 + width * BYTES_PER_PIXEL;
+    let mut original = random_filtered_image::<BYTES_PER_PIXEL>(width, 12, 6);
+    // zero rows, each followed by an "average" or "paeth" row that can skip
+    // reading the previous row.
+    original.chunks_exact_mut(line_len).enumerate().for_each(|(y, line)| match y % 4 {
+      0 => line.fill(0),
+      1 => line[0] = 3,
+      2 => line.fill(0),
+      _ => line[0] = 4,
+    });
+    //
+    let mut expected = vec![0_u8; (line_len - 1) * 12];
+    png_filters::unfilter_lines_to::<BYTES_PER_PIXEL>(
+      original.chunks_exact(line_len),
+      expected.chunks_exact_mut(line_len - 1),
+    );
+    let mut actual = original.clone();
+    let _ = png_filters::unfilter_lines::<BYTES_PER_PIXEL>(actual.chunks_exact_mut(line_len));
+    let actual: Vec<u8> =
+      actual.chunks_exact(line_len).flat_map(|line| line[1..].iter().copied()).collect();
+    assert_eq!(expected, actual, "bpp: {BYTES_PER_PIXEL}, width: {width}");
+  }
+  for width in [1, 3, 16, 17, 70] {
+    for_each_bpp!(check, width);
+  }
+}
+
+#[test]
+fn test_recon_mode_wrapping_matches_default() {
+  use png_filters::fallbacks::*;
+  let mut rng = Rng::new(7);
+  let filtered = rng.bytes(48);
+  let previous = rng.bytes(48);
+  let check = |mode: fn(&mut [u8], &[u8]), default: fn(&mut [u8], &[u8]), name: &str| {
+    let mut actual = filtered.clone();
+    mode(&mut actual, &previous);
+    let mut expected = filtered.clone();
+    default(&mut expected, &previous);
+    assert_eq!(expected, actual, "{name}");
+  };
+  check(|x, _| recon_sub_mode::<3, true>(x), |x, _| recon_sub::<3>(x), "sub");
+  check(recon_up_mode::<true>, recon_up, "up");
+  check(recon_average_mode::<3, true>, recon_average::<3>, "average");
+  check(
+    |x, _| recon_average_top_mode::<3, true>(x),
+    |x, _| recon_average_top::<3>(x),
+    "average_top",
+  );
+  check(recon_paeth_mode::<3, true>, recon_paeth::<3>, "paeth");
+}
+
+#[test]
+fn test_recon_mode_saturating_clamps() {
+  use png_filters::fallbacks::*;
+  // 200 + 100 wraps to 44, but saturates to 255.
+  let mut wrapping = [200, 100];
+  recon_sub_mode::<1, true>(&mut wrapping);
+  assert_eq!(wrapping, [200, 44]);
+  let mut saturating = [200, 100];
+  recon_sub_mode::<1, false>(&mut saturating);
+  assert_eq!(saturating, [200, 255]);
+  //
+  let mut actual = [250, 5, 1];
+  recon_up_mode::<false>(&mut actual, &[10, 250, 3]);
+  assert_eq!(actual, [255, 255, 4]);
+  //
+  // average of a=255 and b=255 is 255
+  let mut actual = [255, 10];
+  recon_average_mode::<1, false>(&mut actual, &[0, 255]);
+  assert_eq!(actual, [255, 255]);
+  //
+  // a / 2 = 127, so 200 + 127 clamps
+  let mut actual = [255, 200];
+  recon_average_top_mode::<1, false>(&mut actual);
+  assert_eq!(actual, [255, 255]);
+  //
+  // with c = 0 and a = b = 255 the predictor is 255
+  let mut actual = [255, 1];
+  recon_paeth_mode::<1, false>(&mut actual, &[0, 255]);
+  assert_eq!(actual, [255, 255]);
+  let mut actual = [255, 1];
+  recon_paeth_mode::<1, true>(&mut actual, &[0, 255]);
+  assert_eq!(actual, [255, 0]);
+}
+
+#[test]
+fn test_unfilter_frame() {
+  // a 3x2 RGB frame, then a 2x3 frame placed somewhere else on the canvas.
+  // Both start with a top line, so "up" on the first line does nothing and
+  // "paeth" is the same as "sub".
+  let mut first = [
+    4, 10, 20, 30, 1, 1, 1, 2, 2, 2, //
+    2, 1, 2, 3, 4, 5, 6, 7, 8, 9,
+  ];
+  let mut second = [
+    2, 50, 60, 70, 80, 90, 100, //
+    2, 1, 1, 1, 1, 1, 1, //
+    3, 2, 2, 2, 0, 0, 0,
+  ];
+  assert_eq!(png_filters::unfilter_frame::<3>(&mut first, 3, 2), 18);
+  assert_eq!(png_filters::unfilter_frame::<3>(&mut second, 2, 3), 18);
+  assert_eq!(
+    first,
+    [
+      0, 10, 20, 30, 11, 21, 31, 13, 23, 33, //
+      0, 11, 22, 33, 15, 26, 37, 20, 31, 42,
+    ]
+  );
+  assert_eq!(
+    second,
+    [
+      0, 50, 60, 70, 80, 90, 100, //
+      0, 51, 61, 71, 81, 91, 101, //
+      0, 27, 32, 37, 54, 61, 69,
+    ]
+  );
+}
+
+#[test]
+fn test_unfilter_lines_fixed_tile() {
+  // a 16x16 RGBA tile
+  let mut original = [0_u8; 65 * 16];
+  original.copy_from_slice(&Rng::new(8).bytes(65 * 16));
+  original.chunks_exact_mut(65).enumerate().for_each(|(y, line)| line[0] = ((y * 3) % 6) as u8);
+  //
+  let mut expected = original;
+  let _ = png_filters::unfilter_lines::<4>(expected.chunks_exact_mut(65));
+  //
+  let mut filters = [0_u8; 16];
+  let mut rows = [[0_u8; 64]; 16];
+  original.chunks_exact(65).zip(filters.iter_mut().zip(rows.iter_mut())).for_each(
+    |(line, (filter, row))| {
+      *filter = line[0];
+      row.copy_from_slice(&line[1..]);
+    },
+  );
+  png_filters::unfilter_lines_fixed::<4, 64, 16>(&mut filters, &mut rows);
+  assert_eq!(filters, [0; 16]);
+  expected.chunks_exact(65).zip(rows.iter()).enumerate().for_each(|(y, (line, row))| {
+    assert_eq!(&line[1..], &row[..], "y: {y}");
+  });
+  //
+  let mut actual = [1, 2, 3, 4];
+  png_filters::fallbacks::recon_up_fixed(&mut actual, &[10, 20, 30, 255]);
+  assert_eq!(actual, [11, 22, 33, 3]);
+}
+
+#[test]
+fn test_unfilter_and_compact() {
+  fn check<const BYTES_PER_PIXEL: usize>(width: usize, height: usize) {
+    let row_len = 1 + width * BYTES_PER_PIXEL;
+    let original = random_filtered_image::<BYTES_PER_PIXEL>(width, height, 104);
+    //
+    let mut expected = original.clone();
+    let _ = png_filters::unfilter_lines::<BYTES_PER_PIXEL>(expected.chunks_exact_mut(row_len));
+    let expected: Vec<u8> =
+      expected.chunks_exact(row_len).flat_map(|line| line[1..].iter().copied()).collect();
+    //
+    let mut actual = original;
+    let len = png_filters::unfilter_and_compact::<BYTES_PER_PIXEL>(&mut actual, row_len);
+    let msg = format!("bpp: {BYTES_PER_PIXEL}, {width}x{height}");
+    assert_eq!(len, width * height * BYTES_PER_PIXEL, "{msg}");
+    assert_eq!(expected, actual, "{msg}");
+  }
+  for (width, height) in [(1, 1), (3, 4), (17, 9), (0, 3), (4, 0)] {
+    for_each_bpp!(check, width, height);
+  }
+  let mut empty = Vec::new();
+  assert_eq!(png_filters::unfilter_and_compact::<1>(&mut empty, 0), 0);
+}
+
+#[test]
+fn test_unfilter_lines_with_hook() {
+  fn check<const BYTES_PER_PIXEL: usize>(width: usize, height: usize) {
+    let line_len = 1 + width * BYTES_PER_PIXEL;
+    // The top line is filter type 0, and there's an invalid filter type, both
+    // of which still go to the hook.
+    let mut original = random_filtered_image::<BYTES_PER_PIXEL>(width, height, 9);
+    if let Some(line) = original.chunks_exact_mut(line_len).nth(5) {
+      line[0] = 5;
+    }
+    //
+    let mut expected = original.clone();
+    let _ = png_filters::unfilter_lines::<BYTES_PER_PIXEL>(expected.chunks_exact_mut(line_len));
+    let expected_sum: u64 = expected
+      .chunks_exact(line_len)
+      .flat_map(|line| line[1..].iter())
+      .map(|byte| u64::from(*byte))
+      .sum();
+    //
+    let mut actual = original.clone();
+    let mut sum = 0_u64;
+    let mut next_y = 0;
+    let len = png_filters::unfilter_lines_with_hook::<BYTES_PER_PIXEL>(
+      actual.chunks_exact_mut(line_len),
+      &mut |y, row| {
+        assert_eq!(y, next_y);
+        assert_eq!(row, &expected[y * line_len + 1..(y + 1) * line_len]);
+        next_y += 1;
+        sum += row.iter().map(|byte| u64::from(*byte)).sum::<u64>();
+      },
+    );
+    let msg = format!("bpp: {BYTES_PER_PIXEL}, {width}x{height}");
+    assert_eq!(next_y, height, "{msg}");
+    assert_eq!(sum, expected_sum, "{msg}");
+    assert_eq!(len, width * height * BYTES_PER_PIXEL, "{msg}");
+    assert_eq!(expected, actual, "{msg}");
+  }
+  for (width, height) in [(1, 1), (5, 13), (33, 7), (0, 4), (3, 0)] {
+    for_each_bpp!(check, width, height);
+  }
+}
+
+#[test]
+fn test_unfilter_lines_to_sink() {
+  use png_filters::RowSink;
+  /// Keeps every row it's given.
+  struct Collect(Vec<(usize, Vec<u8>)>);
+  impl RowSink for Collect {
+    fn row(&mut self, index: usize, data: &[u8]) {
+      self.0.push((index, data.to_vec()));
+    }
+  }
+  /// Only adds up the bytes.
+  struct Sum(u64);
+  impl RowSink for Sum {
+    fn row(&mut self, _index: usize, data: &[u8]) {
+      self.0 += data.iter().map(|byte| u64::from(*byte)).sum::<u64>();
+    }
+  }
+  fn check<const BYTES_PER_PIXEL: usize>(width: usize, height: usize) {
+    let line_len = 1 + width * BYTES_PER_PIXEL;
+    let original = random_filtered_image::<BYTES_PER_PIXEL>(width, height, 105);
+    let mut expected = original.clone();
+    let _ = png_filters::unfilter_lines::<BYTES_PER_PIXEL>(expected.chunks_exact_mut(line_len));
+    let expected_rows: Vec<(usize, Vec<u8>)> =
+      expected.chunks_exact(line_len).map(|line| line[1..].to_vec()).enumerate().collect();
+    let msg = format!("bpp: {BYTES_PER_PIXEL}, {width}x{height}");
+    // The sink is picked at runtime.
+    let mut collect = Collect(Vec::new());
+    let mut sum = Sum(0);
+    for sink in [&mut collect as &mut dyn RowSink, &mut sum] {
+      let mut actual = original.clone();
+      let len = png_filters::unfilter_lines_to_sink::<BYTES_PER_PIXEL>(
+        actual.chunks_exact_mut(line_len),
+        sink,
+      );
+      assert_eq!(len, width * height * BYTES_PER_PIXEL, "{msg}");
+      assert_eq!(actual, expected, "{msg}");
+    }
+    assert_eq!(collect.0, expected_rows, "{msg}");
+    let expected_sum: u64 =
+      expected_rows.iter().flat_map(|(_, row)| row.iter()).map(|byte| u64::from(*byte)).sum();
+    assert_eq!(sum.0, expected_sum, "{msg}");
+  }
+  for (width, height) in [(1, 1), (5, 13), (33, 7), (0, 4), (3, 0)] {
+    for_each_bpp!(check, width, height);
+  }
+}
+
+#[test]
+fn test_bytes_per_pixel_panic_message() {
+  fn panic_message(f: impl FnOnce() + std::panic::UnwindSafe) -> String {
+    let payload = std::panic::catch_unwind(f).unwrap_err();
+    match payload.downcast::<String>() {
+      Ok(message) => *message,
+      Err(payload) => payload.downcast::<&str>().unwrap().to_string(),
+    }
+  }
+  // All the filter types are 0, so no `recon_*` function would ever be called.
+  let message = panic_message(|| {
+    let mut lines = [0_u8; 19 * 2];
+    let _ = png_filters::unfilter_lines_dynamic(9, lines.chunks_exact_mut(19));
+  });
+  assert!(message.contains('9'), "{message}");
+  let message = panic_message(|| {
+    let mut lines = [0_u8; 19 * 2];
+    let _ = png_filters::unfilter_lines::<9>(lines.chunks_exact_mut(19));
+  });
+  assert!(message.contains('9'), "{message}");
+  let message = panic_message(|| {
+    let mut lines = [0_u8; 19 * 2];
+    let _ = png_filters::options::UnfilterOptions::new().run::<9>(lines.chunks_exact_mut(19));
+  });
+  assert!(message.contains('9'), "{message}");
+}
+
+#[test]
+fn test_unfilter_flat() {
+  fn check<const BYTES_PER_PIXEL: usize>(width: usize, height: usize) {
+    let row_len = 1 + width * BYTES_PER_PIXEL;
+    let mut expected = random_filtered_image::<BYTES_PER_PIXEL>(width, height, 106);
+    let mut actual = expected.clone();
+    let expected_len =
+      png_filters::unfilter_lines::<BYTES_PER_PIXEL>(expected.chunks_exact_mut(row_len));
+    let actual_len = png_filters::unfilter_flat::<BYTES_PER_PIXEL>(&mut actual, row_len);
+    let msg = format!("bpp: {BYTES_PER_PIXEL}, {width}x{height}");
+    assert_eq!(actual_len, expected_len, "{msg}");
+    assert_eq!(actual, expected, "{msg}");
+  }
+  for (width, height) in [(1, 1), (5, 13), (33, 7), (0, 4), (3, 0)] {
+    for_each_bpp!(check, width, height);
+  }
+  assert_eq!(png_filters::unfilter_flat::<4>(&mut [], 0), 0);
+}
+
+#[test]
+#[should_panic]
+fn test_unfilter_flat_partial_line() {
+  let _ = png_filters::unfilter_flat::<1>(&mut [0; 7], 3);
+}
+
+#[test]
+fn test_try_unfilter_flat() {
+  use png_filters::options::UnfilterError;
+  // Two lines of 1 + 4 pixels, with the row length missing the filter byte.
+  let original = [1, 1, 2, 3, 4, 2, 5, 6, 7, 8];
+  let mut actual = original;
+  let error = UnfilterError::BufferLength { len: 10, line_len: 4 };
+  assert_eq!(png_filters::try_unfilter_flat::<1>(&mut actual, 4), Err(error));
+  assert_eq!(actual, original);
+  let error = UnfilterError::BufferLength { len: 10, line_len: 0 };
+  assert_eq!(png_filters::try_unfilter_flat::<1>(&mut actual, 0), Err(error));
+  assert_eq!(actual, original);
+  //
+  assert_eq!(png_filters::try_unfilter_flat::<1>(&mut actual, 5), Ok(8));
+  assert_eq!(actual, [0, 1, 3, 6, 10, 0, 6, 9, 13, 18]);
+  assert_eq!(png_filters::try_unfilter_flat::<1>(&mut [], 0), Ok(0));
+  let mut invalid = [7, 1, 2];
+  let error = UnfilterError::InvalidFilterType { y: 0, filter: 7 };
+  assert_eq!(png_filters::try_unfilter_flat::<1>(&mut invalid, 3), Err(error));
+}
+
+#[test]
+fn test_unfilter_row_range() {
+  fn check<const BYTES_PER_PIXEL: usize>() {
+    const WIDTH: usize = 37;
+    const HEIGHT: usize = 30;
+    let line_len = 1 + WIDTH * BYTES_PER_PIXEL;
+    let original = random_filtered_image::<BYTES_PER_PIXEL>(WIDTH, HEIGHT, 107);
+    let mut full = original.clone();
+    let _ = png_filters::unfilter_lines::<BYTES_PER_PIXEL>(full.chunks_exact_mut(line_len));
+    // Rows 10..20, seeded from row 9 of the full decode.
+    let previous = &full[9 * line_len + 1..10 * line_len];
+    let mut partial = original.clone();
+    let reconstructed = png_filters::unfilter_row_range::<BYTES_PER_PIXEL>(
+      previous,
+      partial[10 * line_len..].chunks_exact_mut(line_len),
+      10,
+    );
+    assert_eq!(reconstructed, 10 * WIDTH * BYTES_PER_PIXEL, "bpp: {BYTES_PER_PIXEL}");
+    assert_eq!(
+      &partial[10 * line_len..20 * line_len],
+      &full[10 * line_len..20 * line_len],
+      "bpp: {BYTES_PER_PIXEL}"
+    );
+    // The rows outside the range are untouched.
+    assert_eq!(&partial[..10 * line_len], &original[..10 * line_len], "bpp: {BYTES_PER_PIXEL}");
+    assert_eq!(&partial[20 * line_len..], &original[20 * line_len..], "bpp: {BYTES_PER_PIXEL}");
+  }
+  for_each_bpp!(check);
+  assert_eq!(png_filters::unfilter_row_range::<4>(&[], [].chunks_exact_mut(9), 0), 0);
+}
+
+#[test]
+fn test_unfilter_row_with_prev_ring_buffer() {
+  fn check<const BYTES_PER_PIXEL: usize>() {
+    const WIDTH: usize = 37;
+    const HEIGHT: usize = 30;
+    let line_len = 1 + WIDTH * BYTES_PER_PIXEL;
+    let original = random_filtered_image::<BYTES_PER_PIXEL>(WIDTH, HEIGHT, 108);
+    let mut full = original.clone();
+    let _ = png_filters::unfilter_lines::<BYTES_PER_PIXEL>(full.chunks_exact_mut(line_len));
+    // Each row goes into a 2 slot ring buffer, using the other slot as the
+    // previous row.
+    let mut ring = vec![0_u8; 2 * (line_len - 1)];
+    for (y, line) in original.chunks_exact(line_len).enumerate() {
+      let (first, second) = ring.split_at_mut(line_len - 1);
+      let (row, previous) = if y % 2 == 0 { (first, &*second) } else { (second, &*first) };
+      row.copy_from_slice(&line[1..]);
+      let previous = if y == 0 { &[][..] } else { previous };
+      png_filters::unfilter_row_with_prev::<BYTES_PER_PIXEL>(line[0], row, previous);
+      assert_eq!(
+        &*row,
+        &full[y * line_len + 1..(y + 1) * line_len],
+        "bpp: {BYTES_PER_PIXEL}, y: {y}"
+      );
+    }
+  }
+  for_each_bpp!(check);
+}
+
+#[test]
+#[should_panic]
+fn test_unfilter_row_with_prev_wrong_length() {
+  png_filters::unfilter_row_with_prev::<1>(2, &mut [1, 2, 3], &[1, 2]);
+}
+
+#[test]
+#[should_panic]
+fn test_unfilter_row_range_too_many_rows() {
+  let _ = png_filters::unfilter_row_range::<1>(&[0; 2], [0; 9].chunks_exact_mut(3), 4);
+}
+
+#[test]
+fn test_unfilter_lines_strided() {
+  const PADDING: usize = 8;
+  fn check<const BYTES_PER_PIXEL: usize>(width: usize, height: usize) {
+    let line_len = 1 + width * BYTES_PER_PIXEL;
+    let stride = line_len + PADDING;
+    let mut tight = random_filtered_image::<BYTES_PER_PIXEL>(width, height, 109);
+    // The same lines, each followed by padding that should never change.
+    let mut padded: Vec<u8> = tight
+      .chunks_exact(line_len)
+      .flat_map(|line| line.iter().copied().chain([0xAB; PADDING]))
+      .collect();
+    let _ = png_filters::unfilter_lines::<BYTES_PER_PIXEL>(tight.chunks_exact_mut(line_len));
+    let len = png_filters::unfilter_lines_strided::<BYTES_PER_PIXEL>(
+      &mut padded,
+      stride,
+      width * BYTES_PER_PIXEL,
+    );
+    let msg = format!("bpp: {BYTES_PER_PIXEL}, {width}x{height}");
+    assert_eq!(len, width * height * BYTES_PER_PIXEL, "{msg}");
+    padded.chunks_exact(stride).zip(tight.chunks_exact(line_len)).for_each(|(padded, tight)| {
+      assert_eq!(&padded[..line_len], tight, "{msg}");
+      assert_eq!(&padded[line_len..], &[0xAB; PADDING], "{msg}");
+    });
+  }
+  for (width, height) in [(1, 1), (5, 13), (33, 7), (0, 4), (3, 0)] {
+    for_each_bpp!(check, width, height);
+  }
+  assert_eq!(png_filters::unfilter_lines_strided::<4>(&mut [], 0, 0), 0);
+}
+
+#[test]
+fn test_cpu_features() {
+  let features = png_filters::cpu::features();
+  assert_eq!(features, png_filters::cpu::features());
+  // and the same from another thread, after the first detection.
+  assert_eq!(features, std::thread::spawn(png_filters::cpu::features).join().unwrap());
+  #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+  {
+    assert_eq!(features.sse2, is_x86_feature_detected!("sse2"));
+    assert_eq!(features.sse4_1, is_x86_feature_detected!("sse4.1"));
+    assert_eq!(features.avx2, is_x86_feature_detected!("avx2"));
+    assert_eq!(features.avx512bw, is_x86_feature_detected!("avx512bw"));
+    assert!(!features.neon);
+  }
+  #[cfg(target_arch = "aarch64")]
+  {
+    assert_eq!(features.neon, std::arch::is_aarch64_feature_detected!("neon"));
+    assert!(!features.sse2 && !features.sse4_1 && !features.avx2 && !features.avx512bw);
+  }
+}
+
+#[test]
+fn test_supported_bytes_per_pixel() {
+  for bytes_per_pixel in png_filters::SUPPORTED_BYTES_PER_PIXEL {
+    assert!(png_filters::is_supported_bpp(bytes_per_pixel));
+    let line_len = 1 + 3 * bytes_per_pixel;
+    let mut lines = vec![1_u8; line_len * 2];
+    let reconstructed =
+      png_filters::unfilter_lines_dynamic(bytes_per_pixel, lines.chunks_exact_mut(line_len));
+    assert_eq!(reconstructed, 2 * 3 * bytes_per_pixel);
+    let _ = png_filters::selected_backends(bytes_per_pixel);
+  }
+  assert!(!png_filters::is_supported_bpp(0));
+  assert!(!png_filters::is_supported_bpp(9));
+}
+
+#[test]
+fn test_filter_type_conversions() {
+  use png_filters::{FilterType, InvalidFilterType};
+  for (byte, filter) in (0_u8..).zip(FilterType::ALL) {
+    assert_eq!(FilterType::try_from(byte), Ok(filter));
+    assert_eq!(u8::from(filter), byte);
+    assert_eq!(filter as u8, byte);
+  }
+  for byte in 5..=u8::MAX {
+    assert_eq!(FilterType::try_from(byte), Err(InvalidFilterType(byte)));
+  }
+  assert_eq!(InvalidFilterType(7).to_string(), "7 isn't a filter type");
+}
+
+#[test]
+fn test_unfilter_lines_one_pixel_wide() {
+  use png_filters::{options::UnfilterOptions, Backend};
+  // A 1x100 RGBA8 column: "sub" (and "paeth" on the top line) has no pixel to
+  // the left, so it leaves the pixel as it is.
+  let line_len = 1 + 4;
+  let original = random_filtered_image::<4>(1, 100, 10);
+  let mut expected = vec![0_u8; 4 * 100];
+  png_filters::unfilter_lines_to::<4>(
+    original.chunks_exact(line_len),
+    expected.chunks_exact_mut(4),
+  );
+  let expected: Vec<u8> =
+    expected.chunks_exact(4).flat_map(|pixel| [0].iter().chain(pixel)).copied().collect();
+  //
+  let mut actual = original.clone();
+  assert_eq!(png_filters::unfilter_lines::<4>(actual.chunks_exact_mut(line_len)), 400);
+  assert_eq!(expected, actual);
+  // Every "sub" line is unchanged apart from the filter byte.
+  original.chunks_exact(line_len).zip(actual.chunks_exact(line_len)).for_each(|(before, after)| {
+    if before[0] == 1 {
+      assert_eq!(before[1..], after[1..]);
+    }
+  });
+  // And the same with every backend.
+  for backend in [
+    Backend::Fallback,
+    Backend::Sse2,
+    Backend::Sse4_1,
+    Backend::Neon,
+    Backend::Portable,
+    Backend::Avx2,
+    Backend::Avx512,
+  ] {
+    let mut actual = original.clone();
+    let outcome =
+      UnfilterOptions::new().backend(backend).run::<4>(actual.chunks_exact_mut(line_len));
+    if outcome.result.is_ok() {
+      assert_eq!(expected, actual, "{backend:?}");
+    }
+  }
+}
+
+#[test]
+fn test_unfilter_lines_custom() {
+  use png_filters::RowFilter;
+  enum Custom {
+    AddConstant(u8),
+    XorAbove,
+  }
+  impl RowFilter for Custom {
+    fn reconstruct(&self, filtered: &mut [u8], previous: &[u8]) {
+      match self {
+        Custom::AddConstant(k) => filtered.iter_mut().for_each(|x| *x = x.wrapping_add(*k)),
+        Custom::XorAbove => filtered.iter_mut().zip(previous).for_each(|(x, b)| *x ^= b),
+      }
+    }
+  }
+  let table = [Custom::AddConstant(10), Custom::XorAbove];
+  #[rustfmt::skip]
+  let mut actual = [
+    5, 1, 2, 3, 4,
+    2, 1, 1, 1, 1,
+    6, 1, 2, 3, 4,
+    9, 7, 7, 7, 7,
+    1, 1, 1, 1, 1,
+  ];
+  let len = png_filters::unfilter_lines_custom::<1, _>(actual.chunks_exact_mut(5), &table);
+  assert_eq!(len, 20);
+  #[rustfmt::skip]
+  let expected = [
+    0, 11, 12, 13, 14,
+    0, 12, 13, 14, 15,
+    0, 13, 15, 13, 11,
+    0, 7, 7, 7, 7,
+    0, 1, 2, 3, 4,
+  ];
+  assert_eq!(expected, actual);
+  // On the top line the previous row is all 0.
+  let mut actual = [6, 1, 2, 3, 4, 6, 1, 1, 1, 1];
+  let _ = png_filters::unfilter_lines_custom::<2, _>(actual.chunks_exact_mut(5), &table);
+  assert_eq!(actual, [0, 1, 2, 3, 4, 0, 0, 3, 2, 5]);
+  // Without any custom filter types it's the same as `unfilter_lines`.
+  let line_len = 1 + 33 * 4;
+  let original = random_filtered_image::<4>(33, 20, 11);
+  let mut expected = original.clone();
+  let _ = png_filters::unfilter_lines::<4>(expected.chunks_exact_mut(line_len));
+  let mut actual = original.clone();
+  let _ = png_filters::unfilter_lines_custom::<4, _>(actual.chunks_exact_mut(line_len), &table);
+  assert_eq!(expected, actual);
 }
@@ -58,6 +58,25 @@ fn test_recon_average_neon() {
       png_filters::neon::recon_average::<4>(&mut actual, &last_row);
       let expected = [7, 10, 66, 63, 136, 136, 43, 66];
       assert_eq!(expected, actual);
+      //
+      let last_row = [12, 17, 127, 128, 255, 250, 7, 54, 100];
+      let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8, 9];
+      png_filters::neon::recon_average::<3>(&mut actual, &last_row);
+      let expected = [7, 10, 66, 66, 137, 164, 43, 103, 141];
+      assert_eq!(expected, actual);
+      //
+      // the halving add must floor even when both inputs are 255.
+      let last_row = [u8::MAX; 4];
+      let mut actual = [0, 0, 0, 0];
+      png_filters::neon::recon_average::<1>(&mut actual, &last_row);
+      let expected = [127, 191, 223, 239];
+      assert_eq!(expected, actual);
+      //
+      let last_row = [u8::MAX; 6];
+      let mut actual = [0, 0, 0, 0, 0, 0];
+      png_filters::neon::recon_average::<3>(&mut actual, &last_row);
+      let expected = [127, 127, 127, 191, 191, 191];
+      assert_eq!(expected, actual);
     }
   }
 }
@@ -76,6 +95,11 @@ fn test_recon_average_top_neon() {
       let expected = [1, 2, 3, 0, 6, 6, 10, 11];
       assert_eq!(expected, actual);
       //
+      let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8, 9];
+      png_filters::neon::recon_average_top::<3>(&mut actual);
+      let expected = [1, 2, 3, 255, 6, 7, 134, 11, 12];
+      assert_eq!(expected, actual);
+      //
       let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8];
       png_filters::neon::recon_average_top::<4>(&mut actual);
       let expected = [1, 2, 3, 255, 5, 7, 8, 135];
@@ -107,3 +131,66 @@ fn test_recon_paeth_neon() {
     }
   }
 }
+
+#[test]
+fn test_recon_average_neon_width_1_every_pair() {
+  if !is_aarch64_feature_detected!("neon") {
+    return;
+  }
+  // Each row is two 1 byte pixels. The first has nothing to the left or
+  // above, so it reconstructs to `a`, and then the second one's average is of
+  // that `a` and the `b` above it. The second filtered byte is mixed up from
+  // `a` and `b` so that the final wrapping add sees every value too.
+  for a in 0..=u8::MAX {
+    for b in 0..=u8::MAX {
+      let x = a.wrapping_mul(31) ^ b;
+      let filtered = [a, x];
+      let previous = [0, b];
+      let average = ((u16::from(a) + u16::from(b)) / 2) as u8;
+      let mut expected = filtered;
+      png_filters::fallbacks::recon_average::<1>(&mut expected, &previous);
+      assert_eq!(expected, [a, x.wrapping_add(average)], "a: {a}, b: {b}");
+      let mut actual = filtered;
+      unsafe { png_filters::neon::recon_average::<1>(&mut actual, &previous) };
+      assert_eq!(expected, actual, "a: {a}, b: {b}");
+    }
+  }
+}
+
+#[test]
+fn test_recon_up_neon_lengths() {
+  if is_aarch64_feature_detected!("neon") {
+    // whole 16 byte blocks, a tail, and both.
+    for len in [0, 1, 7, 15, 16, 17, 31, 32, 33, 100, 1000] {
+      let filtered = crate::Rng::new(221).bytes(len);
+      let previous = crate::Rng::new(222).bytes(len);
+      //
+      let mut expected = filtered.clone();
+      png_filters::fallbacks::recon_up(&mut expected, &previous);
+      let mut actual = filtered.clone();
+      unsafe { png_filters::neon::recon_up(&mut actual, &previous) };
+      assert_eq!(expected, actual, "len: {len}");
+    }
+  }
+}
+
+#[test]
+fn test_recon_sub_prefix_neon() {
+  if is_aarch64_feature_detected!("neon") {
+    unsafe {
+      let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8];
+      png_filters::neon::recon_sub_prefix(&mut actual);
+      let expected = [1, 3, 6, 5, 10, 16, 23, 31];
+      assert_eq!(expected, actual);
+    }
+    // lengths that aren't a multiple of 16 (or of 8), so the carry goes
+    // between blocks and into the leftover bytes.
+    for len in [0, 1, 7, 8, 15, 16, 17, 31, 33, 100, 4097] {
+      let mut filtered = crate::Rng::new(223).bytes(len);
+      let mut expected = filtered.clone();
+      png_filters::fallbacks::recon_sub::<1>(&mut expected);
+      unsafe { png_filters::neon::recon_sub_prefix(&mut filtered) };
+      assert_eq!(expected, filtered, "len: {len}");
+    }
+  }
+}
@@ -0,0 +1,188 @@
+use png_filters::{
+  options::{UnfilterError, UnfilterOptions},
+  selected_backends, selected_backends_with, Backend, DispatchThresholds, FilterType,
+};
+
+use crate::{random_filtered_image, Rng};
+
+#[test]
+fn test_unfilter_options_combinations() {
+  fn check<const BYTES_PER_PIXEL: usize>() {
+    let line_len = 1 + 19 * BYTES_PER_PIXEL;
+    let original = random_filtered_image::<BYTES_PER_PIXEL>(19, 12, 1);
+    let seed = Rng::new(2).bytes(line_len - 1);
+    let counts = [3, 3, 2, 2, 2];
+    //
+    let backends = [
+      None,
+      Some(Backend::Fallback),
+      Some(Backend::Sse2),
+      Some(Backend::Sse4_1),
+      Some(Backend::Neon),
+      Some(Backend::Portable),
+      Some(Backend::Avx2),
+      Some(Backend::Avx512),
+    ];
+    for backend in backends {
+      for previous_row in [None, Some(&seed[..])] {
+        for (count_filters, simd_min_row_len) in [(false, 0), (true, 0), (true, usize::MAX)] {
+          // the expected output is the same as an image with the seed row on top.
+          let mut expected: Vec<u8> =
+            [0].iter().chain(seed.iter()).chain(original.iter()).copied().collect();
+          let _ =
+            png_filters::unfilter_lines::<BYTES_PER_PIXEL>(expected.chunks_exact_mut(line_len));
+          if previous_row.is_none() {
+            expected = original.clone();
+            let _ =
+              png_filters::unfilter_lines::<BYTES_PER_PIXEL>(expected.chunks_exact_mut(line_len));
+          } else {
+            expected.drain(..line_len);
+          }
+          //
+          let mut options =
+            UnfilterOptions::new().count_filters(count_filters).simd_min_row_len(simd_min_row_len);
+          if let Some(backend) = backend {
+            options = options.backend(backend);
+          }
+          if let Some(previous_row) = previous_row {
+            options = options.previous_row(previous_row);
+          }
+          let mut actual = original.clone();
+          let outcome = options.run::<BYTES_PER_PIXEL>(actual.chunks_exact_mut(line_len));
+          let msg = format!(
+            "{backend:?} {previous_row:?} {count_filters} {simd_min_row_len} bpp: {BYTES_PER_PIXEL}"
+          );
+          if let Err(UnfilterError::BackendUnavailable(unavailable)) = outcome.result {
+            assert_eq!(Some(unavailable), backend, "{msg}");
+            assert_eq!(outcome.filter_counts, None, "{msg}");
+            assert_eq!(original, actual, "{msg}");
+            continue;
+          }
+          assert_eq!(outcome.result, Ok(()), "{msg}");
+          assert_eq!(
+            outcome.filter_counts,
+            if count_filters { Some(counts) } else { None },
+            "{msg}"
+          );
+          assert_eq!(expected, actual, "{msg}");
+        }
+      }
+    }
+  }
+  for_each_bpp!(check);
+}
+
+#[test]
+fn test_unfilter_options_fallback_always_available() {
+  let mut actual = [1, 1, 2, 3, 4, 2, 5, 6, 7, 8];
+  let outcome =
+    UnfilterOptions::new().backend(Backend::Fallback).run::<1>(actual.chunks_exact_mut(5));
+  assert_eq!(outcome.result, Ok(()));
+  assert_eq!(actual, [0, 1, 3, 6, 10, 0, 6, 9, 13, 18]);
+}
+
+#[test]
+fn test_unfilter_options_thresholds() {
+  assert_eq!(DispatchThresholds::default(), DispatchThresholds::DEFAULT);
+  let never = DispatchThresholds { sub: usize::MAX, average: usize::MAX, paeth: usize::MAX };
+  for bytes_per_pixel in png_filters::SUPPORTED_BYTES_PER_PIXEL {
+    assert_eq!(
+      selected_backends_with(bytes_per_pixel, DispatchThresholds::DEFAULT),
+      selected_backends(bytes_per_pixel)
+    );
+    // "up" has no threshold, everything else goes back to the fallbacks.
+    let report = selected_backends_with(bytes_per_pixel, never);
+    let others = [report.sub, report.average, report.average_top, report.paeth];
+    assert_eq!(others, [Backend::Fallback; 4], "bpp: {bytes_per_pixel}");
+  }
+  // The output doesn't depend on the thresholds.
+  fn check<const BYTES_PER_PIXEL: usize>(thresholds: DispatchThresholds) {
+    let line_len = 1 + 37 * BYTES_PER_PIXEL;
+    let original = random_filtered_image::<BYTES_PER_PIXEL>(37, 10, 3);
+    let mut expected = original.clone();
+    let _ = png_filters::unfilter_lines::<BYTES_PER_PIXEL>(expected.chunks_exact_mut(line_len));
+    let mut actual = original;
+    let outcome = UnfilterOptions::new()
+      .thresholds(thresholds)
+      .run::<BYTES_PER_PIXEL>(actual.chunks_exact_mut(line_len));
+    assert_eq!(outcome.result, Ok(()));
+    assert_eq!(expected, actual, "bpp: {BYTES_PER_PIXEL}, {thresholds:?}");
+  }
+  let always = DispatchThresholds { sub: 1, average: 1, paeth: 1 };
+  for thresholds in [never, always] {
+    for_each_bpp!(check, thresholds);
+  }
+}
+
+#[test]
+fn test_unfilter_options_previous_row_length() {
+  let original = [2, 1, 2, 3, 4];
+  let mut actual = original;
+  let outcome =
+    UnfilterOptions::new().previous_row(&[1, 2, 3]).run::<1>(actual.chunks_exact_mut(5));
+  assert_eq!(outcome.result, Err(UnfilterError::PreviousRowLength { expected: 4, actual: 3 }));
+  assert_eq!(original, actual);
+}
+
+#[test]
+fn test_unfilter_options_invalid_filter_type() {
+  let mut actual = [1, 1, 2, 3, 4, 7, 5, 6, 7, 8, 2, 1, 1, 1, 1, 9, 0, 0, 0, 0];
+  let outcome = UnfilterOptions::new().count_filters(true).run::<1>(actual.chunks_exact_mut(5));
+  assert_eq!(outcome.result, Err(UnfilterError::InvalidFilterType { y: 1, filter: 7 }));
+  assert_eq!(outcome.filter_counts, Some([0, 1, 1, 0, 0]));
+  assert_eq!(outcome.filter_count(FilterType::Sub), Some(1));
+  assert_eq!(outcome.filter_count(FilterType::Paeth), Some(0));
+  assert_eq!(actual, [0, 1, 3, 6, 10, 0, 5, 6, 7, 8, 0, 6, 7, 8, 9, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn test_unfilter_options_row_shorter_than_pixel() {
+  // bpp 4 with only 2 bytes per row can't be a real image.
+  let original = [3, 200, 100, 1, 50, 60];
+  let mut actual = original;
+  let outcome = UnfilterOptions::new().run::<4>(actual.chunks_exact_mut(3));
+  let error = UnfilterError::RowShorterThanPixel { row_len: 2, bytes_per_pixel: 4 };
+  assert_eq!(outcome.result, Err(error));
+  assert_eq!(original, actual);
+  assert_eq!(png_filters::try_unfilter_lines::<4>(actual.chunks_exact_mut(3)), Err(error));
+  assert_eq!(original, actual);
+  // A width of 0 is still fine.
+  let mut actual = [1, 2, 3];
+  assert_eq!(png_filters::try_unfilter_lines::<4>(actual.chunks_exact_mut(1)), Ok(0));
+  assert_eq!(actual, [0, 0, 0]);
+}
+
+#[test]
+fn test_try_unfilter_lines() {
+  let mut actual = [1, 1, 2, 3, 4, 2, 5, 6, 7, 8];
+  assert_eq!(png_filters::try_unfilter_lines::<1>(actual.chunks_exact_mut(5)), Ok(8));
+  assert_eq!(actual, [0, 1, 3, 6, 10, 0, 6, 9, 13, 18]);
+  let mut actual = [1, 1, 2, 3, 4, 7, 5, 6, 7, 8];
+  assert_eq!(
+    png_filters::try_unfilter_lines::<1>(actual.chunks_exact_mut(5)),
+    Err(UnfilterError::InvalidFilterType { y: 1, filter: 7 })
+  );
+  assert_eq!(actual, [0, 1, 3, 6, 10, 0, 5, 6, 7, 8]);
+}
+
+#[test]
+fn test_try_unfilter_lines_inline() {
+  let mut actual = [1, 1, 2, 3, 4, 2, 5, 6, 7, 8, 7, 1, 1, 1, 1, 1, 2, 2, 2, 2];
+  assert_eq!(
+    png_filters::try_unfilter_lines_inline::<1>(actual.chunks_exact_mut(5)),
+    Err(UnfilterError::InvalidFilterType { y: 2, filter: 7 })
+  );
+  // The two lines before the bad one are done, the rest are untouched.
+  assert_eq!(actual, [0, 1, 3, 6, 10, 0, 6, 9, 13, 18, 7, 1, 1, 1, 1, 1, 2, 2, 2, 2]);
+  //
+  let mut actual = [1, 1, 2, 3, 4, 2, 5, 6, 7, 8];
+  assert_eq!(png_filters::try_unfilter_lines_inline::<1>(actual.chunks_exact_mut(5)), Ok(8));
+  assert_eq!(actual, [0, 1, 3, 6, 10, 0, 6, 9, 13, 18]);
+  //
+  let mut actual = [9, 1, 2, 3, 4];
+  assert_eq!(
+    png_filters::try_unfilter_lines_inline::<1>(actual.chunks_exact_mut(5)),
+    Err(UnfilterError::InvalidFilterType { y: 0, filter: 9 })
+  );
+  assert_eq!(actual, [9, 1, 2, 3, 4]);
+}
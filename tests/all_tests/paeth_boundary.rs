@@ -0,0 +1,163 @@
+//! "paeth" at the edges of the byte range, on every backend.
+//!
+//! `p = a + b - c` goes from -255 to 510, so the SIMD versions compute it (and
+//! `pa`, `pb`, `pc`) in 16-bit lanes and only pack back down to bytes at the
+//! end. The values where `p` is negative or above 255 are where a saturating
+//! pack or a reinterpret of the intermediate values would show up. Each case
+//! sweeps `a` (the reconstructed pixel to the left), `b` (the pixel above), and
+//! `c` (the pixel above and to the left) over `EDGES`, and checks that every
+//! backend gives exactly the fallback's bytes.
+
+use png_filters::{options::UnfilterOptions, Backend};
+
+const EDGES: [u8; 4] = [0, 1, 254, 255];
+
+/// The predictor picked by the PNG spec, written out separately from any of
+/// the crate's code.
+fn predictor(a: u8, b: u8, c: u8) -> u8 {
+  let p = i16::from(a) + i16::from(b) - i16::from(c);
+  let pa = (p - i16::from(a)).abs();
+  let pb = (p - i16::from(b)).abs();
+  let pc = (p - i16::from(c)).abs();
+  if pa <= pb && pa <= pc {
+    a
+  } else if pb <= pc {
+    b
+  } else {
+    c
+  }
+}
+
+/// The filtered line and the previous row for one case, `pixels` wide.
+///
+/// The first pixel is filtered so that it reconstructs to `a`, the second
+/// pixel is `x` and has `b` above it and `c` above and to the left, and any
+/// pixels after that are 0 (so that the rows are long enough to go through the
+/// SIMD loops).
+fn case(bytes_per_pixel: usize, pixels: usize, a: u8, b: u8, c: u8, x: u8) -> (Vec<u8>, Vec<u8>) {
+  let mut previous = vec![0_u8; pixels * bytes_per_pixel];
+  let mut line = vec![0_u8; 1 + pixels * bytes_per_pixel];
+  line[0] = 4;
+  previous[..bytes_per_pixel].fill(c);
+  previous[bytes_per_pixel..2 * bytes_per_pixel].fill(b);
+  // The first pixel has 0 to the left, so its predictor is `c`.
+  line[1..1 + bytes_per_pixel].fill(a.wrapping_sub(predictor(0, c, 0)));
+  line[1 + bytes_per_pixel..1 + 2 * bytes_per_pixel].fill(x);
+  (line, previous)
+}
+
+fn check<const BYTES_PER_PIXEL: usize>() {
+  for pixels in [2, 3, 17] {
+    for a in EDGES {
+      for b in EDGES {
+        for c in EDGES {
+          for x in [0, 1, 255] {
+            check_case::<BYTES_PER_PIXEL>(pixels, a, b, c, x);
+          }
+        }
+      }
+    }
+  }
+}
+
+/// Checks one case on every backend and every extra kernel.
+fn check_case<const BYTES_PER_PIXEL: usize>(pixels: usize, a: u8, b: u8, c: u8, x: u8) {
+  let backends = [
+    Backend::Fallback,
+    Backend::Sse2,
+    Backend::Sse4_1,
+    Backend::Neon,
+    Backend::Portable,
+    Backend::Avx2,
+    Backend::Avx512,
+  ];
+  let (filtered, previous) = case(BYTES_PER_PIXEL, pixels, a, b, c, x);
+  let msg = format!("bpp: {BYTES_PER_PIXEL}, pixels: {pixels}, a: {a}, b: {b}, c: {c}, x: {x}");
+  let mut expected = filtered[1..].to_vec();
+  png_filters::fallbacks::recon_paeth::<BYTES_PER_PIXEL>(&mut expected, &previous);
+  // Check the case itself against the formula.
+  assert_eq!(&expected[..BYTES_PER_PIXEL], &[a; BYTES_PER_PIXEL][..], "{msg}");
+  assert_eq!(expected[BYTES_PER_PIXEL], x.wrapping_add(predictor(a, b, c)), "{msg}");
+  //
+  for backend in backends {
+    let mut actual = filtered.clone();
+    let outcome = UnfilterOptions::new()
+      .backend(backend)
+      .previous_row(&previous)
+      .run::<BYTES_PER_PIXEL>(actual.chunks_exact_mut(filtered.len()));
+    if outcome.result.is_err() {
+      // not available on this CPU
+      continue;
+    }
+    assert_eq!(&expected[..], &actual[1..], "{msg}, {backend:?}");
+  }
+  for (name, kernel) in extra_kernels::<BYTES_PER_PIXEL>() {
+    let mut actual = filtered[1..].to_vec();
+    kernel(&mut actual, &previous);
+    assert_eq!(expected, actual, "{msg}, {name}");
+  }
+}
+
+/// A safe wrapper for a `recon_paeth` function.
+type Kernel = fn(&mut [u8], &[u8]);
+
+/// The "paeth" functions that no backend uses as a whole, and that are
+/// available on this CPU.
+#[allow(unused_mut)]
+fn extra_kernels<const BYTES_PER_PIXEL: usize>() -> Vec<(&'static str, Kernel)> {
+  let mut out: Vec<(&'static str, Kernel)> = vec![("fallbacks::recon_paeth_branchless", |x, b| {
+    png_filters::fallbacks::recon_paeth_branchless::<BYTES_PER_PIXEL>(x, b)
+  })];
+  if BYTES_PER_PIXEL == 4 {
+    out.push(("fallbacks::recon_paeth_rgba8", |x, b| {
+      png_filters::fallbacks::recon_paeth_rgba8(x, b)
+    }));
+  }
+  #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+  {
+    use png_filters::{sse2, sse4_1};
+    use std::arch::is_x86_feature_detected;
+    if BYTES_PER_PIXEL == 6 && is_x86_feature_detected!("sse2") {
+      out.push(("sse2::recon_paeth_6", |x, b| unsafe { sse2::recon_paeth_6(x, b) }));
+    }
+    if is_x86_feature_detected!("sse4.1") {
+      out.push(("sse4_1::recon_paeth_min", |x, b| unsafe {
+        sse4_1::recon_paeth_min::<BYTES_PER_PIXEL>(x, b)
+      }));
+    }
+  }
+  out
+}
+
+#[test]
+fn test_paeth_negative_p() {
+  // `p = 0 + 0 - 255 = -255`, so `pa = 255`, `pb = 255`, `pc = 510`. The tie
+  // between `pa` and `pb` goes to `a`.
+  assert_eq!(predictor(0, 0, 255), 0);
+  // `p = 0 + 1 - 255 = -254`: `pa = 254`, `pb = 255`, `pc = 509`, so `a` again.
+  assert_eq!(predictor(0, 1, 255), 0);
+  // `p = 1 + 0 - 255 = -254`: `pa = 255`, `pb = 254`, so `b`.
+  assert_eq!(predictor(1, 0, 255), 0);
+  fn check_all<const BYTES_PER_PIXEL: usize>() {
+    for pixels in [2, 17] {
+      for x in [0, 1, 255] {
+        check_case::<BYTES_PER_PIXEL>(pixels, 0, 0, 255, x);
+        check_case::<BYTES_PER_PIXEL>(pixels, 0, 1, 255, x);
+        check_case::<BYTES_PER_PIXEL>(pixels, 1, 0, 255, x);
+      }
+    }
+  }
+  check_all::<1>();
+  check_all::<2>();
+  check_all::<3>();
+  check_all::<4>();
+  check_all::<5>();
+  check_all::<6>();
+  check_all::<7>();
+  check_all::<8>();
+}
+
+#[test]
+fn test_paeth_boundary_every_backend() {
+  for_each_bpp!(check);
+}
@@ -0,0 +1,274 @@
+//! Randomized tests that every backend's `recon_*` matches the fallback.
+//!
+//! Each case is a random bytes per pixel, row length, filtered row, and
+//! previous row. When a kernel disagrees with the fallback the case is shrunk
+//! (fewer pixels, then simpler bytes) before panicking, and the panic message
+//! has the seed so that you can replay it with
+//! `PNG_FILTERS_PROPERTY_SEED=<seed> cargo test property`. Seeds that found a
+//! bug should be added to `REGRESSION_SEEDS`.
+
+use crate::Rng;
+use png_filters::fallbacks;
+
+/// Seeds that are always run, in addition to a fresh random seed.
+const REGRESSION_SEEDS: &[u64] = &[1, 0x5EED, 0xDEAD_BEEF_CAFE_F00D];
+
+/// How many cases each seed runs.
+const CASES_PER_SEED: usize = 300;
+
+/// The longest row, in pixels. Long enough to cross several 16 byte blocks.
+const MAX_PIXELS: usize = 70;
+
+/// A safe wrapper for a `recon_*` function, it ignores the previous row if the
+/// filter doesn't use one.
+type Kernel = fn(&mut [u8], &[u8]);
+
+/// Each kernel available on this CPU, along with the fallback that it should
+/// match.
+#[allow(unused_mut)]
+fn kernels<const BYTES_PER_PIXEL: usize>() -> Vec<(&'static str, Kernel, Kernel)> {
+  let sub: Kernel = |x, _| fallbacks::recon_sub::<BYTES_PER_PIXEL>(x);
+  let up: Kernel = |x, b| fallbacks::recon_up(x, b);
+  let average: Kernel = |x, b| fallbacks::recon_average::<BYTES_PER_PIXEL>(x, b);
+  let average_top: Kernel = |x, _| fallbacks::recon_average_top::<BYTES_PER_PIXEL>(x);
+  let paeth: Kernel = |x, b| fallbacks::recon_paeth::<BYTES_PER_PIXEL>(x, b);
+  let mut out: Vec<(&'static str, Kernel, Kernel)> = vec![(
+    "fallbacks::recon_paeth_branchless",
+    |x, b| fallbacks::recon_paeth_branchless::<BYTES_PER_PIXEL>(x, b),
+    paeth,
+  )];
+  if BYTES_PER_PIXEL == 4 {
+    out.push(("fallbacks::recon_paeth_rgba8", |x, b| fallbacks::recon_paeth_rgba8(x, b), paeth));
+    out.push((
+      "fallbacks::recon_average_swar_rgba8",
+      |x, b| fallbacks::recon_average_swar_rgba8(x, b),
+      average,
+    ));
+  }
+  #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+  {
+    use png_filters::{sse2, sse4_1};
+    use std::arch::is_x86_feature_detected;
+    if is_x86_feature_detected!("sse2") {
+      out.push(("sse2::recon_sub", |x, _| unsafe { sse2::recon_sub::<BYTES_PER_PIXEL>(x) }, sub));
+      out.push(("sse2::recon_up", |x, b| unsafe { sse2::recon_up(x, b) }, up));
+      out.push((
+        "sse2::recon_average",
+        |x, b| unsafe { sse2::recon_average::<BYTES_PER_PIXEL>(x, b) },
+        average,
+      ));
+      out.push((
+        "sse2::recon_average_top",
+        |x, _| unsafe { sse2::recon_average_top::<BYTES_PER_PIXEL>(x) },
+        average_top,
+      ));
+      out.push((
+        "sse2::recon_paeth",
+        |x, b| unsafe { sse2::recon_paeth::<BYTES_PER_PIXEL>(x, b) },
+        paeth,
+      ));
+      if BYTES_PER_PIXEL == 6 {
+        out.push(("sse2::recon_average_6", |x, b| unsafe { sse2::recon_average_6(x, b) }, average));
+        out.push(("sse2::recon_paeth_6", |x, b| unsafe { sse2::recon_paeth_6(x, b) }, paeth));
+      }
+      if matches!(BYTES_PER_PIXEL, 1 | 2 | 4) {
+        out.push((
+          "sse2::recon_sub_prefix",
+          |x, _| unsafe { sse2::recon_sub_prefix::<BYTES_PER_PIXEL>(x) },
+          sub,
+        ));
+        out.push((
+          "sse2::recon_sub_prefix_runs",
+          |x, _| unsafe { sse2::recon_sub_prefix_runs::<BYTES_PER_PIXEL>(x) },
+          sub,
+        ));
+      }
+    }
+    if is_x86_feature_detected!("sse4.1") {
+      out.push((
+        "sse4_1::recon_sub",
+        |x, _| unsafe { sse4_1::recon_sub::<BYTES_PER_PIXEL>(x) },
+        sub,
+      ));
+      out.push((
+        "sse4_1::recon_sub_odd",
+        |x, _| unsafe { sse4_1::recon_sub_odd::<BYTES_PER_PIXEL>(x) },
+        sub,
+      ));
+      out.push(("sse4_1::recon_up", |x, b| unsafe { sse4_1::recon_up(x, b) }, up));
+      out.push((
+        "sse4_1::recon_average",
+        |x, b| unsafe { sse4_1::recon_average::<BYTES_PER_PIXEL>(x, b) },
+        average,
+      ));
+      out.push((
+        "sse4_1::recon_average_top",
+        |x, _| unsafe { sse4_1::recon_average_top::<BYTES_PER_PIXEL>(x) },
+        average_top,
+      ));
+      out.push((
+        "sse4_1::recon_paeth",
+        |x, b| unsafe { sse4_1::recon_paeth::<BYTES_PER_PIXEL>(x, b) },
+        paeth,
+      ));
+      out.push((
+        "sse4_1::recon_paeth_min",
+        |x, b| unsafe { sse4_1::recon_paeth_min::<BYTES_PER_PIXEL>(x, b) },
+        paeth,
+      ));
+    }
+  }
+  #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+  if std::arch::is_x86_feature_detected!("avx2") {
+    use png_filters::avx2;
+    if BYTES_PER_PIXEL == 1 {
+      out.push(("avx2::recon_sub_prefix", |x, _| unsafe { avx2::recon_sub_prefix(x) }, sub));
+    }
+    if BYTES_PER_PIXEL == 8 {
+      out.push((
+        "avx2::recon_average_top_8",
+        |x, _| unsafe { avx2::recon_average_top_8(x) },
+        average_top,
+      ));
+    }
+  }
+  #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+  if std::arch::is_x86_feature_detected!("avx512bw") {
+    out.push(("avx512::recon_up", |x, b| unsafe { png_filters::avx512::recon_up(x, b) }, up));
+  }
+  #[cfg(target_arch = "aarch64")]
+  {
+    use png_filters::neon;
+    use std::arch::is_aarch64_feature_detected;
+    if is_aarch64_feature_detected!("neon") {
+      out.push(("neon::recon_sub", |x, _| unsafe { neon::recon_sub::<BYTES_PER_PIXEL>(x) }, sub));
+      if BYTES_PER_PIXEL == 1 {
+        out.push(("neon::recon_sub_prefix", |x, _| unsafe { neon::recon_sub_prefix(x) }, sub));
+      }
+      out.push(("neon::recon_up", |x, b| unsafe { neon::recon_up(x, b) }, up));
+      out.push((
+        "neon::recon_average",
+        |x, b| unsafe { neon::recon_average::<BYTES_PER_PIXEL>(x, b) },
+        average,
+      ));
+      out.push((
+        "neon::recon_average_top",
+        |x, _| unsafe { neon::recon_average_top::<BYTES_PER_PIXEL>(x) },
+        average_top,
+      ));
+      out.push((
+        "neon::recon_paeth",
+        |x, b| unsafe { neon::recon_paeth::<BYTES_PER_PIXEL>(x, b) },
+        paeth,
+      ));
+    }
+  }
+  #[cfg(feature = "portable_simd")]
+  {
+    use png_filters::portable;
+    out.push(("portable::recon_sub", |x, _| portable::recon_sub::<BYTES_PER_PIXEL>(x), sub));
+    out.push(("portable::recon_up", |x, b| portable::recon_up(x, b), up));
+    out.push((
+      "portable::recon_average",
+      |x, b| portable::recon_average::<BYTES_PER_PIXEL>(x, b),
+      average,
+    ));
+    out.push((
+      "portable::recon_average_top",
+      |x, _| portable::recon_average_top::<BYTES_PER_PIXEL>(x),
+      average_top,
+    ));
+    out.push((
+      "portable::recon_paeth",
+      |x, b| portable::recon_paeth::<BYTES_PER_PIXEL>(x, b),
+      paeth,
+    ));
+  }
+  // Keep the fallbacks "used" on targets without any SIMD backend.
+  let _ = (sub, up, average, average_top);
+  out
+}
+
+/// If `kernel` and `reference` give different output for this case.
+fn fails(kernel: Kernel, reference: Kernel, filtered: &[u8], previous: &[u8]) -> bool {
+  let mut actual = filtered.to_vec();
+  kernel(&mut actual, previous);
+  let mut expected = filtered.to_vec();
+  reference(&mut expected, previous);
+  actual != expected
+}
+
+/// Makes a failing case as small as possible while it still fails.
+fn shrink(
+  bytes_per_pixel: usize, kernel: Kernel, reference: Kernel, mut filtered: Vec<u8>,
+  mut previous: Vec<u8>,
+) -> (Vec<u8>, Vec<u8>) {
+  // Drop whole pixels from the end, then from the start.
+  while filtered.len() > bytes_per_pixel {
+    let len = filtered.len() - bytes_per_pixel;
+    if fails(kernel, reference, &filtered[..len], &previous[..len]) {
+      filtered.truncate(len);
+      previous.truncate(len);
+    } else if fails(kernel, reference, &filtered[bytes_per_pixel..], &previous[bytes_per_pixel..]) {
+      filtered.drain(..bytes_per_pixel);
+      previous.drain(..bytes_per_pixel);
+    } else {
+      break;
+    }
+  }
+  // Then zero out as many bytes as we can.
+  for i in 0..filtered.len() {
+    let old = core::mem::take(&mut filtered[i]);
+    if !fails(kernel, reference, &filtered, &previous) {
+      filtered[i] = old;
+    }
+    let old = core::mem::take(&mut previous[i]);
+    if !fails(kernel, reference, &filtered, &previous) {
+      previous[i] = old;
+    }
+  }
+  (filtered, previous)
+}
+
+fn check_seed<const BYTES_PER_PIXEL: usize>(seed: u64) {
+  let mut rng = Rng::new(seed ^ BYTES_PER_PIXEL as u64);
+  let kernels = kernels::<BYTES_PER_PIXEL>();
+  for _ in 0..CASES_PER_SEED {
+    let len = rng.below(MAX_PIXELS + 1) * BYTES_PER_PIXEL;
+    let filtered = rng.bytes(len);
+    let previous = rng.bytes(len);
+    for (name, kernel, reference) in kernels.iter().copied() {
+      if fails(kernel, reference, &filtered, &previous) {
+        let (filtered, previous) =
+          shrink(BYTES_PER_PIXEL, kernel, reference, filtered.clone(), previous.clone());
+        panic!(
+          "{name}::<{BYTES_PER_PIXEL}> doesn't match the fallback (seed {seed}), \
+           filtered: {filtered:?}, previous: {previous:?}"
+        );
+      }
+    }
+  }
+}
+
+#[test]
+fn test_property_kernels_match_fallbacks() {
+  let mut seeds = REGRESSION_SEEDS.to_vec();
+  match std::env::var("PNG_FILTERS_PROPERTY_SEED") {
+    Ok(seed) => seeds.push(seed.parse().expect("PNG_FILTERS_PROPERTY_SEED should be a u64")),
+    Err(_) => {
+      let mut seed = [0_u8; 8];
+      getrandom::getrandom(&mut seed).unwrap();
+      seeds.push(u64::from_le_bytes(seed));
+    }
+  }
+  for seed in seeds {
+    check_seed::<1>(seed);
+    check_seed::<2>(seed);
+    check_seed::<3>(seed);
+    check_seed::<4>(seed);
+    check_seed::<5>(seed);
+    check_seed::<6>(seed);
+    check_seed::<7>(seed);
+    check_seed::<8>(seed);
+  }
+}
@@ -0,0 +1,143 @@
+//! Small inputs that once found a bug (or came close to one), checked against
+//! every backend.
+//!
+//! Each case has the filtered image data and the expected output, written out
+//! by hand, so these don't depend on the fallbacks being right. When a new
+//! problem input turns up, shrink it (the property tests print a shrunk case)
+//! and add it to `REGRESSIONS`.
+
+use png_filters::{
+  options::{UnfilterError, UnfilterOptions, UnfilterOutcome},
+  Backend,
+};
+
+struct Regression {
+  /// What the case is checking.
+  name: &'static str,
+  bytes_per_pixel: usize,
+  /// Bytes per line, including the filter byte.
+  line_len: usize,
+  filtered: &'static [u8],
+  /// The unfiltered lines, with each filter byte set to 0.
+  expected: &'static [u8],
+  /// The first line with an invalid filter type, and that filter type.
+  invalid: Option<(usize, u8)>,
+}
+
+const REGRESSIONS: &[Regression] = &[
+  Regression {
+    // RGB8 at a width of 3 pixels is 9 bytes, so the SIMD backends only ever
+    // see a partial register.
+    name: "average, width 3, rgb8",
+    bytes_per_pixel: 3,
+    line_len: 10,
+    filtered: &[
+      3, 200, 100, 255, 1, 2, 3, 255, 255, 255, //
+      3, 10, 250, 0, 128, 128, 128, 7, 8, 9,
+    ],
+    expected: &[
+      0, 200, 100, 255, 101, 52, 130, 49, 25, 64, //
+      0, 110, 44, 127, 233, 176, 0, 148, 108, 41,
+    ],
+    invalid: None,
+  },
+  Regression {
+    // RGBA8 at a width of 8 pixels is two full registers, and the `a` pixel
+    // has to carry from the first register into the second.
+    name: "average, width 8, rgba8",
+    bytes_per_pixel: 4,
+    line_len: 33,
+    filtered: &[
+      3, 255, 0, 128, 1, 255, 0, 128, 1, 255, 0, 128, 1, 255, 0, 128, 1, 255, 0, 128, 1, 255, 0,
+      128, 1, 255, 0, 128, 1, 255, 0, 128, 1, //
+      3, 255, 127, 127, 255, 1, 127, 254, 128, 127, 0, 127, 0, 254, 1, 127, 255, 255, 128, 254,
+      127, 127, 254, 254, 128, 255, 255, 128, 255, 127, 254, 128, 0,
+    ],
+    expected: &[
+      0, 255, 0, 128, 1, 126, 0, 192, 1, 62, 0, 224, 1, 30, 0, 240, 1, 14, 0, 248, 1, 6, 0, 252, 1,
+      2, 0, 254, 1, 0, 0, 255, 1, //
+      0, 126, 127, 191, 255, 127, 190, 189, 0, 221, 95, 77, 0, 123, 48, 29, 255, 67, 152, 136, 255,
+      163, 74, 192, 0, 81, 36, 95, 255, 167, 16, 47, 128,
+    ],
+    invalid: None,
+  },
+  Regression {
+    // A line with an invalid filter type is left as-is, and it's still the
+    // previous row for the line after it.
+    name: "invalid filter type",
+    bytes_per_pixel: 1,
+    line_len: 5,
+    filtered: &[
+      1, 1, 2, 3, 4, //
+      5, 9, 9, 9, 9, //
+      2, 1, 1, 1, 1, //
+      4, 1, 2, 3, 4,
+    ],
+    expected: &[
+      0, 1, 3, 6, 10, //
+      0, 9, 9, 9, 9, //
+      0, 10, 10, 10, 10, //
+      0, 11, 13, 16, 20,
+    ],
+    invalid: Some((1, 5)),
+  },
+];
+
+fn run(
+  bytes_per_pixel: usize, options: UnfilterOptions<'_>, lines: &mut [u8], line_len: usize,
+) -> UnfilterOutcome {
+  let lines = lines.chunks_exact_mut(line_len);
+  match bytes_per_pixel {
+    1 => options.run::<1>(lines),
+    2 => options.run::<2>(lines),
+    3 => options.run::<3>(lines),
+    4 => options.run::<4>(lines),
+    5 => options.run::<5>(lines),
+    6 => options.run::<6>(lines),
+    7 => options.run::<7>(lines),
+    8 => options.run::<8>(lines),
+    other => panic!("bad bytes_per_pixel: {other}"),
+  }
+}
+
+#[test]
+fn test_regressions_every_backend() {
+  let backends = [
+    None,
+    Some(Backend::Fallback),
+    Some(Backend::Sse2),
+    Some(Backend::Sse4_1),
+    Some(Backend::Neon),
+    Some(Backend::Portable),
+    Some(Backend::Avx2),
+    Some(Backend::Avx512),
+  ];
+  for case in REGRESSIONS {
+    assert_eq!(case.filtered.len(), case.expected.len(), "{}", case.name);
+    for backend in backends {
+      let mut options = UnfilterOptions::new();
+      if let Some(backend) = backend {
+        options = options.backend(backend);
+      }
+      let mut actual = case.filtered.to_vec();
+      let outcome = run(case.bytes_per_pixel, options, &mut actual, case.line_len);
+      let msg = format!("{} ({backend:?})", case.name);
+      match outcome.result {
+        Err(UnfilterError::BackendUnavailable(_)) => continue,
+        Err(UnfilterError::InvalidFilterType { y, filter }) => {
+          assert_eq!(case.invalid, Some((y, filter)), "{msg}");
+        }
+        result => assert_eq!(result, Ok(()), "{msg}"),
+      }
+      assert!(case.invalid.is_none() || outcome.result.is_err(), "{msg}");
+      assert_eq!(case.expected, &actual[..], "{msg}");
+    }
+    // and the plain entry point agrees too
+    let mut actual = case.filtered.to_vec();
+    let _ = png_filters::unfilter_lines_dynamic(
+      case.bytes_per_pixel,
+      actual.chunks_exact_mut(case.line_len),
+    );
+    assert_eq!(case.expected, &actual[..], "{}", case.name);
+  }
+}
@@ -0,0 +1,20 @@
+#![cfg(feature = "self-test")]
+
+use png_filters::{self_test::self_test, Backend};
+
+#[test]
+fn test_self_test_passes() {
+  assert_eq!(self_test(), Ok(()));
+}
+
+#[test]
+fn test_self_test_error_display() {
+  let error = png_filters::self_test::SelfTestError {
+    case: "paeth",
+    bytes_per_pixel: 4,
+    backend: Some(Backend::Sse2),
+  };
+  assert_eq!(error.to_string(), "paeth at 4 bytes per pixel is wrong with the Sse2 backend");
+  let error = png_filters::self_test::SelfTestError { backend: None, ..error };
+  assert_eq!(error.to_string(), "paeth at 4 bytes per pixel is wrong with the automatic selection");
+}
@@ -76,6 +76,11 @@ fn test_recon_average_top_sse2() {
       let expected = [1, 2, 3, 0, 6, 6, 10, 11];
       assert_eq!(expected, actual);
       //
+      let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8, 9];
+      png_filters::sse2::recon_average_top::<3>(&mut actual);
+      let expected = [1, 2, 3, 255, 6, 7, 134, 11, 12];
+      assert_eq!(expected, actual);
+      //
       let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8];
       png_filters::sse2::recon_average_top::<4>(&mut actual);
       let expected = [1, 2, 3, 255, 5, 7, 8, 135];
@@ -107,3 +112,401 @@ fn test_recon_paeth_sse2() {
     }
   }
 }
+
+#[test]
+#[cfg(not(feature = "no-simd"))]
+fn test_selected_backends_x86() {
+  use png_filters::{selected_backends, Backend};
+  if is_x86_feature_detected!("sse4.1") {
+    for bytes_per_pixel in 3..=8 {
+      assert_eq!(selected_backends(bytes_per_pixel).paeth, Backend::Sse4_1);
+    }
+  }
+  for bytes_per_pixel in 1..=2 {
+    assert_eq!(selected_backends(bytes_per_pixel).paeth, Backend::Fallback);
+  }
+  if is_x86_feature_detected!("sse2") {
+    for bytes_per_pixel in [2, 4] {
+      assert_eq!(selected_backends(bytes_per_pixel).sub, Backend::Sse2);
+    }
+  }
+  if is_x86_feature_detected!("avx2") {
+    assert_eq!(selected_backends(1).sub, Backend::Avx2);
+  } else if is_x86_feature_detected!("sse2") {
+    assert_eq!(selected_backends(1).sub, Backend::Sse2);
+  }
+  assert_eq!(selected_backends(1).average, Backend::Fallback);
+  if cfg!(not(feature = "portable_simd")) && is_x86_feature_detected!("avx2") {
+    assert_eq!(selected_backends(8).average_top, Backend::Avx2);
+  }
+  if is_x86_feature_detected!("avx512bw") {
+    assert_eq!(selected_backends(3).up, Backend::Avx512);
+  } else if is_x86_feature_detected!("sse2") {
+    assert_eq!(selected_backends(3).up, Backend::Sse2);
+  }
+}
+
+#[test]
+fn test_recon_sub_prefix_sse2() {
+  if is_x86_feature_detected!("sse2") {
+    unsafe {
+      let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8];
+      png_filters::sse2::recon_sub_prefix::<1>(&mut actual);
+      let expected = [1, 3, 6, 5, 10, 16, 23, 31];
+      assert_eq!(expected, actual);
+      //
+      let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8];
+      png_filters::sse2::recon_sub_prefix::<2>(&mut actual);
+      let expected = [1, 2, 4, 1, 9, 7, 16, 15];
+      assert_eq!(expected, actual);
+      //
+      let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8];
+      png_filters::sse2::recon_sub_prefix::<4>(&mut actual);
+      let expected = [1, 2, 3, u8::MAX, 6, 8, 10, 7];
+      assert_eq!(expected, actual);
+    }
+  }
+}
+
+#[test]
+fn test_recon_sub_prefix_sse2_long_rows() {
+  fn check<const BYTES_PER_PIXEL: usize>(len: usize) {
+    let filtered = crate::Rng::new(200).bytes(len);
+    //
+    let mut expected = filtered.clone();
+    png_filters::fallbacks::recon_sub::<BYTES_PER_PIXEL>(&mut expected);
+    let mut actual = filtered.clone();
+    unsafe { png_filters::sse2::recon_sub_prefix::<BYTES_PER_PIXEL>(&mut actual) };
+    assert_eq!(expected, actual, "bpp: {BYTES_PER_PIXEL}, len: {len}");
+  }
+  if is_x86_feature_detected!("sse2") {
+    // long enough to use whole 16 byte blocks, plus a tail that isn't.
+    for len in [4, 12, 16, 20, 32, 44, 1000] {
+      check::<1>(len);
+      check::<2>(len);
+      check::<4>(len);
+    }
+    check::<1>(17);
+    check::<2>(34);
+  }
+}
+
+#[test]
+fn test_recon_sub_prefix_runs_sse2() {
+  fn check<const BYTES_PER_PIXEL: usize>(filtered: &[u8], msg: &str) {
+    let mut expected = filtered.to_vec();
+    png_filters::fallbacks::recon_sub::<BYTES_PER_PIXEL>(&mut expected);
+    let mut actual = filtered.to_vec();
+    unsafe { png_filters::sse2::recon_sub_prefix_runs::<BYTES_PER_PIXEL>(&mut actual) };
+    assert_eq!(expected, actual, "bpp: {BYTES_PER_PIXEL}, {msg}");
+  }
+  if is_x86_feature_detected!("sse2") {
+    for len in [4, 16, 20, 32, 44, 1000] {
+      let filtered = crate::Rng::new(201).bytes(len);
+      // Zero runs that do and don't line up with the 16 byte blocks, including
+      // a zero block right at the start (where `a` is still 0).
+      let mut runs = filtered.clone();
+      runs[..len.min(16)].fill(0);
+      runs[len / 3..len / 2].fill(0);
+      runs[len - len.min(40)..].fill(0);
+      runs[len - 1] = 7;
+      let msg = format!("len: {len}");
+      for filtered in [&filtered, &runs, &vec![0; len]] {
+        check::<1>(filtered, &msg);
+        check::<2>(filtered, &msg);
+        check::<4>(filtered, &msg);
+      }
+    }
+  }
+}
+
+#[test]
+fn test_recon_paeth_sse4_1_matches_fallback() {
+  fn check<const BYTES_PER_PIXEL: usize>(pixels: usize) {
+    let len = pixels * BYTES_PER_PIXEL;
+    let filtered = crate::Rng::new(202).bytes(len);
+    let mut previous = crate::Rng::new(203).bytes(len);
+    // pull some bytes to the extremes, where the predictor ties are decided.
+    previous.iter_mut().step_by(3).for_each(|b| *b = if *b & 1 == 0 { 0 } else { u8::MAX });
+    //
+    let mut expected = filtered.clone();
+    png_filters::fallbacks::recon_paeth::<BYTES_PER_PIXEL>(&mut expected, &previous);
+    let mut actual = filtered.clone();
+    unsafe { png_filters::sse4_1::recon_paeth::<BYTES_PER_PIXEL>(&mut actual, &previous) };
+    assert_eq!(expected, actual, "bpp: {BYTES_PER_PIXEL}, pixels: {pixels}");
+  }
+  if is_x86_feature_detected!("sse4.1") {
+    for pixels in [0, 1, 2, 3, 33, 200] {
+      for_each_bpp!(check, pixels);
+    }
+  }
+}
+
+#[test]
+fn test_recon_up_sse2_lengths() {
+  if is_x86_feature_detected!("sse2") {
+    // whole 16 byte blocks, a tail, and both.
+    for len in [0, 1, 7, 15, 16, 17, 31, 32, 33, 100, 1000] {
+      let filtered = crate::Rng::new(204).bytes(len);
+      let previous = crate::Rng::new(205).bytes(len);
+      //
+      let mut expected = filtered.clone();
+      png_filters::fallbacks::recon_up(&mut expected, &previous);
+      let mut actual = filtered.clone();
+      unsafe { png_filters::sse2::recon_up(&mut actual, &previous) };
+      assert_eq!(expected, actual, "len: {len}");
+    }
+  }
+}
+
+#[test]
+fn test_recon_up_checksummed_sse2() {
+  if is_x86_feature_detected!("sse2") {
+    for len in [0, 1, 15, 16, 17, 33, 1000, 100_000] {
+      let filtered = crate::Rng::new(206).bytes(len);
+      let previous = crate::Rng::new(207).bytes(len);
+      //
+      let mut expected = filtered.clone();
+      png_filters::fallbacks::recon_up(&mut expected, &previous);
+      let expected_sum = expected.iter().map(|&x| u32::from(x)).sum::<u32>();
+      let mut actual = filtered.clone();
+      let actual_sum = unsafe { png_filters::sse2::recon_up_checksummed(&mut actual, &previous) };
+      assert_eq!(expected, actual, "len: {len}");
+      assert_eq!(expected_sum, actual_sum, "len: {len}");
+    }
+    // every byte at 255 gives the largest per-block sums.
+    let mut actual = vec![255_u8; 4099];
+    let sum = unsafe { png_filters::sse2::recon_up_checksummed(&mut actual, &[0; 4099]) };
+    assert_eq!(sum, 255 * 4099);
+  }
+}
+
+#[test]
+fn test_recon_average_top_sse4_1() {
+  if is_x86_feature_detected!("sse4.1") {
+    unsafe {
+      let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8];
+      png_filters::sse4_1::recon_average_top::<1>(&mut actual);
+      let expected = [1, 2, 4, 1, 5, 8, 11, 13];
+      assert_eq!(expected, actual);
+      //
+      let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8, 9];
+      png_filters::sse4_1::recon_average_top::<3>(&mut actual);
+      let expected = [1, 2, 3, 255, 6, 7, 134, 11, 12];
+      assert_eq!(expected, actual);
+    }
+  }
+}
+
+#[test]
+fn test_recon_6_sse2() {
+  fn check(pixels: usize) {
+    let len = pixels * 6;
+    let filtered = crate::Rng::new(208).bytes(len);
+    let mut previous = crate::Rng::new(209).bytes(len);
+    previous.iter_mut().step_by(5).for_each(|b| *b = if *b & 1 == 0 { 0 } else { u8::MAX });
+    //
+    let mut expected = filtered.clone();
+    png_filters::fallbacks::recon_average::<6>(&mut expected, &previous);
+    let mut actual = filtered.clone();
+    unsafe { png_filters::sse2::recon_average_6(&mut actual, &previous) };
+    assert_eq!(expected, actual, "average, pixels: {pixels}");
+    //
+    let mut expected = filtered.clone();
+    png_filters::fallbacks::recon_paeth::<6>(&mut expected, &previous);
+    let mut actual = filtered.clone();
+    unsafe { png_filters::sse2::recon_paeth_6(&mut actual, &previous) };
+    assert_eq!(expected, actual, "paeth, pixels: {pixels}");
+  }
+  if is_x86_feature_detected!("sse2") {
+    // odd and even pixel counts, and lengths that do and don't leave room for
+    // a full 16 byte store.
+    for pixels in [0, 1, 2, 3, 4, 5, 6, 7, 100, 101] {
+      check(pixels);
+    }
+    // every combination of edge values in a, b, and c.
+    let edges: [u8; 8] = [0, 1, 127, 128, 129, 254, 255, 64];
+    for a in edges {
+      for b in edges {
+        for c in edges {
+          // the first pixel reconstructs to `a` (see the fallback paeth test).
+          let filtered = [a.wrapping_sub(c), 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+          let previous = [c, 0, 0, 0, 0, 0, b, 0, 0, 0, 0, 0];
+          let mut expected = filtered;
+          png_filters::fallbacks::recon_paeth::<6>(&mut expected, &previous);
+          let mut actual = filtered;
+          unsafe { png_filters::sse2::recon_paeth_6(&mut actual, &previous) };
+          assert_eq!(expected, actual, "a: {a}, b: {b}, c: {c}");
+        }
+      }
+    }
+  }
+}
+
+#[test]
+fn test_recon_paeth_8_sse4_1() {
+  if is_x86_feature_detected!("sse4.1") {
+    let last_row = [12, 17, 127, 128, 255, 250, 7, 54, 0, 255, 200, 1, 128, 3, 9, 254];
+    let mut actual = [1, 2, 3, u8::MAX, 5, 6, 7, 8, 9, 10, 255, 0, 128, 127, 1, 2];
+    unsafe { png_filters::sse4_1::recon_paeth_8(&mut actual, &last_row) };
+    let expected = [13, 19, 130, 127, 4, 0, 14, 62, 9, 9, 199, 1, 132, 127, 15, 0];
+    assert_eq!(expected, actual);
+    //
+    for pixels in [0, 1, 2, 3, 33, 200] {
+      let mut filtered = crate::Rng::new(210).bytes(pixels * 8);
+      let mut previous = crate::Rng::new(211).bytes(pixels * 8);
+      previous.iter_mut().step_by(3).for_each(|b| *b = if *b & 1 == 0 { 0 } else { u8::MAX });
+      let mut expected = filtered.clone();
+      png_filters::fallbacks::recon_paeth::<8>(&mut expected, &previous);
+      unsafe { png_filters::sse4_1::recon_paeth_8(&mut filtered, &previous) };
+      assert_eq!(expected, filtered, "pixels: {pixels}");
+    }
+    // every combination of edge values in a, b, and c, in every channel.
+    let edges: [u8; 8] = [0, 1, 127, 128, 129, 254, 255, 64];
+    for a in edges {
+      for b in edges {
+        for c in edges {
+          let mut filtered = [0_u8; 16];
+          let mut previous = [0_u8; 16];
+          filtered[..8].fill(a.wrapping_sub(c));
+          previous[..8].fill(c);
+          previous[8..].fill(b);
+          let mut expected = filtered;
+          png_filters::fallbacks::recon_paeth::<8>(&mut expected, &previous);
+          unsafe { png_filters::sse4_1::recon_paeth_8(&mut filtered, &previous) };
+          assert_eq!(expected, filtered, "a: {a}, b: {b}, c: {c}");
+        }
+      }
+    }
+  }
+}
+
+#[test]
+fn test_recon_paeth_min_sse4_1_ties() {
+  if is_x86_feature_detected!("sse4.1") {
+    // Every combination of these gives lots of ties between `pa`, `pb`, and
+    // `pc` (any two of `a`, `b`, `c` equal, or `c` halfway between them), so
+    // the tie-break order gets checked. See the branchless fallback test for
+    // how the rows are set up.
+    let edges: [u8; 12] = [0, 1, 2, 3, 4, 64, 127, 128, 129, 253, 254, 255];
+    for a in edges {
+      for b in edges {
+        for c in edges {
+          let filtered = [a.wrapping_sub(c), 0];
+          let previous = [c, b];
+          let mut expected = filtered;
+          png_filters::fallbacks::recon_paeth::<1>(&mut expected, &previous);
+          let mut actual = filtered;
+          unsafe { png_filters::sse4_1::recon_paeth_min::<1>(&mut actual, &previous) };
+          assert_eq!(expected, actual, "a: {a}, b: {b}, c: {c}");
+          let mut actual = filtered;
+          unsafe { png_filters::sse4_1::recon_paeth::<1>(&mut actual, &previous) };
+          assert_eq!(expected, actual, "a: {a}, b: {b}, c: {c}");
+        }
+      }
+    }
+  }
+}
+
+#[test]
+fn test_recon_sub_odd_sse4_1() {
+  fn check<const BYTES_PER_PIXEL: usize>(pixels: usize) {
+    let filtered = crate::Rng::new(212).bytes(pixels * BYTES_PER_PIXEL);
+    let mut expected = filtered.clone();
+    png_filters::fallbacks::recon_sub::<BYTES_PER_PIXEL>(&mut expected);
+    let mut actual = filtered.clone();
+    unsafe { png_filters::sse4_1::recon_sub_odd::<BYTES_PER_PIXEL>(&mut actual) };
+    assert_eq!(expected, actual, "bpp: {BYTES_PER_PIXEL}, pixels: {pixels}");
+  }
+  if is_x86_feature_detected!("sse4.1") {
+    // shorter than one load, exactly one load, and lengths that leave
+    // different sized tails after the last full load.
+    for pixels in [0, 1, 2, 3, 4, 5, 6, 7, 11, 16, 33, 100, 101] {
+      for_each_bpp!(check, pixels);
+    }
+  }
+}
+
+#[test]
+fn test_recon_average_sse4_1_max_bytes() {
+  // With every byte at 255, `a + b` is 510, the most that the 16-bit lanes
+  // ever hold. A signed load or shift would get this wrong.
+  fn check<const BYTES_PER_PIXEL: usize>() {
+    let previous = [u8::MAX; 40];
+    let len = previous.len() / BYTES_PER_PIXEL * BYTES_PER_PIXEL;
+    let previous = &previous[..len];
+    // The first pixel is 128 + 255/2 = 255, and then every pixel after that is
+    // 0 + (255 + 255)/2 = 255.
+    let mut actual = vec![0_u8; len];
+    actual[..BYTES_PER_PIXEL].fill(128);
+    unsafe { png_filters::sse4_1::recon_average::<BYTES_PER_PIXEL>(&mut actual, previous) };
+    assert_eq!(actual, vec![u8::MAX; len], "bpp: {BYTES_PER_PIXEL}");
+    // All 255 on both rows, where the sums wrap.
+    let mut expected = vec![u8::MAX; len];
+    png_filters::fallbacks::recon_average::<BYTES_PER_PIXEL>(&mut expected, previous);
+    let mut actual = vec![u8::MAX; len];
+    unsafe { png_filters::sse4_1::recon_average::<BYTES_PER_PIXEL>(&mut actual, previous) };
+    assert_eq!(expected, actual, "bpp: {BYTES_PER_PIXEL}");
+  }
+  if is_x86_feature_detected!("sse4.1") {
+    for_each_bpp!(check);
+  }
+}
+
+#[test]
+fn test_recon_sub_prefix_avx2_matches_fallback() {
+  if !is_x86_feature_detected!("avx2") {
+    return;
+  }
+  // every tail length after 0 to 3 full blocks, and some long rows.
+  let lens = (0..=100).chain([4096, 4096 + 31, 10_000]);
+  for len in lens {
+    let filtered = crate::Rng::new(215).bytes(len);
+    for bytes in [filtered.clone(), vec![u8::MAX; len]] {
+      let mut expected = bytes.clone();
+      png_filters::fallbacks::recon_sub::<1>(&mut expected);
+      let mut actual = bytes;
+      unsafe { png_filters::avx2::recon_sub_prefix(&mut actual) };
+      assert_eq!(expected, actual, "len: {len}");
+    }
+  }
+}
+
+#[test]
+fn test_recon_average_top_8_avx2_matches_fallback() {
+  if !is_x86_feature_detected!("avx2") {
+    return;
+  }
+  // every pixel count after 0 to 3 full blocks, and a long row.
+  for pixels in (0..=16).chain([512, 515]) {
+    let filtered = crate::Rng::new(216).bytes(pixels * 8);
+    for bytes in [filtered.clone(), vec![u8::MAX; pixels * 8]] {
+      let mut expected = bytes.clone();
+      png_filters::fallbacks::recon_average_top::<8>(&mut expected);
+      let mut actual = bytes;
+      unsafe { png_filters::avx2::recon_average_top_8(&mut actual) };
+      assert_eq!(expected, actual, "pixels: {pixels}");
+    }
+  }
+}
+
+#[test]
+fn test_recon_up_avx512_tails() {
+  if !is_x86_feature_detected!("avx512bw") {
+    return;
+  }
+  // one byte short of a block, exactly one block, and tails of 1 byte after
+  // one block and of 1 and 63 bytes after two.
+  for len in [0, 1, 63, 64, 65, 127, 128, 129, 191, 1000] {
+    let mut filtered = crate::Rng::new(217).bytes(len);
+    let previous = crate::Rng::new(218).bytes(len);
+    let mut expected = filtered.clone();
+    png_filters::fallbacks::recon_up(&mut expected, &previous);
+    // A byte just past the row, which the masked store must leave alone.
+    filtered.push(0xAA);
+    let (actual, after) = filtered.split_at_mut(len);
+    unsafe { png_filters::avx512::recon_up(actual, &previous) };
+    assert_eq!(expected, actual, "len: {len}");
+    assert_eq!(after, [0xAA], "len: {len}");
+  }
+}
@@ -0,0 +1,54 @@
+#![cfg(feature = "timing")]
+
+use std::time::Duration;
+
+use png_filters::timing::{unfilter_lines_timed, FilterTimings};
+
+#[test]
+fn test_unfilter_lines_timed() {
+  const LINE_LEN: usize = 1 + 4 * 512;
+  // "average" on the top line, then each filter type once. None of the rows
+  // are all zero, so "average" and "paeth" really use the previous row.
+  let filters = [3, 1, 2, 3, 4, 0];
+  let mut image: Vec<u8> = filters
+    .iter()
+    .flat_map(|&filter| {
+      core::iter::once(filter)
+        .chain((1..LINE_LEN).map(move |i| (i * 7 + usize::from(filter)) as u8))
+    })
+    .collect();
+  let mut expected = image.clone();
+  let _ = png_filters::unfilter_lines::<4>(expected.chunks_exact_mut(LINE_LEN));
+  //
+  let timings = unfilter_lines_timed::<4>(image.chunks_exact_mut(LINE_LEN));
+  assert_eq!(expected, image);
+  let FilterTimings { sub, up, average, average_top, paeth } = timings;
+  for (name, time) in
+    [("sub", sub), ("up", up), ("average", average), ("average_top", average_top), ("paeth", paeth)]
+  {
+    assert!(time > Duration::ZERO, "{name}: {timings:?}");
+  }
+  assert!(timings.total() > Duration::ZERO);
+  assert_eq!(timings.total(), sub + up + average + average_top + paeth);
+  // With no lines there's nothing to time.
+  let mut empty: [u8; 0] = [];
+  assert_eq!(unfilter_lines_timed::<4>(empty.chunks_exact_mut(LINE_LEN)), FilterTimings::default());
+}
+
+#[test]
+fn test_unfilter_lines_timed_below_zero_row() {
+  const LINE_LEN: usize = 1 + 4 * 512;
+  // A "none" row that's all zero, then a "paeth" row, which is done (and
+  // timed) as "sub" since the row above is zero.
+  let mut image = vec![0_u8; 2 * LINE_LEN];
+  image[LINE_LEN] = 4;
+  image[LINE_LEN + 1..].iter_mut().enumerate().for_each(|(i, x)| *x = (i * 7) as u8);
+  let mut expected = image.clone();
+  let _ = png_filters::unfilter_lines::<4>(expected.chunks_exact_mut(LINE_LEN));
+  //
+  let timings = unfilter_lines_timed::<4>(image.chunks_exact_mut(LINE_LEN));
+  assert_eq!(expected, image);
+  assert!(timings.sub > Duration::ZERO, "{timings:?}");
+  assert_eq!(timings.paeth, Duration::ZERO, "{timings:?}");
+  assert_eq!(timings.total(), timings.sub);
+}
@@ -0,0 +1,20 @@
+#![cfg(feature = "trace")]
+
+use std::sync::Mutex;
+
+use png_filters::trace::{set_trace_hook, TraceEvent};
+
+static EVENTS: Mutex<Vec<TraceEvent>> = Mutex::new(Vec::new());
+
+#[test]
+fn test_trace_hook() {
+  set_trace_hook(Some(|event| EVENTS.lock().unwrap().push(*event)));
+  // other tests might run at the same time, so we look for our event in the
+  // log rather than expecting it to be the only one.
+  let mut image = [1, 1, 2, 3, 4, 5, 6, 0, 7, 8, 9, 10, 11, 12];
+  let _ = png_filters::unfilter_lines::<6>(image.chunks_exact_mut(7));
+  set_trace_hook(None);
+  let expected = TraceEvent { bytes_per_pixel: 6, report: png_filters::selected_backends(6) };
+  assert!(EVENTS.lock().unwrap().contains(&expected));
+  assert!(expected.to_string().starts_with("png_filters bpp=6 sub="));
+}
@@ -0,0 +1,98 @@
+//! The `_u16_native` functions must give exactly the byte-wise result.
+
+use png_filters::fallbacks::*;
+
+use crate::{random_filtered_image, Rng};
+
+fn to_samples(bytes: &[u8]) -> Vec<u16> {
+  bytes.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect()
+}
+
+#[test]
+fn test_add_doesnt_carry_between_bytes() {
+  let mut row = [0x00FF_u16, 0xFFFF];
+  recon_up_u16_native(&mut row, &[0x0001, 0x0101]);
+  assert_eq!(row, [0x0000, 0x0000]);
+  let mut row = [0x0001_u16, 0x0000];
+  recon_sub_u16_native::<1>(&mut row);
+  assert_eq!(row, [0x0001, 0x0001]);
+}
+
+#[test]
+fn test_u16_native_matches_bytes() {
+  fn check<const SAMPLES_PER_PIXEL: usize, const BYTES_PER_PIXEL: usize>() {
+    assert_eq!(SAMPLES_PER_PIXEL * 2, BYTES_PER_PIXEL);
+    for width in [0, 1, 2, 7, 64] {
+      let len = width * BYTES_PER_PIXEL;
+      let mut rng = Rng::new(width as u64);
+      let filtered = rng.bytes(len);
+      let previous = rng.bytes(len);
+      let filtered_samples = to_samples(&filtered);
+      let previous_samples = to_samples(&previous);
+      let msg = format!("spp: {SAMPLES_PER_PIXEL}, width: {width}");
+      //
+      let mut expected = filtered.clone();
+      recon_sub::<BYTES_PER_PIXEL>(&mut expected);
+      let mut actual = filtered_samples.clone();
+      recon_sub_u16_native::<SAMPLES_PER_PIXEL>(&mut actual);
+      assert_eq!(to_samples(&expected), actual, "sub, {msg}");
+      //
+      let mut expected = filtered.clone();
+      recon_up(&mut expected, &previous);
+      let mut actual = filtered_samples.clone();
+      recon_up_u16_native(&mut actual, &previous_samples);
+      assert_eq!(to_samples(&expected), actual, "up, {msg}");
+      //
+      let mut expected = filtered.clone();
+      recon_average::<BYTES_PER_PIXEL>(&mut expected, &previous);
+      let mut actual = filtered_samples.clone();
+      recon_average_u16_native::<SAMPLES_PER_PIXEL>(&mut actual, &previous_samples);
+      assert_eq!(to_samples(&expected), actual, "average, {msg}");
+      //
+      let mut expected = filtered.clone();
+      recon_average_top::<BYTES_PER_PIXEL>(&mut expected);
+      let mut actual = filtered_samples.clone();
+      recon_average_top_u16_native::<SAMPLES_PER_PIXEL>(&mut actual);
+      assert_eq!(to_samples(&expected), actual, "average top, {msg}");
+      //
+      let mut expected = filtered.clone();
+      recon_paeth::<BYTES_PER_PIXEL>(&mut expected, &previous);
+      let mut actual = filtered_samples.clone();
+      recon_paeth_u16_native::<SAMPLES_PER_PIXEL>(&mut actual, &previous_samples);
+      assert_eq!(to_samples(&expected), actual, "paeth, {msg}");
+    }
+  }
+  for _ in 0..20 {
+    check::<1, 2>();
+    check::<2, 4>();
+    check::<3, 6>();
+    check::<4, 8>();
+  }
+}
+
+#[test]
+fn test_u16_native_rgb16_image() {
+  // A whole RGB16 image, row by row, against unfilter_lines.
+  const WIDTH: usize = 33;
+  let line_len = 1 + WIDTH * 6;
+  let mut image = random_filtered_image::<6>(WIDTH, 10, 1);
+  let mut rows: Vec<Vec<u16>> =
+    image.chunks_exact(line_len).map(|line| to_samples(&line[1..])).collect();
+  let _ = png_filters::unfilter_lines::<6>(image.chunks_exact_mut(line_len));
+  //
+  let mut previous = vec![0_u16; WIDTH * 3];
+  for (y, row) in rows.iter_mut().enumerate() {
+    match y % 5 {
+      1 => recon_sub_u16_native::<3>(row),
+      2 => recon_up_u16_native(row, &previous),
+      3 if y == 0 => recon_average_top_u16_native::<3>(row),
+      3 => recon_average_u16_native::<3>(row, &previous),
+      4 => recon_paeth_u16_native::<3>(row, &previous),
+      _ => (),
+    }
+    previous.copy_from_slice(row);
+  }
+  image.chunks_exact(line_len).zip(rows).enumerate().for_each(|(y, (line, row))| {
+    assert_eq!(to_samples(&line[1..]), row, "y: {y}");
+  });
+}
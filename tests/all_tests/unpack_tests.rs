@@ -0,0 +1,58 @@
+use png_filters::unpack::{unpack_bits, unpack_bits_scaled, BitDepth};
+
+#[test]
+fn test_unpack_bits_1() {
+  let mut actual = vec![0b1010_0110, 0b1100_0000];
+  unpack_bits(&mut actual, BitDepth::One, 8);
+  let expected = [1, 0, 1, 0, 0, 1, 1, 0];
+  assert_eq!(expected, actual[..]);
+  //
+  let mut actual = vec![0b1010_0110, 0b1101_1111];
+  unpack_bits(&mut actual, BitDepth::One, 11);
+  let expected = [1, 0, 1, 0, 0, 1, 1, 0, 1, 1, 0];
+  assert_eq!(expected, actual[..]);
+}
+
+#[test]
+fn test_unpack_bits_2() {
+  let mut actual = vec![0b00_01_10_11, 0b11_10_01_00];
+  unpack_bits(&mut actual, BitDepth::Two, 8);
+  let expected = [0, 1, 2, 3, 3, 2, 1, 0];
+  assert_eq!(expected, actual[..]);
+  //
+  let mut actual = vec![0b00_01_10_11, 0b11_10_11_11];
+  unpack_bits(&mut actual, BitDepth::Two, 6);
+  let expected = [0, 1, 2, 3, 3, 2];
+  assert_eq!(expected, actual[..]);
+}
+
+#[test]
+fn test_unpack_bits_4() {
+  let mut actual = vec![0x01, 0x23, 0xEF];
+  unpack_bits(&mut actual, BitDepth::Four, 6);
+  let expected = [0x0, 0x1, 0x2, 0x3, 0xE, 0xF];
+  assert_eq!(expected, actual[..]);
+  //
+  let mut actual = vec![0x01, 0x23, 0xEF];
+  unpack_bits(&mut actual, BitDepth::Four, 5);
+  let expected = [0x0, 0x1, 0x2, 0x3, 0xE];
+  assert_eq!(expected, actual[..]);
+}
+
+#[test]
+fn test_unpack_bits_scaled() {
+  let mut actual = vec![0b1010_0000];
+  unpack_bits_scaled(&mut actual, BitDepth::One, 3);
+  let expected = [255, 0, 255];
+  assert_eq!(expected, actual[..]);
+  //
+  let mut actual = vec![0b00_01_10_11];
+  unpack_bits_scaled(&mut actual, BitDepth::Two, 4);
+  let expected = [0, 85, 170, 255];
+  assert_eq!(expected, actual[..]);
+  //
+  let mut actual = vec![0x0F, 0x80];
+  unpack_bits_scaled(&mut actual, BitDepth::Four, 3);
+  let expected = [0, 255, 136];
+  assert_eq!(expected, actual[..]);
+}